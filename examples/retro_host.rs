@@ -0,0 +1,337 @@
+// A bare libretro frontend for checking the core without RetroArch: it dlopens the built
+// core, loads a ROM through retro_load_game and runs it the way a frontend would.
+//
+//   cargo build && cargo run --example retro_host -- target/debug/libgb_emulator.so game.gb \
+//       [--frames <n>] [--screenshot <out.png>]
+//
+// Exits non-zero when the core misbehaves: wrong frame sizes, no audio, a state that doesn't
+// restore to the same frames, or memory maps that move or stop being the core's memory.
+
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr, CString};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use gb_emulator::libretro::{
+    GameInfo, SystemAvInfo, SystemInfo, RETRO_API_VERSION, RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+    RETRO_MEMORY_RTC, RETRO_MEMORY_SAVE_RAM, RETRO_MEMORY_SYSTEM_RAM, RETRO_MEMORY_VIDEO_RAM,
+    RETRO_PIXEL_FORMAT_XRGB8888,
+};
+use gb_emulator::png;
+
+#[link(name = "dl")]
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+const RTLD_NOW: c_int = 2;
+
+// what the core has handed over so far
+static FRAME: Mutex<(Vec<u32>, usize, usize)> = Mutex::new((Vec::new(), 0, 0));
+static VIDEO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static AUDIO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static PIXEL_FORMAT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            PIXEL_FORMAT.store(unsafe { *(data as *const c_uint) } as usize, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    VIDEO_FRAMES.fetch_add(1, Ordering::Relaxed);
+    if data.is_null() {
+        return; // a dupe of the last frame
+    }
+    let (width, height) = (width as usize, height as usize);
+    let mut frame = FRAME.lock().unwrap();
+    frame.0.clear();
+    for y in 0..height {
+        let row = unsafe { std::slice::from_raw_parts((data as *const u8).add(y * pitch) as *const u32, width) };
+        frame.0.extend_from_slice(row);
+    }
+    frame.1 = width;
+    frame.2 = height;
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {
+    AUDIO_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    AUDIO_FRAMES.fetch_add(frames, Ordering::Relaxed);
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, _id: c_uint) -> i16 {
+    0
+}
+
+// the entry points a frontend needs, looked up by name
+struct Core {
+    api_version: extern "C" fn() -> c_uint,
+    set_environment: extern "C" fn(unsafe extern "C" fn(c_uint, *mut c_void) -> bool),
+    set_video_refresh: extern "C" fn(unsafe extern "C" fn(*const c_void, c_uint, c_uint, usize)),
+    set_audio_sample: extern "C" fn(unsafe extern "C" fn(i16, i16)),
+    set_audio_sample_batch: extern "C" fn(unsafe extern "C" fn(*const i16, usize) -> usize),
+    set_input_poll: extern "C" fn(unsafe extern "C" fn()),
+    set_input_state: extern "C" fn(unsafe extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16),
+    init: extern "C" fn(),
+    deinit: extern "C" fn(),
+    get_system_info: unsafe extern "C" fn(*mut SystemInfo),
+    get_system_av_info: unsafe extern "C" fn(*mut SystemAvInfo),
+    load_game: unsafe extern "C" fn(*const GameInfo) -> bool,
+    unload_game: extern "C" fn(),
+    run: extern "C" fn(),
+    reset: extern "C" fn(),
+    serialize_size: extern "C" fn() -> usize,
+    serialize: extern "C" fn(*mut c_void, usize) -> bool,
+    unserialize: extern "C" fn(*const c_void, usize) -> bool,
+    get_memory_data: extern "C" fn(c_uint) -> *mut c_void,
+    get_memory_size: extern "C" fn(c_uint) -> usize,
+}
+
+fn open(path: &str) -> Result<Core, String> {
+    let name = CString::new(path).map_err(|e| e.to_string())?;
+    let handle = unsafe { dlopen(name.as_ptr(), RTLD_NOW) };
+    if handle.is_null() {
+        return Err(unsafe { CStr::from_ptr(dlerror()) }.to_string_lossy().into_owned());
+    }
+
+    macro_rules! symbol {
+        ($name:literal) => {
+            unsafe { symbol(handle, path, concat!($name, "\0"))? }
+        };
+    }
+
+    Ok(Core {
+        api_version: symbol!("retro_api_version"),
+        set_environment: symbol!("retro_set_environment"),
+        set_video_refresh: symbol!("retro_set_video_refresh"),
+        set_audio_sample: symbol!("retro_set_audio_sample"),
+        set_audio_sample_batch: symbol!("retro_set_audio_sample_batch"),
+        set_input_poll: symbol!("retro_set_input_poll"),
+        set_input_state: symbol!("retro_set_input_state"),
+        init: symbol!("retro_init"),
+        deinit: symbol!("retro_deinit"),
+        get_system_info: symbol!("retro_get_system_info"),
+        get_system_av_info: symbol!("retro_get_system_av_info"),
+        load_game: symbol!("retro_load_game"),
+        unload_game: symbol!("retro_unload_game"),
+        run: symbol!("retro_run"),
+        reset: symbol!("retro_reset"),
+        serialize_size: symbol!("retro_serialize_size"),
+        serialize: symbol!("retro_serialize"),
+        unserialize: symbol!("retro_unserialize"),
+        get_memory_data: symbol!("retro_get_memory_data"),
+        get_memory_size: symbol!("retro_get_memory_size"),
+    })
+}
+
+// looks a function up in the core. T has to be the function pointer type it was exported as
+unsafe fn symbol<T: Copy>(handle: *mut c_void, path: &str, name: &str) -> Result<T, String> {
+    let symbol = unsafe { dlsym(handle, name.as_ptr() as *const c_char) };
+    if symbol.is_null() {
+        return Err(format!("{path} has no {}", name.trim_end_matches('\0')));
+    }
+    Ok(unsafe { std::mem::transmute_copy::<*mut c_void, T>(&symbol) })
+}
+
+fn current_frame() -> Vec<u32> {
+    FRAME.lock().unwrap().0.clone()
+}
+
+const MEMORY_MAPS: [(c_uint, &str); 4] = [
+    (RETRO_MEMORY_SAVE_RAM, "save RAM"),
+    (RETRO_MEMORY_RTC, "RTC"),
+    (RETRO_MEMORY_SYSTEM_RAM, "system RAM"),
+    (RETRO_MEMORY_VIDEO_RAM, "video RAM"),
+];
+
+// every memory map as the frontend sees it: where it is and what it holds right now
+fn memory_maps(core: &Core) -> Result<Vec<(*mut u8, Vec<u8>)>, String> {
+    MEMORY_MAPS.iter().map(|&(id, name)| {
+        let data = (core.get_memory_data)(id) as *mut u8;
+        let size = (core.get_memory_size)(id);
+        if data.is_null() != (size == 0) {
+            return Err(format!("{name} is {size} bytes but its pointer is {data:?}"));
+        }
+        let contents = if data.is_null() { Vec::new() } else { unsafe { std::slice::from_raw_parts(data, size) }.to_vec() };
+        Ok((data, contents))
+    }).collect()
+}
+
+fn serialize(core: &Core, size: usize) -> Result<Vec<u8>, String> {
+    let mut state = vec![0u8; size];
+    if !(core.serialize)(state.as_mut_ptr() as *mut c_void, size) {
+        return Err("retro_serialize failed".into());
+    }
+    Ok(state)
+}
+
+fn host(args: &[String]) -> Result<(), String> {
+    let [core_path, rom_path, options @ ..] = args else {
+        return Err("usage: retro_host <core.so> <rom> [--frames <n>] [--screenshot <out.png>]".into());
+    };
+    let option = |name: &str| options.iter().position(|o| o == name).and_then(|i| options.get(i + 1));
+    let frames: usize = option("--frames").map_or(Ok(300), |n| n.parse()).map_err(|_| "--frames expects a number")?;
+
+    let core = open(core_path)?;
+    if (core.api_version)() != RETRO_API_VERSION {
+        return Err(format!("core reports API version {}", (core.api_version)()));
+    }
+
+    (core.set_environment)(environment);
+    (core.set_video_refresh)(video_refresh);
+    (core.set_audio_sample)(audio_sample);
+    (core.set_audio_sample_batch)(audio_sample_batch);
+    (core.set_input_poll)(input_poll);
+    (core.set_input_state)(input_state);
+    (core.init)();
+
+    let mut info: SystemInfo = unsafe { std::mem::zeroed() };
+    unsafe { (core.get_system_info)(&mut info) };
+    let name = unsafe { CStr::from_ptr(info.library_name) }.to_string_lossy();
+    let version = unsafe { CStr::from_ptr(info.library_version) }.to_string_lossy();
+    println!("core:         {name} {version}");
+
+    let rom = std::fs::read(rom_path).map_err(|e| format!("{rom_path}: {e}"))?;
+    let path = CString::new(rom_path.as_str()).unwrap();
+    let game = GameInfo { path: path.as_ptr(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: std::ptr::null() };
+    if !unsafe { (core.load_game)(&game) } {
+        return Err("retro_load_game failed".into());
+    }
+    if PIXEL_FORMAT.load(Ordering::Relaxed) != RETRO_PIXEL_FORMAT_XRGB8888 as usize {
+        return Err("core didn't ask for XRGB8888".into());
+    }
+
+    let mut av: SystemAvInfo = unsafe { std::mem::zeroed() };
+    unsafe { (core.get_system_av_info)(&mut av) };
+    println!("timing:       {:.4} fps, {} Hz", av.timing.fps, av.timing.sample_rate);
+    for (id, name) in MEMORY_MAPS {
+        println!("{:<13} {} bytes", format!("{name}:"), (core.get_memory_size)(id));
+    }
+
+    for _ in 0..frames {
+        (core.run)();
+    }
+
+    let (width, height) = {
+        let frame = FRAME.lock().unwrap();
+        (frame.1, frame.2)
+    };
+    let video_frames = VIDEO_FRAMES.load(Ordering::Relaxed);
+    let audio_frames = AUDIO_FRAMES.load(Ordering::Relaxed);
+    println!("video:        {video_frames} frames of {width}x{height}");
+    println!("audio:        {audio_frames} frames ({:.1} per video frame)", audio_frames as f64 / frames.max(1) as f64);
+
+    if video_frames != frames {
+        return Err(format!("expected one video frame per retro_run, got {video_frames} for {frames}"));
+    }
+    if width as u32 > av.geometry.max_width || height as u32 > av.geometry.max_height {
+        return Err(format!("{width}x{height} is larger than the reported geometry"));
+    }
+    if frames > 0 && audio_frames == 0 {
+        return Err("no audio".into());
+    }
+
+    if let Some(path) = option("--screenshot") {
+        png::write(path, width, height, &current_frame()).map_err(|e| format!("{path}: {e}"))?;
+    }
+
+    // a state restored and run forward has to give the same frames as the first time through
+    let size = (core.serialize_size)();
+    if size == 0 {
+        println!("save states:  not supported by this core");
+    } else {
+        let state = serialize(&core, size)?;
+        let maps = memory_maps(&core)?;
+        let mut expected = Vec::new();
+        for _ in 0..30 {
+            (core.run)();
+            expected.push(current_frame());
+        }
+        if !(core.unserialize)(state.as_ptr() as *const c_void, size) {
+            return Err("retro_unserialize failed".into());
+        }
+        for (i, frame) in expected.iter().enumerate() {
+            (core.run)();
+            if current_frame() != *frame {
+                return Err(format!("frame {i} after restoring a state differs"));
+            }
+        }
+        println!("save states:  {size} bytes, restored frames match");
+
+        // frontends keep the memory map pointers across loading a state, so they have to stay
+        // where they were and show the restored memory
+        (core.unserialize)(state.as_ptr() as *const c_void, size);
+        let restored = memory_maps(&core)?;
+        for ((_, name), ((data, contents), (before, expected))) in MEMORY_MAPS.iter().zip(restored.iter().zip(&maps)) {
+            if data != before {
+                return Err(format!("{name} moved when a state was loaded"));
+            }
+            // the RTC block ends in a wall clock timestamp, which moves on regardless
+            let compared = if *name == "RTC" { contents.len().min(40) } else { contents.len() };
+            if contents.len() != expected.len() || contents[..compared] != expected[..compared] {
+                return Err(format!("{name} doesn't show the loaded state"));
+            }
+        }
+
+        // and writes through them land in the core, so they show up in the next state
+        let (wram, _) = memory_maps(&core)?[2];
+        unsafe { *wram ^= 0xFF };
+        let changed = serialize(&core, size)?;
+        unsafe { *wram ^= 0xFF };
+        if changed == state || serialize(&core, size)? != state {
+            return Err("writes to system RAM don't reach the core".into());
+        }
+        println!("memory maps:  stay put across loading a state");
+    }
+
+    // frontends hold on to every memory map pointer, so a reset mustn't move any of them,
+    // and writes through them still have to reach the console that comes back up
+    let maps = memory_maps(&core)?;
+    (core.reset)();
+    (core.run)();
+    let reset = memory_maps(&core)?;
+    for ((_, name), ((data, _), (before, _))) in MEMORY_MAPS.iter().zip(reset.iter().zip(&maps)) {
+        if data != before {
+            return Err(format!("{name} moved on reset"));
+        }
+    }
+    if size > 0 {
+        for (index, &(id, name)) in MEMORY_MAPS.iter().enumerate() {
+            if !matches!(id, RETRO_MEMORY_SYSTEM_RAM | RETRO_MEMORY_VIDEO_RAM) {
+                continue;
+            }
+            let state = serialize(&core, size)?;
+            let (data, _) = reset[index];
+            unsafe { *data ^= 0xFF };
+            let changed = serialize(&core, size)?;
+            unsafe { *data ^= 0xFF };
+            if changed == state {
+                return Err(format!("writes to {name} don't reach the core after a reset"));
+            }
+        }
+    }
+    println!("reset:        memory maps stay put");
+    (core.unload_game)();
+    (core.deinit)();
+    println!("ok");
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = host(&args) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,708 @@
+use crate::audio;
+
+use serde::{Serialize, Deserialize};
+
+/* Sound registers, NR10 (0xFF10) through NR52 (0xFF26), then wave RAM at 0xFF30 */
+const NR10: u16 = 0xFF10;
+const NR11: u16 = 0xFF11;
+const NR12: u16 = 0xFF12;
+const NR13: u16 = 0xFF13;
+const NR14: u16 = 0xFF14;
+const NR21: u16 = 0xFF16;
+const NR22: u16 = 0xFF17;
+const NR23: u16 = 0xFF18;
+const NR24: u16 = 0xFF19;
+const NR30: u16 = 0xFF1A;
+const NR31: u16 = 0xFF1B;
+const NR32: u16 = 0xFF1C;
+const NR33: u16 = 0xFF1D;
+const NR34: u16 = 0xFF1E;
+const NR41: u16 = 0xFF20;
+const NR42: u16 = 0xFF21;
+const NR43: u16 = 0xFF22;
+const NR44: u16 = 0xFF23;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const WAVE_RAM: u16 = 0xFF30;
+const PCM12: u16 = 0xFF76;
+const PCM34: u16 = 0xFF77;
+
+// bits that always read back as 1, indexed from NR10
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// T-cycles of audio gathered before finished samples are handed to the output buffer
+const AUDIO_FRAME_CLOCKS: u32 = 8192;
+
+#[derive(Default, Serialize, Deserialize)]
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    max: u16, // 64, or 256 for the wave channel
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        LengthCounter { enabled: false, counter: 0, max }
+    }
+
+    fn load(&mut self, length: u16) {
+        self.counter = self.max - length;
+    }
+
+    // returns true when the counter runs out and the channel should be disabled
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
+
+// counts an envelope or sweep timer down, returns whether it ran out. it then reloads from
+// `period`, with 0 counting as 8; a timer that was never loaded runs out on its first clock
+fn count_down(timer: &mut u8, period: u8) -> bool {
+    if *timer > 1 {
+        *timer -= 1;
+        return false;
+    }
+    *timer = if period == 0 { 8 } else { period };
+    true
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    // the timer runs with a period of 0 too, but only a real period moves the volume
+    fn clock(&mut self) {
+        if !count_down(&mut self.timer, self.period) || self.period == 0 { return; }
+
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    negate_used: bool, // a calculation in negate mode happened since the last trigger
+}
+
+#[derive(Serialize, Deserialize)]
+struct SquareChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: i32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Sweep, // only wired up on channel 1
+}
+
+impl SquareChannel {
+    fn new() -> Self {
+        SquareChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 4
+    }
+
+    fn step(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.duty_step = (self.duty_step + 1) & 0x07;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled { return 0; }
+        DUTY_PATTERNS[self.duty as usize][self.duty_step as usize] * self.envelope.volume
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        // the low two bits of the frequency timer survive a trigger
+        self.timer = (self.timer & 0x03) | (self.period() & !0x03);
+        self.envelope.trigger();
+    }
+
+    // computes the next sweep frequency, disabling the channel on overflow
+    fn sweep_calculate(&mut self) -> u16 {
+        let delta = self.sweep.shadow >> self.sweep.shift;
+        let frequency = if self.sweep.negate {
+            self.sweep.negate_used = true;
+            self.sweep.shadow.wrapping_sub(delta)
+        } else {
+            self.sweep.shadow + delta
+        };
+
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn sweep_trigger(&mut self) {
+        self.sweep.shadow = self.frequency;
+        self.sweep.timer = if self.sweep.period == 0 { 8 } else { self.sweep.period };
+        self.sweep.enabled = self.sweep.period != 0 || self.sweep.shift != 0;
+        self.sweep.negate_used = false;
+        if self.sweep.shift != 0 {
+            self.sweep_calculate();
+        }
+    }
+
+    fn sweep_clock(&mut self) {
+        if !count_down(&mut self.sweep.timer, self.sweep.period) { return; }
+        if !self.sweep.enabled || self.sweep.period == 0 { return; }
+
+        let frequency = self.sweep_calculate();
+        if frequency <= 2047 && self.sweep.shift != 0 {
+            self.sweep.shadow = frequency;
+            self.frequency = frequency;
+            // the overflow check runs a second time with the new frequency
+            self.sweep_calculate();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: i32,
+    position: u8,
+    sample_buffer: u8,
+    just_read: bool, // the channel fetched from wave RAM on the most recent cycle
+    length: LengthCounter,
+    ram: [u8; 16],
+}
+
+impl WaveChannel {
+    fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            just_read: false,
+            length: LengthCounter::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 2
+    }
+
+    fn step(&mut self, cycles: i32) {
+        self.just_read = false;
+        if !self.enabled { return; }
+
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) & 0x1F;
+            self.sample_buffer = self.ram[(self.position / 2) as usize];
+            self.just_read = true;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled { return 0; }
+
+        let sample = if self.position & 1 == 0 {
+            self.sample_buffer >> 4
+        } else {
+            self.sample_buffer & 0x0F
+        };
+        match self.volume_code {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+
+    fn trigger(&mut self, cgb: bool) {
+        // retriggering on DMG right as the channel reads a sample corrupts the first bytes of wave RAM
+        if !cgb && self.enabled && self.timer <= 2 {
+            let index = (((self.position + 1) & 0x1F) / 2) as usize;
+            if index < 4 {
+                self.ram[0] = self.ram[index];
+            } else {
+                let block = index & !0x03;
+                self.ram.copy_within(block..block + 4, 0);
+            }
+        }
+
+        self.enabled = self.dac_enabled;
+        self.position = 0;
+        // the first sample is delayed by a few cycles after a trigger
+        self.timer = self.period() + 6;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NoiseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    clock_shift: u8,
+    width_mode: bool, // 7-bit LFSR instead of 15-bit
+    divisor_code: u8,
+    timer: i32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift) as i32
+    }
+
+    fn step(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period();
+
+            // shifts 14 and 15 never clock the LFSR
+            if self.clock_shift >= 14 { continue; }
+
+            let xor = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.width_mode {
+                self.lfsr = (self.lfsr & !0x40) | (xor << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled { return 0; }
+        (!self.lfsr & 0x01) as u8 * self.envelope.volume
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.envelope.trigger();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Apu {
+    pub cgb: bool, // CGB hardware, which changes a few power and wave RAM quirks
+    #[serde(skip, default = "default_output")]
+    pub output: audio::AudioBuffer,
+    clock: u32, // T-cycles into the current audio frame
+    last_mix: (f32, f32),
+    powered: bool,
+    registers: [u8; 0x20], // last values written to NR10-NR52, for read back
+    frame_step: u8,        // next frame sequencer step to run
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+}
+
+fn default_output() -> audio::AudioBuffer {
+    audio::AudioBuffer::new(audio::DEFAULT_SAMPLE_RATE)
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            cgb: false,
+            output: audio::AudioBuffer::new(audio::DEFAULT_SAMPLE_RATE),
+            clock: 0,
+            last_mix: (0.0, 0.0),
+            powered: false,
+            registers: [0; 0x20],
+            frame_step: 0,
+            square1: SquareChannel::new(),
+            square2: SquareChannel::new(),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+        }
+    }
+
+    // advances the channel timers; like the PPU this always runs at normal speed.
+    // the mixed output is sampled every M-cycle and only changes are passed on
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            if self.powered {
+                self.square1.step(4);
+                self.square2.step(4);
+                self.wave.step(4);
+                self.noise.step(4);
+            }
+
+            let (left, right) = self.mix();
+            if (left, right) != self.last_mix {
+                self.output.add_delta(self.clock, left - self.last_mix.0, right - self.last_mix.1);
+                self.last_mix = (left, right);
+            }
+            self.clock += 4;
+        }
+
+        if self.clock >= AUDIO_FRAME_CLOCKS {
+            self.output.end_frame(self.clock);
+            self.clock = 0;
+        }
+    }
+
+    // clocked at 512 Hz by falling edges of DIV bit 4 (bit 5 in double speed)
+    pub fn frame_sequencer_step(&mut self) {
+        if !self.powered { return; }
+
+        match self.frame_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.square1.sweep_clock();
+            }
+            7 => {
+                self.square1.envelope.clock();
+                self.square2.envelope.clock();
+                self.noise.envelope.clock();
+            }
+            _ => {}
+        }
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    fn clock_lengths(&mut self) {
+        if self.square1.length.clock() { self.square1.enabled = false; }
+        if self.square2.length.clock() { self.square2.enabled = false; }
+        if self.wave.length.clock() { self.wave.enabled = false; }
+        if self.noise.length.clock() { self.noise.enabled = false; }
+    }
+
+    // digital outputs of the four channels, 0-15 each
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [self.square1.output(), self.square2.output(), self.wave.output(), self.noise.output()]
+    }
+
+    // runs the channels through their DACs and the NR50/NR51 mixer, returns (left, right) in -1.0..=1.0
+    pub fn mix(&self) -> (f32, f32) {
+        if !self.powered { return (0.0, 0.0); }
+
+        let outputs = self.channel_outputs();
+        let dacs = [self.square1.dac_enabled, self.square2.dac_enabled, self.wave.dac_enabled, self.noise.dac_enabled];
+        let panning = self.registers[(NR51 - NR10) as usize];
+        let volume = self.registers[(NR50 - NR10) as usize];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for channel in 0..4 {
+            if !dacs[channel] { continue; }
+            let analog = outputs[channel] as f32 / 7.5 - 1.0;
+            if panning & (0x10 << channel) != 0 { left += analog; }
+            if panning & (0x01 << channel) != 0 { right += analog; }
+        }
+
+        let left_volume = ((volume >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (volume & 0x07) as f32 + 1.0;
+        (left / 4.0 * left_volume / 8.0, right / 4.0 * right_volume / 8.0)
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            NR52 => {
+                let status = (self.square1.enabled as u8)
+                    | (self.square2.enabled as u8) << 1
+                    | (self.wave.enabled as u8) << 2
+                    | (self.noise.enabled as u8) << 3;
+                0x70 | (self.powered as u8) << 7 | status
+            }
+            NR10..=0xFF2F => {
+                let index = (addr - NR10) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            WAVE_RAM..=0xFF3F => {
+                if self.wave.enabled {
+                    // while playing, only the byte being read is visible (and on DMG only at that moment)
+                    if self.cgb || self.wave.just_read {
+                        self.wave.ram[(self.wave.position / 2) as usize]
+                    } else {
+                        0xFF
+                    }
+                } else {
+                    self.wave.ram[(addr - WAVE_RAM) as usize]
+                }
+            }
+            PCM12 if self.cgb => {
+                let outputs = self.channel_outputs();
+                outputs[0] | outputs[1] << 4
+            }
+            PCM34 if self.cgb => {
+                let outputs = self.channel_outputs();
+                outputs[2] | outputs[3] << 4
+            }
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if let WAVE_RAM..=0xFF3F = addr {
+            if self.wave.enabled {
+                if self.cgb || self.wave.just_read {
+                    self.wave.ram[(self.wave.position / 2) as usize] = value;
+                }
+            } else {
+                self.wave.ram[(addr - WAVE_RAM) as usize] = value;
+            }
+            return;
+        }
+
+        if addr == NR52 {
+            self.set_power(value & 0x80 != 0);
+            return;
+        }
+
+        if !self.powered {
+            // the DMG keeps its length counters writable while powered off
+            if !self.cgb {
+                match addr {
+                    NR11 => self.square1.length.load((value & 0x3F) as u16),
+                    NR21 => self.square2.length.load((value & 0x3F) as u16),
+                    NR31 => self.wave.length.load(value as u16),
+                    NR41 => self.noise.length.load((value & 0x3F) as u16),
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        if !(NR10..NR52).contains(&addr) { return; }
+        self.registers[(addr - NR10) as usize] = value;
+
+        // the next frame sequencer step won't clock lengths, which enables the extra length clock quirks
+        let length_quirk = self.frame_step & 0x01 == 1;
+
+        match addr {
+            NR10 => {
+                self.square1.sweep.period = (value >> 4) & 0x07;
+                self.square1.sweep.negate = value & 0x08 != 0;
+                self.square1.sweep.shift = value & 0x07;
+                // leaving negate mode after a negated calculation kills the channel
+                if !self.square1.sweep.negate && self.square1.sweep.negate_used {
+                    self.square1.enabled = false;
+                }
+            }
+            NR11 => {
+                self.square1.duty = value >> 6;
+                self.square1.length.load((value & 0x3F) as u16);
+            }
+            NR12 => {
+                self.square1.envelope.write(value);
+                self.square1.dac_enabled = value & 0xF8 != 0;
+                if !self.square1.dac_enabled { self.square1.enabled = false; }
+            }
+            NR13 => self.square1.frequency = (self.square1.frequency & 0x700) | value as u16,
+            NR14 => {
+                self.square1.frequency = (self.square1.frequency & 0xFF) | ((value & 0x07) as u16) << 8;
+                if Apu::write_length_enable(&mut self.square1.length, &mut self.square1.enabled, value, length_quirk) {
+                    self.square1.trigger();
+                    self.square1.sweep_trigger();
+                }
+            }
+            NR21 => {
+                self.square2.duty = value >> 6;
+                self.square2.length.load((value & 0x3F) as u16);
+            }
+            NR22 => {
+                self.square2.envelope.write(value);
+                self.square2.dac_enabled = value & 0xF8 != 0;
+                if !self.square2.dac_enabled { self.square2.enabled = false; }
+            }
+            NR23 => self.square2.frequency = (self.square2.frequency & 0x700) | value as u16,
+            NR24 => {
+                self.square2.frequency = (self.square2.frequency & 0xFF) | ((value & 0x07) as u16) << 8;
+                if Apu::write_length_enable(&mut self.square2.length, &mut self.square2.enabled, value, length_quirk) {
+                    self.square2.trigger();
+                }
+            }
+            NR30 => {
+                self.wave.dac_enabled = value & 0x80 != 0;
+                if !self.wave.dac_enabled { self.wave.enabled = false; }
+            }
+            NR31 => self.wave.length.load(value as u16),
+            NR32 => self.wave.volume_code = (value >> 5) & 0x03,
+            NR33 => self.wave.frequency = (self.wave.frequency & 0x700) | value as u16,
+            NR34 => {
+                self.wave.frequency = (self.wave.frequency & 0xFF) | ((value & 0x07) as u16) << 8;
+                if Apu::write_length_enable(&mut self.wave.length, &mut self.wave.enabled, value, length_quirk) {
+                    self.wave.trigger(self.cgb);
+                }
+            }
+            NR41 => self.noise.length.load((value & 0x3F) as u16),
+            NR42 => {
+                self.noise.envelope.write(value);
+                self.noise.dac_enabled = value & 0xF8 != 0;
+                if !self.noise.dac_enabled { self.noise.enabled = false; }
+            }
+            NR43 => {
+                self.noise.clock_shift = value >> 4;
+                self.noise.width_mode = value & 0x08 != 0;
+                self.noise.divisor_code = value & 0x07;
+            }
+            NR44 => {
+                let trigger = Apu::write_length_enable(&mut self.noise.length, &mut self.noise.enabled, value, length_quirk);
+                if trigger {
+                    self.noise.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // NR10-NR51 as last written, and wave RAM even while it's playing, for BESS states
+    pub fn written(&self, addr: u16) -> u8 {
+        match addr {
+            NR10..NR52 => self.registers[(addr - NR10) as usize],
+            WAVE_RAM..=0xFF3F => self.wave.ram[(addr - WAVE_RAM) as usize],
+            _ => self.read(addr),
+        }
+    }
+
+    // writes back 0xFF10-0xFF3F as saved by `written`. the trigger bits are left out, so the
+    // channels come back silent until the game next starts a note
+    pub fn restore(&mut self, registers: &[u8; 0x30]) {
+        let value = |addr: u16| registers[(addr - NR10) as usize];
+        self.set_power(false);
+        self.write(NR52, value(NR52));
+        for addr in NR10..NR52 {
+            let trigger = matches!(addr, NR14 | NR24 | NR34 | NR44);
+            self.write(addr, if trigger { value(addr) & 0x7F } else { value(addr) });
+        }
+        self.wave.ram.copy_from_slice(&registers[(WAVE_RAM - NR10) as usize..]);
+    }
+
+    // handles the length enable bit of an NRx4 write, returns whether the write also triggers the channel
+    fn write_length_enable(length: &mut LengthCounter, enabled: &mut bool, value: u8, length_quirk: bool) -> bool {
+        let was_enabled = length.enabled;
+        let trigger = value & 0x80 != 0;
+        length.enabled = value & 0x40 != 0;
+
+        // enabling the length counter in the first half of a length period clocks it once more
+        if length_quirk && !was_enabled && length.enabled && length.counter > 0 {
+            length.counter -= 1;
+            if length.counter == 0 && !trigger {
+                *enabled = false;
+            }
+        }
+
+        if trigger && length.counter == 0 {
+            length.counter = if length_quirk && length.enabled { length.max - 1 } else { length.max };
+        }
+
+        trigger
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if on && !self.powered {
+            // the frame sequencer restarts so its next step is 0
+            self.frame_step = 0;
+            self.square1.duty_step = 0;
+            self.square2.duty_step = 0;
+            self.wave.sample_buffer = 0;
+        } else if !on && self.powered {
+            // powering off clears every register; DMG length counters are the exception
+            let lengths = [self.square1.length.counter, self.square2.length.counter, self.wave.length.counter, self.noise.length.counter];
+            let wave_ram = self.wave.ram;
+
+            self.square1 = SquareChannel::new();
+            self.square2 = SquareChannel::new();
+            self.wave = WaveChannel::new();
+            self.noise = NoiseChannel::new();
+            self.registers = [0; 0x20];
+            self.wave.ram = wave_ram;
+
+            if !self.cgb {
+                self.square1.length.counter = lengths[0];
+                self.square2.length.counter = lengths[1];
+                self.wave.length.counter = lengths[2];
+                self.noise.length.counter = lengths[3];
+            }
+        }
+        self.powered = on;
+    }
+}
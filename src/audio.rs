@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+
+pub const CLOCK_RATE: u32 = 4194304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+// band-limited steps are built from a windowed sinc, stored per fractional sample phase
+const PHASES: usize = 32;
+const KERNEL_WIDTH: usize = 16;
+
+// how long finished samples may queue before the oldest are dropped, in seconds
+const MAX_BUFFERED: f64 = 0.5;
+
+// charge factors of the output capacitor per T-cycle, which makes the high-pass filter
+const DMG_CHARGE_FACTOR: f64 = 0.999958;
+const CGB_CHARGE_FACTOR: f64 = 0.998943;
+
+// Turns amplitude changes at emulated clock times into stereo samples at a host rate.
+// Each change is spread over a few output samples as a band-limited step, so the square
+// waves don't alias, and finished samples go through the hardware's DC blocking filter.
+pub struct AudioBuffer {
+    sample_rate: u32,
+    samples_per_clock: f64,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    deltas: [Vec<f32>; 2], // pending amplitude changes, one entry per output sample
+    position: f64,         // output sample position of the current frame's clock 0
+    integrator: [f32; 2],
+    capacitor: [f32; 2],
+    charge: f32,
+    cgb: bool,
+    samples: VecDeque<(f32, f32)>,
+}
+
+impl AudioBuffer {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut buffer = AudioBuffer {
+            sample_rate,
+            samples_per_clock: 0.0,
+            kernel: build_kernel(),
+            deltas: [Vec::new(), Vec::new()],
+            position: 0.0,
+            integrator: [0.0; 2],
+            capacitor: [0.0; 2],
+            charge: 0.0,
+            cgb: false,
+            samples: VecDeque::new(),
+        };
+        buffer.set_sample_rate(sample_rate);
+        buffer
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.samples_per_clock = sample_rate as f64 / CLOCK_RATE as f64;
+        self.set_cgb(self.cgb);
+
+        // enough room for one frame of deltas plus the kernel tail
+        let size = (sample_rate as usize / 30) + KERNEL_WIDTH * 2;
+        self.deltas = [vec![0.0; size], vec![0.0; size]];
+        self.position = 0.0;
+        self.samples.clear();
+    }
+
+    // the CGB's output capacitor discharges faster than the DMG's
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        let factor = if cgb { CGB_CHARGE_FACTOR } else { DMG_CHARGE_FACTOR };
+        self.charge = factor.powf(CLOCK_RATE as f64 / self.sample_rate as f64) as f32;
+    }
+
+    // records a change in amplitude at `clock` T-cycles into the current frame
+    pub fn add_delta(&mut self, clock: u32, left: f32, right: f32) {
+        let position = self.position + clock as f64 * self.samples_per_clock;
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64) as usize;
+        let kernel = &self.kernel[phase.min(PHASES - 1)];
+
+        if index + KERNEL_WIDTH > self.deltas[0].len() {
+            // a frame ran much longer than expected, grow rather than lose the step
+            let size = index + KERNEL_WIDTH * 2;
+            self.deltas[0].resize(size, 0.0);
+            self.deltas[1].resize(size, 0.0);
+        }
+
+        for (i, k) in kernel.iter().enumerate() {
+            self.deltas[0][index + i] += left * k;
+            self.deltas[1][index + i] += right * k;
+        }
+    }
+
+    // closes a frame of `clocks` T-cycles and moves every sample it finished into the queue
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.position += clocks as f64 * self.samples_per_clock;
+        let finished = (self.position as usize).min(self.deltas[0].len());
+
+        for i in 0..finished {
+            let mut frame = [0.0; 2];
+            for (channel, out) in frame.iter_mut().enumerate() {
+                self.integrator[channel] += self.deltas[channel][i];
+                let input = self.integrator[channel];
+                *out = input - self.capacitor[channel];
+                self.capacitor[channel] = input - *out * self.charge;
+            }
+            self.samples.push_back((frame[0], frame[1]));
+        }
+
+        for deltas in self.deltas.iter_mut() {
+            deltas.copy_within(finished.., 0);
+            let len = deltas.len();
+            deltas[len - finished..].fill(0.0);
+        }
+        self.position -= finished as f64;
+
+        let max = (self.sample_rate as f64 * MAX_BUFFERED) as usize;
+        while self.samples.len() > max {
+            self.samples.pop_front();
+        }
+    }
+
+    // stereo frames ready to be read
+    pub fn samples_available(&self) -> usize {
+        self.samples.len()
+    }
+
+    // fills `out` with interleaved left/right samples, returns the number of stereo frames written
+    pub fn read_samples_f32(&mut self, out: &mut [f32]) -> usize {
+        let frames = (out.len() / 2).min(self.samples.len());
+        for (i, (left, right)) in self.samples.drain(..frames).enumerate() {
+            out[i * 2] = left;
+            out[i * 2 + 1] = right;
+        }
+        frames
+    }
+
+    pub fn read_samples_i16(&mut self, out: &mut [i16]) -> usize {
+        let frames = (out.len() / 2).min(self.samples.len());
+        for (i, (left, right)) in self.samples.drain(..frames).enumerate() {
+            out[i * 2] = to_i16(left);
+            out[i * 2 + 1] = to_i16(right);
+        }
+        frames
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+// Blackman windowed sinc, one row per phase, each row normalised so a step keeps its height
+fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let cutoff = 0.45; // relative to the output sample rate, just under Nyquist
+    let half = KERNEL_WIDTH as f64 / 2.0;
+
+    (0..PHASES).map(|phase| {
+        let offset = phase as f64 / PHASES as f64;
+        let mut row = [0.0f32; KERNEL_WIDTH];
+        let mut sum = 0.0;
+
+        for (i, k) in row.iter_mut().enumerate() {
+            let x = i as f64 - half + 1.0 - offset;
+            let sinc = if x.abs() < 1e-9 {
+                2.0 * cutoff
+            } else {
+                (2.0 * std::f64::consts::PI * cutoff * x).sin() / (std::f64::consts::PI * x)
+            };
+            let n = (x + half) / KERNEL_WIDTH as f64;
+            let window = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * n).cos() + 0.08 * (4.0 * std::f64::consts::PI * n).cos();
+            let value = sinc * window.max(0.0);
+            *k = value as f32;
+            sum += value;
+        }
+
+        for k in row.iter_mut() {
+            *k /= sum as f32;
+        }
+        row
+    }).collect()
+}
@@ -0,0 +1,305 @@
+use crate::cartridge::{Cartridge, Mbc, RTC_SAVE_SIZE};
+use crate::cpu::{CPU, Model};
+use crate::error::{EmulationError, Result};
+use crate::savestate;
+
+// Best Effort Save States, the block format SameBoy and other emulators share so states can move
+// between them (https://github.com/LIJI32/SameBoy/blob/master/BESS.md). The memory regions come
+// first as a raw dump, then a chain of blocks:
+//
+//   NAME   the emulator that wrote it
+//   INFO   the ROM's title and global checksum
+//   CORE   model, CPU and I/O registers, and where in the file each memory region is
+//   MBC    register writes that put the MBC back into its banking state
+//   RTC    the MBC3 clock, laid out like the block after a .sav
+//   END
+//
+// and an 8 byte footer: the offset of the first block as a u32, then "BESS". Numbers are little
+// endian. Other emulators put their own state before all this and their own blocks in the chain;
+// blocks we don't know are skipped, and whatever BESS doesn't cover (how far the PPU is into a
+// mode, the APU's channels, SGB state) starts out as on a freshly booted console.
+
+const FOOTER: &[u8; 4] = b"BESS";
+const FOOTER_SIZE: usize = 8;
+const BLOCK_HEADER_SIZE: usize = 8;
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 1;
+
+// CORE block layout
+const CORE_SIZE: usize = 0xD0;
+const CORE_MODEL: usize = 0x04;
+const CORE_REGISTERS: usize = 0x08; // PC, AF, BC, DE, HL, SP
+const CORE_IME: usize = 0x14;
+const CORE_IE: usize = 0x15;
+const CORE_EXECUTION: usize = 0x16; // 0 running, 1 halted, 2 stopped
+const CORE_IO: usize = 0x18;
+const CORE_REGIONS: usize = 0x98; // size and offset of each of the regions below
+
+const WRAM: usize = 0;
+const VRAM: usize = 1;
+const CARTRIDGE_RAM: usize = 2;
+const OAM: usize = 3;
+const HRAM: usize = 4;
+const BG_PALETTES: usize = 5;
+const OBJ_PALETTES: usize = 6;
+const REGIONS: usize = 7;
+
+// INFO block: the header title and global checksum, to spot states made with another ROM
+const INFO_TITLE: std::ops::Range<usize> = 0x134..0x144;
+const INFO_CHECKSUM: std::ops::Range<usize> = 0x14E..0x150;
+
+pub fn is_bess(data: &[u8]) -> bool {
+    data.len() >= FOOTER_SIZE && data.ends_with(FOOTER)
+}
+
+fn info(rom: &[u8]) -> Vec<u8> {
+    [&rom[INFO_TITLE], &rom[INFO_CHECKSUM]].concat()
+}
+
+// appends a region made of `parts`, returns its size and offset for the CORE block
+fn dump(data: &mut Vec<u8>, parts: &[&[u8]]) -> (u32, u32) {
+    let offset = data.len();
+    for part in parts {
+        data.extend_from_slice(part);
+    }
+    ((data.len() - offset) as u32, offset as u32)
+}
+
+fn block(data: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    data.extend_from_slice(id);
+    data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    data.extend_from_slice(body);
+}
+
+// the writes that select the current banks, in an order that leaves each MBC as it is now
+fn mbc_writes(cartridge: &Cartridge) -> Vec<(u16, u8)> {
+    let enable = if cartridge.ram_enabled { 0x0A } else { 0x00 };
+    let bank = cartridge.rom_bank;
+    match cartridge.mbc {
+        Mbc::None => Vec::new(),
+        Mbc::Mbc1 => vec![(0x0000, enable), (0x2000, bank as u8), (0x4000, cartridge.bank_high), (0x6000, cartridge.mode as u8)],
+        Mbc::Mbc2 => vec![(0x0000, enable), (0x0100, bank as u8)],
+        Mbc::Mbc3 => vec![(0x0000, enable), (0x2000, bank as u8), (0x4000, cartridge.ram_bank)],
+        Mbc::Mbc5 => vec![(0x0000, enable), (0x2000, bank as u8), (0x3000, (bank >> 8) as u8), (0x4000, cartridge.ram_bank)],
+    }
+}
+
+fn execution_state(cpu: &CPU) -> u8 {
+    if cpu.stopped() {
+        2
+    } else {
+        cpu.halted() as u8
+    }
+}
+
+pub fn export(cpu: &CPU) -> Vec<u8> {
+    let bus = &cpu.bus;
+    let cgb = bus.apu.cgb; // CGB hardware, with its extra banks even when running a DMG cart
+    let mut data = Vec::new();
+
+    let mut wram = vec![&bus.memory[0xC000..0xE000]];
+    let mut vram = vec![&bus.memory[0x8000..0xA000]];
+    let mut palettes: [&[u8]; 2] = [&[], &[]];
+    if cgb {
+        wram.extend(bus.wram_banks.iter().map(|bank| &bank[..]));
+        vram.push(&bus.vram1);
+        palettes = [&bus.ppu.bg_palette_ram, &bus.ppu.obj_palette_ram];
+    }
+    let cartridge_ram = bus.cartridge.as_ref().map_or(&[][..], |c| &c.ram);
+    let regions = [
+        dump(&mut data, &wram),
+        dump(&mut data, &vram),
+        dump(&mut data, &[cartridge_ram]),
+        dump(&mut data, &[&bus.memory[0xFE00..0xFEA0]]),
+        dump(&mut data, &[&bus.memory[0xFF80..0xFFFF]]),
+        dump(&mut data, &[palettes[0]]),
+        dump(&mut data, &[palettes[1]]),
+    ];
+    let first_block = data.len() as u32;
+
+    block(&mut data, b"NAME", concat!("gb-emulator ", env!("CARGO_PKG_VERSION")).as_bytes());
+    if let Some(cartridge) = &bus.cartridge {
+        block(&mut data, b"INFO", &info(&cartridge.rom));
+    }
+
+    let mut core = Vec::with_capacity(CORE_SIZE);
+    core.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+    core.extend_from_slice(&MINOR_VERSION.to_le_bytes());
+    core.extend_from_slice(match (&bus.sgb, cgb) {
+        (Some(_), _) => b"SN  ",
+        (None, true) => b"CC  ",
+        (None, false) => b"GD  ",
+    });
+    let r = &cpu.registers;
+    for value in [cpu.pc, r.get_af(), r.get_bc(), r.get_de(), r.get_hl(), cpu.sp()] {
+        core.extend_from_slice(&value.to_le_bytes());
+    }
+    core.extend_from_slice(&[cpu.ime() as u8, bus.memory[0xFFFF], execution_state(cpu), 0]);
+    core.extend_from_slice(&bus.io_registers());
+    for (size, offset) in regions {
+        core.extend_from_slice(&size.to_le_bytes());
+        core.extend_from_slice(&offset.to_le_bytes());
+    }
+    block(&mut data, b"CORE", &core);
+
+    if let Some(cartridge) = &bus.cartridge {
+        let writes = mbc_writes(cartridge);
+        if !writes.is_empty() {
+            let body: Vec<u8> = writes.iter().flat_map(|&(addr, value)| [addr as u8, (addr >> 8) as u8, value]).collect();
+            block(&mut data, b"MBC ", &body);
+        }
+        if let Some(rtc) = &cartridge.rtc {
+            block(&mut data, b"RTC ", &rtc.save());
+        }
+    }
+    block(&mut data, b"END ", &[]);
+
+    data.extend_from_slice(&first_block.to_le_bytes());
+    data.extend_from_slice(FOOTER);
+    data
+}
+
+// the blocks this understands, found by walking the chain
+#[derive(Default)]
+struct Blocks<'a> {
+    info: Option<&'a [u8]>,
+    core: Option<&'a [u8]>,
+    mbc: Option<&'a [u8]>,
+    rtc: Option<&'a [u8]>,
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+}
+
+fn invalid(message: &str) -> EmulationError {
+    EmulationError::InvalidState(message.into())
+}
+
+fn blocks(data: &[u8]) -> Result<Blocks<'_>> {
+    let end = data.len() - FOOTER_SIZE;
+    let mut at = read_u32(data, end);
+    let mut blocks = Blocks::default();
+
+    // a chain that runs into the footer without an END block is taken as finished
+    while at + BLOCK_HEADER_SIZE <= end {
+        let id = &data[at..at + 4];
+        let len = read_u32(data, at + 4);
+        let body = data.get(at + BLOCK_HEADER_SIZE..at + BLOCK_HEADER_SIZE + len)
+            .ok_or_else(|| invalid(&format!("{} block runs past the end", String::from_utf8_lossy(id))))?;
+        match id {
+            b"INFO" => blocks.info = Some(body),
+            b"CORE" => blocks.core = Some(body),
+            b"MBC " => blocks.mbc = Some(body),
+            b"RTC " => blocks.rtc = Some(body),
+            b"END " => break,
+            _ => {}
+        }
+        at += BLOCK_HEADER_SIZE + len;
+    }
+    Ok(blocks)
+}
+
+// replaces `cpu` with the state in a BESS file, keeping the cartridge's ROM and the host side
+pub fn import(cpu: &mut CPU, data: &[u8]) -> Result<()> {
+    if !is_bess(data) {
+        return Err(invalid("no BESS footer"));
+    }
+    let blocks = blocks(data)?;
+    let core = blocks.core.ok_or_else(|| invalid("no CORE block"))?;
+    if core.len() < CORE_SIZE {
+        return Err(invalid("CORE block too short"));
+    }
+    let (major, minor) = (read_u16(core, 0), read_u16(core, 2));
+    if major != MAJOR_VERSION {
+        return Err(EmulationError::InvalidState(format!("BESS version {major}.{minor}, this build reads version {MAJOR_VERSION}.x")));
+    }
+
+    let rom = cpu.bus.cartridge.as_ref().map(|c| &c.rom);
+    if let (Some(saved), Some(rom)) = (blocks.info, rom) && saved.len() >= 0x12 && saved[..0x12] != info(rom)[..] {
+        return Err(invalid("saved with a different ROM"));
+    }
+
+    let mut regions = [&[][..]; REGIONS];
+    for (i, region) in regions.iter_mut().enumerate() {
+        let (size, offset) = (read_u32(core, CORE_REGIONS + i * 8), read_u32(core, CORE_REGIONS + i * 8 + 4));
+        *region = data.get(offset..offset + size).ok_or_else(|| invalid("a memory region runs past the end"))?;
+    }
+
+    // boot a fresh console as the saved model, with the same cartridge in, and lay the state over it
+    let mut loaded = CPU::new();
+    let cgb_cart = cpu.bus.cartridge.as_ref().is_some_and(|c| c.header.supports_cgb());
+    let model = match core[CORE_MODEL] {
+        b'G' => Model::Dmg,
+        b'S' => Model::Sgb,
+        b'C' => Model::Cgb,
+        _ if cgb_cart => Model::Cgb,
+        _ => Model::Dmg,
+    };
+    if let Some(current) = &cpu.bus.cartridge {
+        let mut cartridge = Cartridge::new(current.rom.clone())?;
+        cartridge.ram.clone_from(&current.ram); // kept when the state has no cartridge RAM
+        loaded.bus.cartridge = Some(cartridge);
+    }
+    loaded.power_on(model, cgb_cart);
+
+    let bus = &mut loaded.bus;
+    let (memory, wram_banks, vram1) = (&mut bus.memory, &mut bus.wram_banks, &mut bus.vram1);
+    let (low_wram, high) = memory.split_at_mut(0xE000);
+    let (vram, low_wram) = low_wram.split_at_mut(0xA000);
+    let mut wram = vec![&mut low_wram[0xC000 - 0xA000..]];
+    wram.extend(wram_banks.iter_mut().map(|bank| &mut bank[..]));
+    fill(wram, regions[WRAM]);
+    fill(vec![&mut vram[0x8000..], &mut vram1[..]], regions[VRAM]);
+    fill(vec![&mut high[0xFE00 - 0xE000..0xFEA0 - 0xE000]], regions[OAM]);
+    fill(vec![&mut high[0xFF80 - 0xE000..0xFFFF - 0xE000]], regions[HRAM]);
+    fill(vec![&mut bus.ppu.bg_palette_ram], regions[BG_PALETTES]);
+    fill(vec![&mut bus.ppu.obj_palette_ram], regions[OBJ_PALETTES]);
+    if let Some(cartridge) = &mut bus.cartridge {
+        fill(vec![&mut cartridge.ram], regions[CARTRIDGE_RAM]);
+    }
+
+    bus.restore_io_registers(core[CORE_IO..CORE_IO + 0x80].try_into().unwrap());
+    bus.memory[0xFFFF] = core[CORE_IE];
+
+    if let Some(cartridge) = &mut bus.cartridge {
+        for write in blocks.mbc.unwrap_or_default().chunks_exact(3) {
+            let addr = u16::from_le_bytes([write[0], write[1]]);
+            if addr < 0x8000 {
+                cartridge.write(addr, write[2]);
+            }
+        }
+        if let (Some(rtc), Some(saved)) = (&mut cartridge.rtc, blocks.rtc) && saved.len() >= RTC_SAVE_SIZE {
+            rtc.load(saved);
+        }
+    }
+
+    let register = |i: usize| read_u16(core, CORE_REGISTERS + i * 2);
+    loaded.pc = register(0);
+    loaded.registers.set_af(register(1));
+    loaded.registers.set_bc(register(2));
+    loaded.registers.set_de(register(3));
+    loaded.registers.set_hl(register(4));
+    loaded.set_sp(register(5));
+    loaded.set_ime(core[CORE_IME] != 0);
+    loaded.set_halted(core[CORE_EXECUTION] == 1);
+    loaded.set_stopped(core[CORE_EXECUTION] == 2);
+
+    savestate::replace(cpu, loaded);
+    cpu.bus.apu.output.set_cgb(cpu.bus.apu.cgb);
+    Ok(())
+}
+
+// copies a saved region across `parts` in order. a region from a different model can be shorter
+// or longer than ours; what it doesn't cover is left as it was
+fn fill(parts: Vec<&mut [u8]>, mut saved: &[u8]) {
+    for part in parts {
+        let len = part.len().min(saved.len());
+        part[..len].copy_from_slice(&saved[..len]);
+        saved = &saved[len..];
+    }
+}
@@ -0,0 +1,479 @@
+use crate::error::{EmulationError, Result};
+use crate::png::crc32_update;
+
+use serde::{Serialize, Deserialize};
+
+// Cartridge header fields live at 0x0100-0x014F of every ROM
+
+const TITLE_START: usize = 0x134;
+const TITLE_END: usize = 0x143;
+const CGB_FLAG: usize = 0x143;
+const SGB_FLAG: usize = 0x146;
+const CARTRIDGE_TYPE: usize = 0x147;
+const ROM_SIZE: usize = 0x148;
+const RAM_SIZE: usize = 0x149;
+const OLD_LICENSEE: usize = 0x14B;
+const HEADER_CHECKSUM: usize = 0x14D;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CgbSupport {
+    None,       // DMG only, runs in compatibility mode on a CGB
+    Enhanced,   // 0x80, works on both DMG and CGB
+    Only,       // 0xC0, CGB only
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub old_licensee: u8,
+    pub header_checksum: u8,
+}
+
+impl CartridgeHeader {
+    pub fn from_rom(rom: &[u8]) -> Option<CartridgeHeader> {
+        if rom.len() < 0x150 {
+            return None;
+        }
+
+        let cgb = match rom[CGB_FLAG] {
+            0xC0 => CgbSupport::Only,
+            f if f & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // CGB aware carts reuse the last title byte(s) for the CGB flag
+        let title_end = if cgb == CgbSupport::None { TITLE_END + 1 } else { TITLE_END };
+        let title: String = rom[TITLE_START..title_end].iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect();
+
+        Some(CartridgeHeader {
+            title,
+            cgb,
+            sgb_flag: rom[SGB_FLAG],
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size: rom[ROM_SIZE],
+            ram_size: rom[RAM_SIZE],
+            old_licensee: rom[OLD_LICENSEE],
+            header_checksum: rom[HEADER_CHECKSUM],
+        })
+    }
+
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb != CgbSupport::None
+    }
+
+    // the SGB BIOS only unlocks its features when the old licensee code defers to the new one
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    // the boot ROM refuses to start a cart whose header doesn't sum to the stored checksum
+    pub fn checksum_valid(&self, rom: &[u8]) -> bool {
+        let sum = rom[TITLE_START..HEADER_CHECKSUM].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+        sum == self.header_checksum
+    }
+}
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const MBC2_RAM_SIZE: usize = 0x200;
+
+// the RTC crystal runs at 32768 Hz, which is one second every 4194304 normal speed T-cycles
+const RTC_SECOND_CYCLES: u32 = 4194304;
+
+// size of the RTC block emulators append to .sav files: 5 current and 5 latched registers
+// as little endian u32s, then a u64 unix timestamp
+pub const RTC_SAVE_SIZE: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Mbc {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+// The MBC3 real time clock, counted in emulated time so runs stay reproducible
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rtc {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days: u16, // 9 bits
+    pub halted: bool,
+    pub carry: bool, // day counter overflowed
+    pub latched: [u8; 5],
+    pub cycles: u32, // progress into the current second
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            carry: false,
+            latched: [0; 5],
+            cycles: 0,
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.halted {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= RTC_SECOND_CYCLES {
+            self.cycles -= RTC_SECOND_CYCLES;
+            self.advance_second();
+        }
+    }
+
+    // out of range values count up to the register width before wrapping, without a carry
+    fn advance_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 { return; }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 { return; }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 { return; }
+        self.hours = 0;
+
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    pub fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.days >> 8) as u8 | (self.halted as u8) << 6 | (self.carry as u8) << 7,
+        ]
+    }
+
+    pub fn latch(&mut self) {
+        self.latched = self.registers();
+    }
+
+    // registers 0x08-0x0C: seconds, minutes, hours, day low, day high/halt/carry
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => {
+                // writing the seconds also restarts the sub-second divider
+                self.seconds = value & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value & 0x01) as u16) << 8;
+                self.halted = value & 0x40 != 0;
+                self.carry = value & 0x80 != 0;
+            }
+        }
+        self.latched[(register - 0x08) as usize] = self.registers()[(register - 0x08) as usize];
+    }
+
+    pub fn set_registers(&mut self, registers: [u8; 5]) {
+        for (i, &value) in registers.iter().enumerate() {
+            self.write(0x08 + i as u8, value);
+        }
+    }
+
+    // catches up on time the emulator wasn't running, like the battery backed clock would
+    pub fn advance_seconds(&mut self, seconds: u64) {
+        if self.halted {
+            return;
+        }
+        // days wrap every 512, so there's no point counting further than that plus a carry
+        for _ in 0..seconds.min(513 * 86400) {
+            self.advance_second();
+        }
+    }
+
+    // the block appended to .sav files, stamped with the current time
+    pub fn save(&self) -> [u8; RTC_SAVE_SIZE] {
+        let mut data = [0; RTC_SAVE_SIZE];
+        for (i, value) in self.registers().iter().chain(self.latched.iter()).enumerate() {
+            data[i * 4..i * 4 + 4].copy_from_slice(&(*value as u32).to_le_bytes());
+        }
+        data[40..48].copy_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    // restores a saved block and runs the clock forward by however long ago it was saved
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap()) as u8;
+        self.set_registers([word(0), word(1), word(2), word(3), word(4)]);
+        self.latched = [word(5), word(6), word(7), word(8), word(9)];
+
+        let saved = u64::from_le_bytes(data[40..48].try_into().unwrap());
+        self.advance_seconds(unix_time().saturating_sub(saved));
+    }
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// A cartridge: ROM, external RAM and the memory bank controller that maps them in
+#[derive(Serialize, Deserialize)]
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    #[serde(skip)]
+    pub rom: Vec<u8>, // save states leave this out and take it from the cartridge they load into
+    #[serde(skip)]
+    pub rom_crc: u32, // CRC-32 of `rom`, worked out once since every save state carries it
+    pub ram: Vec<u8>,
+    pub mbc: Mbc,
+    pub battery: bool,
+    pub rtc: Option<Rtc>,
+    pub ram_enabled: bool,
+    pub rom_bank: u16,   // bank register as written; 0 maps to 1 where the MBC does that
+    pub ram_bank: u8,    // RAM bank, or RTC register 0x08-0x0C on MBC3
+    pub bank_high: u8,   // MBC1's 2-bit register: upper ROM bank bits or the RAM bank
+    pub mode: bool,      // MBC1 banking mode
+    pub latch_armed: bool, // MBC3 latches the clock on a 0 then 1 write
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Result<Cartridge> {
+        let header = CartridgeHeader::from_rom(&rom)
+            .ok_or_else(|| EmulationError::InvalidRom(format!("too small to hold a header ({} bytes)", rom.len())))?;
+
+        let (mbc, battery, rtc) = match header.cartridge_type {
+            0x00 | 0x08 => (Mbc::None, false, false),
+            0x09 => (Mbc::None, true, false),
+            0x01 | 0x02 => (Mbc::Mbc1, false, false),
+            0x03 => (Mbc::Mbc1, true, false),
+            0x05 => (Mbc::Mbc2, false, false),
+            0x06 => (Mbc::Mbc2, true, false),
+            0x0F | 0x10 => (Mbc::Mbc3, true, true),
+            0x11 | 0x12 => (Mbc::Mbc3, false, false),
+            0x13 => (Mbc::Mbc3, true, false),
+            0x19 | 0x1A | 0x1C | 0x1D => (Mbc::Mbc5, false, false),
+            0x1B | 0x1E => (Mbc::Mbc5, true, false),
+            t => return Err(EmulationError::InvalidRom(format!("unsupported cartridge type 0x{t:02X}"))),
+        };
+
+        let ram_size = match (mbc, header.ram_size) {
+            (Mbc::Mbc2, _) => MBC2_RAM_SIZE,
+            (_, 0x01) => 0x800,
+            (_, 0x02) => 0x2000,
+            (_, 0x03) => 0x8000,
+            (_, 0x04) => 0x20000,
+            (_, 0x05) => 0x10000,
+            _ => 0,
+        };
+
+        // short dumps still get a full 32 KB address space
+        let mut rom = rom;
+        if rom.len() < ROM_BANK_SIZE * 2 {
+            rom.resize(ROM_BANK_SIZE * 2, 0xFF);
+        }
+
+        Ok(Cartridge {
+            header,
+            rom_crc: !crc32_update(0xFFFF_FFFF, &rom),
+            rom,
+            ram: vec![0; ram_size],
+            mbc,
+            battery,
+            rtc: rtc.then(Rtc::new),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            bank_high: 0,
+            mode: false,
+            latch_armed: false,
+        })
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom_byte(self.low_rom_bank(), addr),
+            0x4000..=0x7FFF => self.rom_byte(self.high_rom_bank(), addr - 0x4000),
+            _ => self.read_ram(addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.write_register(addr, value),
+            _ => self.write_ram(addr, value),
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    // the ROM bank currently mapped at a 0x0000-0x7FFF address
+    pub fn mapped_bank(&self, addr: u16) -> usize {
+        let bank = if addr < 0x4000 { self.low_rom_bank() } else { self.high_rom_bank() };
+        bank % self.rom_banks()
+    }
+
+    fn rom_banks(&self) -> usize {
+        self.rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    fn rom_byte(&self, bank: usize, offset: u16) -> u8 {
+        let bank = bank % self.rom_banks();
+        self.rom.get(bank * ROM_BANK_SIZE + offset as usize).copied().unwrap_or(0xFF)
+    }
+
+    // MBC1 in mode 1 also applies the upper bank bits to the 0x0000-0x3FFF area
+    fn low_rom_bank(&self) -> usize {
+        match self.mbc {
+            Mbc::Mbc1 if self.mode => (self.bank_high as usize) << 5,
+            _ => 0,
+        }
+    }
+
+    fn high_rom_bank(&self) -> usize {
+        let nonzero = |bank: u16| if bank == 0 { 1 } else { bank as usize };
+        match self.mbc {
+            Mbc::None => 1,
+            Mbc::Mbc1 => nonzero(self.rom_bank & 0x1F) | (self.bank_high as usize) << 5,
+            Mbc::Mbc2 => nonzero(self.rom_bank & 0x0F),
+            Mbc::Mbc3 => nonzero(self.rom_bank & 0x7F),
+            Mbc::Mbc5 => (self.rom_bank & 0x1FF) as usize,
+        }
+    }
+
+    fn ram_bank_number(&self) -> usize {
+        match self.mbc {
+            Mbc::Mbc1 if self.mode => self.bank_high as usize,
+            Mbc::Mbc3 => (self.ram_bank & 0x03) as usize,
+            Mbc::Mbc5 => (self.ram_bank & 0x0F) as usize,
+            _ => 0,
+        }
+    }
+
+    // where an 0xA000-0xBFFF access lands in `ram`, if RAM is enabled and mapped there
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() || self.rtc_selected() {
+            return None;
+        }
+        let offset = match self.mbc {
+            Mbc::Mbc2 => (addr & 0x01FF) as usize,
+            _ => self.ram_bank_number() * RAM_BANK_SIZE + (addr - 0xA000) as usize,
+        };
+        Some(offset % self.ram.len())
+    }
+
+    fn rtc_selected(&self) -> bool {
+        self.mbc == Mbc::Mbc3 && self.ram_bank >= 0x08
+    }
+
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ram_enabled && self.rtc_selected() {
+            return match &self.rtc {
+                Some(rtc) if self.ram_bank <= 0x0C => rtc.read(self.ram_bank),
+                _ => 0xFF,
+            };
+        }
+        match self.ram_offset(addr) {
+            // MBC2 RAM is 4 bits wide, the upper nibble floats high
+            Some(offset) if self.mbc == Mbc::Mbc2 => 0xF0 | self.ram[offset],
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.ram_enabled && self.rtc_selected() {
+            if let Some(rtc) = &mut self.rtc && self.ram_bank <= 0x0C {
+                rtc.write(self.ram_bank, value);
+            }
+            return;
+        }
+        if let Some(offset) = self.ram_offset(addr) {
+            self.ram[offset] = if self.mbc == Mbc::Mbc2 { value & 0x0F } else { value };
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match (self.mbc, addr) {
+            (Mbc::None, _) => {}
+            (Mbc::Mbc2, 0x0000..=0x3FFF) => {
+                // address bit 8 picks between the RAM enable and the ROM bank register
+                if addr & 0x0100 != 0 {
+                    self.rom_bank = (value & 0x0F) as u16;
+                } else {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                }
+            }
+            (Mbc::Mbc2, _) => {}
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = value & 0x0F == 0x0A,
+            (Mbc::Mbc1, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x1F) as u16,
+            (Mbc::Mbc1, 0x4000..=0x5FFF) => self.bank_high = value & 0x03,
+            (Mbc::Mbc1, _) => self.mode = value & 0x01 != 0,
+            (Mbc::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x7F) as u16,
+            (Mbc::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = value & 0x0F,
+            (Mbc::Mbc3, _) => {
+                if self.latch_armed && value == 0x01 && let Some(rtc) = &mut self.rtc {
+                    rtc.latch();
+                }
+                self.latch_armed = value == 0x00;
+            }
+            (Mbc::Mbc5, 0x2000..=0x2FFF) => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            (Mbc::Mbc5, 0x3000..=0x3FFF) => self.rom_bank = (self.rom_bank & 0xFF) | ((value & 0x01) as u16) << 8,
+            (Mbc::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = value & 0x0F,
+            (Mbc::Mbc5, _) => {}
+        }
+    }
+
+    // battery backed RAM in the usual .sav layout, with the RTC block after it on MBC3 clocks
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        if !self.battery {
+            return None;
+        }
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.save());
+        }
+        Some(data)
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let len = self.ram.len().min(data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(&data[len..]);
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use std::ops::Add;
-
 use crate::reg;
 use crate::memory;
+use crate::cartridge;
+use crate::joypad;
+use crate::serial;
+use crate::sgb;
 use crate::reg::FlagsRegister;
+use crate::error::{EmulationError, Result};
 
 use serde::{Serialize,Deserialize};
 
@@ -34,6 +37,7 @@ pub struct CpuState {
     ram: Vec<[u16; 2]>,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum Target {
     Reg8(Reg8),
     Reg16(Reg16),
@@ -41,23 +45,28 @@ pub enum Target {
     Value,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum Reg8 {
     A, B, C, D, E, H, L, D8, HLI, BCI, DEI, HLII, HLDI, D16I, CI, D8I
 }
 
 // af, bc, de, hl
+#[derive(Debug, Clone, Copy)]
 pub enum Reg16 {
     AF, BC, DE, HL, SP, D16, I16
 }
 
+#[derive(Debug)]
 pub enum StackTarget {
     AF, BC, DE, HL
 }
 
+#[derive(Debug)]
 pub enum AddHLTarget {
     BC, DE, HL, SP
 }
 
+#[derive(Debug)]
 pub enum Instruction {
     Add(Target),
     AddHL(AddHLTarget),
@@ -104,6 +113,7 @@ pub enum Instruction {
     Set7(Target),
     Cpl,
     Ccf,
+    Daa,
     Scf,
     Nop,
     Rlca,
@@ -112,19 +122,26 @@ pub enum Instruction {
     Rra,
 
     HALT,
+    STOP,
     DI,
+    EI,
+    RETI,
 
     JP(JumpTest),
     JR(JumpTest),
     JPHL,
+    AddSP,
 
     LD(LoadType),
+    LDHLSP,
     POP(StackTarget),
     PUSH(StackTarget),
     CALL(JumpTest),
     RET(JumpTest),
+    RST(u8),
 }
 
+#[derive(Debug)]
 pub enum JumpTest {
     NotZero,
     Zero,
@@ -133,6 +150,7 @@ pub enum JumpTest {
     Always
 }
 
+#[derive(Debug)]
 pub enum LoadType {
     Word(Reg16, Reg16),
     Byte(Reg8, Reg8),
@@ -445,11 +463,12 @@ impl Instruction {
     fn from_byte_not_prefixed(byte: u8) -> Option<Instruction> {
         match byte {
             0x00 => Some(Instruction::Nop),
+            0x10 => Some(Instruction::STOP),
             0x07 => Some(Instruction::Rlca),
             0x0F => Some(Instruction::Rrca),
             0x17 => Some(Instruction::Rla),
             0x1F => Some(Instruction::Rra),
-            /* DAA 0x27 => */
+            0x27 => Some(Instruction::Daa),
             0x2F => Some(Instruction::Cpl),
             0x37 => Some(Instruction::Scf),
             0x3F => Some(Instruction::Ccf),
@@ -468,6 +487,11 @@ impl Instruction {
             0x23 => Some(Instruction::Inc(Target::Reg16(Reg16::HL))),
             0x33 => Some(Instruction::Inc(Target::Reg16(Reg16::SP))),
 
+            0x0B => Some(Instruction::Dec(Target::Reg16(Reg16::BC))),
+            0x1B => Some(Instruction::Dec(Target::Reg16(Reg16::DE))),
+            0x2B => Some(Instruction::Dec(Target::Reg16(Reg16::HL))),
+            0x3B => Some(Instruction::Dec(Target::Reg16(Reg16::SP))),
+
             0x05 => Some(Instruction::Dec(Target::Reg8(Reg8::B))),
             0x0D => Some(Instruction::Dec(Target::Reg8(Reg8::C))),
             0x15 => Some(Instruction::Dec(Target::Reg8(Reg8::D))),
@@ -490,6 +514,7 @@ impl Instruction {
             0x19 => Some(Instruction::AddHL(AddHLTarget::DE)),
             0x29 => Some(Instruction::AddHL(AddHLTarget::HL)),
             0x39 => Some(Instruction::AddHL(AddHLTarget::SP)),
+            0xE8 => Some(Instruction::AddSP),
 
             0x88 => Some(Instruction::Adc(Target::Reg8(Reg8::B))),
             0x89 => Some(Instruction::Adc(Target::Reg8(Reg8::C))),
@@ -578,6 +603,9 @@ impl Instruction {
             0x11 => Some(Instruction::LD(LoadType::Word(Reg16::DE,Reg16::D16))),
             0x21 => Some(Instruction::LD(LoadType::Word(Reg16::HL,Reg16::D16))),
             0x31 => Some(Instruction::LD(LoadType::Word(Reg16::SP,Reg16::D16))),
+            0x08 => Some(Instruction::LD(LoadType::Word(Reg16::I16,Reg16::SP))),
+            0xF9 => Some(Instruction::LD(LoadType::Word(Reg16::SP,Reg16::HL))),
+            0xF8 => Some(Instruction::LDHLSP),
 
             0x40 => Some(Instruction::LD(LoadType::Byte(Reg8::B,Reg8::B))),
             0x41 => Some(Instruction::LD(LoadType::Byte(Reg8::B,Reg8::C))),
@@ -674,6 +702,8 @@ impl Instruction {
             0xF2 => Some(Instruction::LD(LoadType::Byte(Reg8::A,Reg8::CI))),
 
             0xF3 => Some(Instruction::DI),
+            0xFB => Some(Instruction::EI),
+            0xD9 => Some(Instruction::RETI),
 
             0xC1 => Some(Instruction::POP(StackTarget::BC)),
             0xD1 => Some(Instruction::POP(StackTarget::DE)),
@@ -697,16 +727,109 @@ impl Instruction {
             0xC8 => Some(Instruction::RET(JumpTest::Zero)),
             0xD8 => Some(Instruction::RET(JumpTest::Carry)),
 
-            _ => { /* Add more instructions */ None }
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some(Instruction::RST(byte & 0x38)),
+
+            // $D3, $DB, $DD, $E3, $E4, $EB-$ED, $F4 and $FC-$FD don't exist on the SM83
+            _ => None
         }
     }
 }
 
+/* Instruction timings in T-cycles, conditional branches listed as not taken */
+const OPCODE_CYCLES: [u8; 256] = [
+//  0   1   2   3   4   5   6   7   8   9   A   B   C   D   E   F
+    4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4, // 0
+    4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 1
+    8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 2
+    8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 3
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 4
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 5
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 6
+    8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4, // 7
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 8
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 9
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // A
+    4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // B
+    8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16, // C
+    8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16, // D
+   12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16, // E
+   12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16, // F
+];
+
+/* Instruction lengths in bytes, operands included. 0xCB counts the prefixed opcode after it */
+pub const OPCODE_LENGTHS: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 1
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 2
+    2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 3
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 4
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 5
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 6
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 7
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 8
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 9
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // A
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // B
+    1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1, // C
+    1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1, // D
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // E
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // F
+];
+
+// extra cycles a conditional JR/JP/CALL/RET costs when the branch is taken
+fn branch_cycles(opcode: u8) -> u32 {
+    match opcode {
+        0x20 | 0x28 | 0x30 | 0x38 | 0xC2 | 0xCA | 0xD2 | 0xDA => 4,
+        0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xC4 | 0xCC | 0xD4 | 0xDC => 12,
+        _ => 0
+    }
+}
+
+// CB prefixed timings, including the prefix byte
+fn prefixed_cycles(opcode: u8) -> u32 {
+    if opcode & 0x07 != 0x06 {
+        8
+    } else if (0x40..0x80).contains(&opcode) {
+        12 // BIT n,(HL) only reads memory
+    } else {
+        16
+    }
+}
+
+const INTERRUPT_CYCLES: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    Dmg,
+    Sgb,
+    Cgb,
+}
+
+impl Model {
+    // CGB mode is picked from the header's CGB flag, as the CGB boot ROM does
+    pub fn for_header(header: &cartridge::CartridgeHeader) -> Model {
+        if header.supports_cgb() {
+            Model::Cgb
+        } else if header.supports_sgb() {
+            Model::Sgb
+        } else {
+            Model::Dmg
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct CPU {
     pub registers: reg::Registers,
     sp: u16,
     pub pc: u16,
-    bus: memory::MemoryBus,
+    pub bus: memory::MemoryBus,
+    ime: bool,
+    ime_pending: bool,
+    halted: bool,
+    stopped: bool, // in STOP, which only a button press ends
+    branch_taken: bool,
 }
 
 impl CPU {
@@ -729,35 +852,131 @@ impl CPU {
             },
             sp: 0,
             pc: 0,
-            bus: memory::MemoryBus {
-                memory: [0; 0x10000]
-            }
+            bus: memory::MemoryBus::new(),
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            stopped: false,
+            branch_taken: false,
         }
     }
 
-    pub fn load_rom(&mut self, filepath: &str) {
-        let rom: Vec<u8> = std::fs::read(filepath).unwrap();
-        self.bus.memory[0..32768].copy_from_slice(&rom[..]);
-        self.pc = 0x100;
+    pub fn load_rom(&mut self, filepath: &str) -> Result<()> {
+        let rom: Vec<u8> = std::fs::read(filepath).map_err(|e| EmulationError::io(filepath, e))?;
+        self.load_cartridge(cartridge::Cartridge::new(rom)?);
+        Ok(())
+    }
+
+    // inserts a cartridge and powers on as the model it asks for
+    pub fn load_cartridge(&mut self, cartridge: cartridge::Cartridge) {
+        let model = Model::for_header(&cartridge.header);
+        self.load_cartridge_as(cartridge, model);
+    }
+
+    // powers on as a given model whatever the header says, e.g. a CGB running a DMG game
+    // through its compatibility palettes
+    pub fn load_cartridge_as(&mut self, cartridge: cartridge::Cartridge, model: Model) {
+        let cgb_cart = cartridge.header.supports_cgb();
+        self.bus.cartridge = Some(cartridge);
+        self.power_on(model, cgb_cart);
     }
 
-    pub fn run_sm83_tests(&mut self, op_codes: &Vec<u8>, prefixed: bool) {
-        for &i in op_codes {
-            let tests: Vec<CpuTest> = serde_json::from_str::<Vec<CpuTest>>(
-                    &String::from_utf8(
-                        std::fs::read(
-                            format!("sm83/v1/{}{i:02x}.json", if prefixed { "cb " } else { "" })
-                        ).unwrap()
-                    ).unwrap()
-                ).unwrap();
-            for test in tests {
-                self.set_state(&test.initial_state);
-                let instruction: Instruction = Instruction::from_byte(i, prefixed).unwrap();
-                self.pc = self.execute(instruction);
-                self.compare_state(&test.final_state);
+    // puts the registers into the state the boot ROM leaves them in
+    pub fn power_on(&mut self, model: Model, cgb_cart: bool) {
+        match model {
+            Model::Dmg => {
+                self.registers.set_af(0x01B0);
+                self.registers.set_bc(0x0013);
+                self.registers.set_de(0x00D8);
+                self.registers.set_hl(0x014D);
+            }
+            Model::Sgb => {
+                self.registers.set_af(0x0100);
+                self.registers.set_bc(0x0014);
+                self.registers.set_de(0x0000);
+                self.registers.set_hl(0xC060);
+            }
+            Model::Cgb => {
+                // A = 0x11 is how games detect they are running on a CGB
+                self.registers.set_af(0x1180);
+                self.registers.set_bc(0x0000);
+                self.registers.set_de(0xFF56);
+                self.registers.set_hl(0x000D);
             }
-            println!("0x{}{i:02x} passed!", if prefixed { "cb" } else { "" });
         }
+        self.sp = 0xFFFE;
+        self.pc = 0x100;
+
+        self.bus.cgb_mode = model == Model::Cgb && cgb_cart;
+        self.bus.sgb = (model == Model::Sgb).then(sgb::Sgb::new);
+        self.bus.apu.cgb = model == Model::Cgb;
+        self.bus.serial.cgb = self.bus.cgb_mode;
+        self.bus.apu.output.set_cgb(model == Model::Cgb);
+        self.bus.ppu.cgb_mode = self.bus.cgb_mode;
+        self.bus.ppu.compat_mode = model == Model::Cgb && !cgb_cart;
+        if self.bus.ppu.compat_mode {
+            self.bus.ppu.load_compat_palettes();
+        }
+
+        self.bus.memory[0xFF0F] = 0xE1;
+        self.bus.memory[0xFF40] = 0x91;
+        self.bus.memory[0xFF41] = 0x85;
+        self.bus.memory[0xFF47] = 0xFC;
+        self.bus.memory[0xFF48] = 0xFF;
+        self.bus.memory[0xFF49] = 0xFF;
+
+        // the boot ROM leaves the APU powered with every channel panned to both sides
+        self.bus.write_byte(0xFF26, 0x80);
+        self.bus.write_byte(0xFF24, 0x77);
+        self.bus.write_byte(0xFF25, 0xF3);
+    }
+
+    // host side input, either one button at a time or a whole bitmask of joypad::Button bits
+    pub fn set_button(&mut self, button: joypad::Button, pressed: bool) {
+        self.bus.set_button(button, pressed);
+    }
+
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.bus.set_buttons(pressed);
+    }
+
+    // joypads 2-4 of an SGB multiplayer adapter, `player` counts from 0
+    pub fn set_player_buttons(&mut self, player: usize, pressed: u8) {
+        self.bus.set_player_buttons(player, pressed);
+    }
+
+    // the 256x224 bordered frame when running as a Super Game Boy
+    pub fn sgb(&self) -> Option<&sgb::Sgb> {
+        self.bus.sgb.as_ref()
+    }
+
+    // plugs something into the link port, replacing whatever was there
+    pub fn set_serial_device(&mut self, device: Box<dyn serial::SerialDevice>) {
+        self.bus.serial.device = device;
+    }
+
+    // every case in sm83/v1 for one opcode, returning how many there were
+    pub fn run_sm83_tests(&mut self, opcode: u8, prefixed: bool) -> Result<usize> {
+        let path = format!("sm83/v1/{}{opcode:02x}.json", if prefixed { "cb " } else { "" });
+        let json = std::fs::read(&path).map_err(|e| EmulationError::io(&path, e))?;
+        let tests: Vec<CpuTest> = serde_json::from_slice(&json)
+            .map_err(|source| EmulationError::TestParse { path: path.clone(), source })?;
+
+        for test in &tests {
+            self.run_sm83_test(opcode, prefixed, test)?;
+        }
+        Ok(tests.len())
+    }
+
+    // one case of the JSON tests. they treat memory as 64K of plain RAM, so the bus is switched
+    // over to that and registers like DIV or LY keep what the test stores in them
+    pub fn run_sm83_test(&mut self, opcode: u8, prefixed: bool, test: &CpuTest) -> Result<()> {
+        self.bus.flat = true;
+        self.set_state(&test.initial_state);
+        let instruction: Instruction = Instruction::from_byte(opcode, prefixed)
+            .ok_or(EmulationError::UnknownOpcode { pc: self.pc, opcode, prefixed })?;
+        self.pc = self.execute(instruction)?;
+        self.compare_state(&test.name, &test.final_state)
     }
 
     pub fn set_state(&mut self, state: &CpuState) {
@@ -778,40 +997,73 @@ impl CPU {
         }
     }
 
-    pub fn compare_state(&self, state: &CpuState) {
+    pub fn compare_state(&self, name: &str, state: &CpuState) -> Result<()> {
+        let failed = |message: String| Err(EmulationError::TestFailed { name: name.to_string(), message });
+
         /* Compare registers */
-        assert_eq!(self.registers.a, state.a, "Register A: {} (expected {})", self.registers.a, state.a);
-        assert_eq!(self.registers.b, state.b, "Register B: {} (expected {})", self.registers.b, state.b);
-        assert_eq!(self.registers.c, state.c, "Register C: {} (expected {})", self.registers.c, state.c);
-        assert_eq!(self.registers.d, state.d, "Register D: {} (expected {})", self.registers.d, state.d);
-        assert_eq!(self.registers.e, state.e, "Register E: {} (expected {})", self.registers.e, state.e);
-        assert_eq!(u8::from(self.registers.f), state.f, "Flags: {} (expected {})", u8::from(self.registers.f), state.f);
-        assert_eq!(self.registers.h, state.h, "Register H: {} (expected {})", self.registers.h, state.h);
-        assert_eq!(self.registers.l, state.l, "Register L: {} (expected {})", self.registers.l, state.l);
+        let registers = [
+            ("Register A", self.registers.a, state.a),
+            ("Register B", self.registers.b, state.b),
+            ("Register C", self.registers.c, state.c),
+            ("Register D", self.registers.d, state.d),
+            ("Register E", self.registers.e, state.e),
+            ("Flags", u8::from(self.registers.f), state.f),
+            ("Register H", self.registers.h, state.h),
+            ("Register L", self.registers.l, state.l),
+        ];
+        for (register, value, expected) in registers {
+            if value != expected {
+                return failed(format!("{register}: {value} (expected {expected})"));
+            }
+        }
 
         /* Compare PC and SP */
-        assert_eq!(self.pc, state.pc, "PC: {} (expected {})", self.pc, state.pc);
-        assert_eq!(self.sp, state.sp, "SP: {} (expected {})", self.sp, state.sp);
+        if self.pc != state.pc {
+            return failed(format!("PC: {} (expected {})", self.pc, state.pc));
+        }
+        if self.sp != state.sp {
+            return failed(format!("SP: {} (expected {})", self.sp, state.sp));
+        }
 
         /* Compare memory */
-        for r in state.ram.clone() {
-            assert_eq!(self.bus.memory[r[0] as usize], r[1] as u8, "RAM at addr: {}: {} (expected {})", r[0], 
-                self.bus.memory[r[0] as usize], r[1]);
+        for r in state.ram.iter() {
+            let value = self.bus.memory[r[0] as usize];
+            if value != r[1] as u8 {
+                return failed(format!("RAM at addr: {}: {} (expected {})", r[0], value, r[1]));
+            }
         }
+        Ok(())
     }
 
-    pub fn run(&mut self) {
+    // runs until something goes wrong
+    pub fn run(&mut self) -> Result<()> {
         loop {
-            self.step();
+            self.step()?;
+        }
+    }
 
-            if (self.bus.memory[0xff02] == 0x81) {
-                print!("{}", self.bus.memory[0xff01] as char);
-                self.bus.memory[0xff01] = 0;
+    // runs one instruction (or interrupt dispatch, or a HALTed M-cycle), returns the T-cycles it took
+    pub fn step(&mut self) -> Result<u32> {
+        // STOP halts the clocks too, so nothing else moves until a selected button line goes low
+        if self.stopped {
+            if !self.bus.button_held() {
+                return Ok(4);
             }
+            self.stopped = false;
         }
-    }
 
-    fn step(&mut self) {
+        if self.service_interrupt() {
+            self.bus.tick(INTERRUPT_CYCLES);
+            return Ok(INTERRUPT_CYCLES);
+        }
+
+        if self.halted {
+            self.bus.tick(4);
+            return Ok(4);
+        }
+
+        let enable_interrupts = self.ime_pending;
+
         let mut instruction_byte = self.bus.read_byte(self.pc);
         let prefixed = instruction_byte == 0xCB;
 
@@ -819,19 +1071,113 @@ impl CPU {
             instruction_byte = self.bus.read_byte(self.pc.wrapping_add(1));
         }
 
-        let next_pc: u16 = if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed) {
-            self.execute(instruction)
+        self.branch_taken = false;
+        let instruction = Instruction::from_byte(instruction_byte, prefixed)
+            .ok_or(EmulationError::UnknownOpcode { pc: self.pc, opcode: instruction_byte, prefixed })?;
+        let next_pc: u16 = self.execute(instruction)?;
+
+        self.pc = next_pc;
+
+        let cycles = if prefixed {
+            prefixed_cycles(instruction_byte)
+        } else if self.branch_taken {
+            OPCODE_CYCLES[instruction_byte as usize] as u32 + branch_cycles(instruction_byte)
         } else {
-            println!("{:?} PC:{} SP:{} RAM at PC:{}", self.registers, self.pc, self.sp, self.bus.memory[self.pc as usize]);
-            panic!("Unknown instruction: 0x{instruction_byte:x}");
+            OPCODE_CYCLES[instruction_byte as usize] as u32
         };
 
-        self.pc = next_pc;
+        // EI takes effect after the instruction that follows it
+        if enable_interrupts && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+
+        self.bus.tick(cycles);
+
+        // DMA and speed switches keep the CPU off the bus while the rest of the system runs
+        let mut total = cycles;
+        loop {
+            let stall = self.bus.take_stall_cycles();
+            if stall == 0 { break; }
+            self.bus.tick(stall);
+            total += stall;
+        }
+        Ok(total)
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.bus.double_speed
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    // for loading states that only record IME, so an EI still waiting to take effect is dropped
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+        self.ime_pending = false;
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn set_stopped(&mut self, stopped: bool) {
+        self.stopped = stopped;
+    }
+
+    // whether the next step() runs an instruction, rather than dispatching an interrupt or
+    // idling in HALT
+    pub fn executes_next(&self) -> bool {
+        if self.stopped && !self.bus.button_held() {
+            return false;
+        }
+        let pending = self.bus.memory[0xFFFF] & self.bus.memory[0xFF0F] & 0x1F != 0;
+        if pending { !self.ime } else { !self.halted }
+    }
+
+    // dispatches the highest priority pending interrupt, returns whether one was taken
+    fn service_interrupt(&mut self) -> bool {
+        let pending = self.bus.memory[0xFFFF] & self.bus.memory[0xFF0F] & 0x1F;
+        if pending == 0 {
+            return false;
+        }
+
+        // a pending interrupt ends HALT even when IME is off
+        self.halted = false;
+        if !self.ime {
+            return false;
+        }
+
+        let bit = pending.trailing_zeros() as u16;
+        self.ime = false;
+        self.bus.memory[0xFF0F] &= !(1 << bit);
+        self.push(self.pc);
+        self.pc = 0x40 + bit * 8;
+
+        true
     }
 
     // executes an instruction decoded by the step() method
-    pub fn execute(&mut self, instruction: Instruction) -> u16 {
-        match instruction {
+    pub fn execute(&mut self, instruction: Instruction) -> Result<u16> {
+        Ok(match instruction {
             Instruction::JP(test) => {
                 let jump_condition: bool = match test {
                     JumpTest::Always => true,
@@ -840,6 +1186,7 @@ impl CPU {
                     JumpTest::Zero => self.registers.f.zero,
                     JumpTest::NotZero => !self.registers.f.zero
                 };
+                self.branch_taken = jump_condition;
                 self.jp(jump_condition)
             }
             Instruction::JR(test) => {
@@ -850,12 +1197,25 @@ impl CPU {
                     JumpTest::Zero => self.registers.f.zero,
                     JumpTest::NotZero => !self.registers.f.zero
                 };
+                self.branch_taken = jump_condition;
                 self.jr(jump_condition)
             }
             Instruction::JPHL => self.jphl(),
-            Instruction::DI => { self.pc.wrapping_add(1) },
+            Instruction::DI => {
+                self.ime = false;
+                self.ime_pending = false;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::EI => {
+                self.ime_pending = true;
+                self.pc.wrapping_add(1)
+            }
+            Instruction::RETI => {
+                self.ime = true;
+                self.pop().1
+            }
 
-            Instruction::LD(load_type) => self.ld(load_type),
+            Instruction::LD(load_type) => self.ld(load_type)?,
 
             Instruction::CALL(test) => {
                 let jump_condition: bool = match test {
@@ -865,6 +1225,7 @@ impl CPU {
                     JumpTest::Zero => self.registers.f.zero,
                     JumpTest::NotZero => !self.registers.f.zero
                 };
+                self.branch_taken = jump_condition;
                 self.call(jump_condition)
             }
 
@@ -876,9 +1237,15 @@ impl CPU {
                     JumpTest::Zero => self.registers.f.zero,
                     JumpTest::NotZero => !self.registers.f.zero
                 };
+                self.branch_taken = jump_condition;
                 self.ret(jump_condition)
             }
 
+            Instruction::RST(vector) => {
+                self.push(self.pc.wrapping_add(1));
+                vector as u16
+            }
+
             Instruction::PUSH(target) => {
                 let value = match target {
                     StackTarget::AF => {
@@ -919,73 +1286,89 @@ impl CPU {
             Instruction::Nop => self.nop(),
             Instruction::Cpl => self.cpl(),
             Instruction::Ccf => self.ccf(),
+            Instruction::Daa => self.daa(),
             Instruction::Scf => self.scf(),
             Instruction::Rlca => self.rlca(),
             Instruction::Rla => self.rla(),
             Instruction::Rrca => self.rrca(),
             Instruction::Rra => self.rra(),
-            Instruction::HALT => { 
-                println!("halt");
+            Instruction::HALT => {
+                self.halted = true;
                 self.pc.wrapping_add(1)
             },
-            Instruction::Bit0(target) => self.bit(target, 0),
-            Instruction::Bit1(target) => self.bit(target, 1),
-            Instruction::Bit2(target) => self.bit(target, 2),
-            Instruction::Bit3(target) => self.bit(target, 3),
-            Instruction::Bit4(target) => self.bit(target, 4),
-            Instruction::Bit5(target) => self.bit(target, 5),
-            Instruction::Bit6(target) => self.bit(target, 6),
-            Instruction::Bit7(target) => self.bit(target, 7),
-            Instruction::Res0(target) => self.res(target, 0),
-            Instruction::Res1(target) => self.res(target, 1),
-            Instruction::Res2(target) => self.res(target, 2),
-            Instruction::Res3(target) => self.res(target, 3),
-            Instruction::Res4(target) => self.res(target, 4),
-            Instruction::Res5(target) => self.res(target, 5),
-            Instruction::Res6(target) => self.res(target, 6),
-            Instruction::Res7(target) => self.res(target, 7),
-            Instruction::Set0(target) => self.set(target, 0),
-            Instruction::Set1(target) => self.set(target, 1),
-            Instruction::Set2(target) => self.set(target, 2),
-            Instruction::Set3(target) => self.set(target, 3),
-            Instruction::Set4(target) => self.set(target, 4),
-            Instruction::Set5(target) => self.set(target, 5),
-            Instruction::Set6(target) => self.set(target, 6),
-            Instruction::Set7(target) => self.set(target, 7),
-            Instruction::Srl(target) => self.srl(target),
-            Instruction::Swap(target) => self.swap(target),
-            Instruction::Sra(target) => self.sra(target),
-            Instruction::Sla(target) => self.sla(target),
-            Instruction::Rr(target) => self.rr(target),
-            Instruction::Rl(target) => self.rl(target),
-            Instruction::Rrc(target) => self.rrc(target),
-            Instruction::Rlc(target) => self.rlc(target),
-            Instruction::Inc(target) => self.inc(target),
-            Instruction::Dec(target) => self.dec(target),
-            Instruction::Add(target) => self.add(target),
+            Instruction::STOP => {
+                // with KEY1 armed this is the CGB speed switch, otherwise the CPU sleeps until a button press
+                if !self.bus.speed_switch() {
+                    self.stopped = true;
+                }
+                self.pc.wrapping_add(2)
+            },
+            Instruction::Bit0(target) => self.bit(target, 0)?,
+            Instruction::Bit1(target) => self.bit(target, 1)?,
+            Instruction::Bit2(target) => self.bit(target, 2)?,
+            Instruction::Bit3(target) => self.bit(target, 3)?,
+            Instruction::Bit4(target) => self.bit(target, 4)?,
+            Instruction::Bit5(target) => self.bit(target, 5)?,
+            Instruction::Bit6(target) => self.bit(target, 6)?,
+            Instruction::Bit7(target) => self.bit(target, 7)?,
+            Instruction::Res0(target) => self.res(target, 0)?,
+            Instruction::Res1(target) => self.res(target, 1)?,
+            Instruction::Res2(target) => self.res(target, 2)?,
+            Instruction::Res3(target) => self.res(target, 3)?,
+            Instruction::Res4(target) => self.res(target, 4)?,
+            Instruction::Res5(target) => self.res(target, 5)?,
+            Instruction::Res6(target) => self.res(target, 6)?,
+            Instruction::Res7(target) => self.res(target, 7)?,
+            Instruction::Set0(target) => self.set(target, 0)?,
+            Instruction::Set1(target) => self.set(target, 1)?,
+            Instruction::Set2(target) => self.set(target, 2)?,
+            Instruction::Set3(target) => self.set(target, 3)?,
+            Instruction::Set4(target) => self.set(target, 4)?,
+            Instruction::Set5(target) => self.set(target, 5)?,
+            Instruction::Set6(target) => self.set(target, 6)?,
+            Instruction::Set7(target) => self.set(target, 7)?,
+            Instruction::Srl(target) => self.srl(target)?,
+            Instruction::Swap(target) => self.swap(target)?,
+            Instruction::Sra(target) => self.sra(target)?,
+            Instruction::Sla(target) => self.sla(target)?,
+            Instruction::Rr(target) => self.rr(target)?,
+            Instruction::Rl(target) => self.rl(target)?,
+            Instruction::Rrc(target) => self.rrc(target)?,
+            Instruction::Rlc(target) => self.rlc(target)?,
+            Instruction::Inc(target) => self.inc(target)?,
+            Instruction::Dec(target) => self.dec(target)?,
+            Instruction::Add(target) => self.add(target)?,
             Instruction::AddHL(target) => self.addhl(target),
-            Instruction::Adc(target) => self.adc(target),
-            Instruction::Sub(target) => self.sub(target),
-            Instruction::Sbc(target) => self.sbc(target),
-            Instruction::Or(target) => self.or(target),
-            Instruction::And(target) => self.and(target),
-            Instruction::Xor(target) => self.xor(target),
-            Instruction::Cp(target) => self.cp(target),
-            _ => { /* Add more instructions */ self.pc }
-        }
+            Instruction::AddSP => {
+                self.sp = self.sp_offset();
+                self.pc.wrapping_add(2)
+            }
+            Instruction::LDHLSP => {
+                let value = self.sp_offset();
+                self.registers.set_hl(value);
+                self.pc.wrapping_add(2)
+            }
+            Instruction::Adc(target) => self.adc(target)?,
+            Instruction::Sub(target) => self.sub(target)?,
+            Instruction::Sbc(target) => self.sbc(target)?,
+            Instruction::Or(target) => self.or(target)?,
+            Instruction::And(target) => self.and(target)?,
+            Instruction::Xor(target) => self.xor(target)?,
+            Instruction::Cp(target) => self.cp(target)?,
+        })
 
     }
 
-    fn reg8_lookup(&mut self, register: Reg8) -> &mut u8 {
+    fn reg8_lookup(&mut self, register: Reg8) -> Result<&mut u8> {
         match register {
-            Reg8::B => &mut self.registers.b,
-            Reg8::C => &mut self.registers.c,
-            Reg8::D => &mut self.registers.d,
-            Reg8::E => &mut self.registers.e,
-            Reg8::H => &mut self.registers.h,
-            Reg8::L => &mut self.registers.l,
-            Reg8::A => &mut self.registers.a,
-            _ => { panic!("D8 lookup"); }
+            Reg8::B => Ok(&mut self.registers.b),
+            Reg8::C => Ok(&mut self.registers.c),
+            Reg8::D => Ok(&mut self.registers.d),
+            Reg8::E => Ok(&mut self.registers.e),
+            Reg8::H => Ok(&mut self.registers.h),
+            Reg8::L => Ok(&mut self.registers.l),
+            Reg8::A => Ok(&mut self.registers.a),
+            _ => Err(EmulationError::InvalidOperand { pc: self.pc })
         }
     }
 
@@ -999,20 +1382,27 @@ impl CPU {
         }
     }
 
-    fn ref_from_target(&mut self, target: Target) -> Option<&mut u8> {
+    // the 8-bit operand of an ALU, INC/DEC or CB op. memory operands go through the bus like any
+    // other access, so the op is a plain read followed by a write
+    fn read_target(&mut self, target: Target) -> Result<u8> {
         match target {
-            Target::Reg8(r) => {
-                Some(self.reg8_lookup(r))
-            }
+            Target::Reg8(r) => self.reg8_lookup(r).copied(),
+            Target::Reg16Indirect(r) => Ok(self.bus.read_byte(self.reg16_lookup(r))),
+            Target::Value => Ok(self.bus.read_byte(self.pc.wrapping_add(1))),
+            _ => Err(EmulationError::InvalidOperand { pc: self.pc })
+        }
+    }
+
+    fn write_target(&mut self, target: Target, value: u8) -> Result<()> {
+        match target {
+            Target::Reg8(r) => *self.reg8_lookup(r)? = value,
             Target::Reg16Indirect(r) => {
-                let addr: u16 = self.reg16_lookup(r);
-                Some(self.bus.get_ref(addr))
+                let addr = self.reg16_lookup(r);
+                self.bus.write_byte(addr, value);
             }
-            Target::Value => {
-                Some(&mut self.bus.memory[self.pc.wrapping_add(1) as usize])
-            }
-            _ => { None }
+            _ => return Err(EmulationError::InvalidOperand { pc: self.pc })
         }
+        Ok(())
     }
 
     fn call(&mut self, jump: bool) -> u16 {
@@ -1053,7 +1443,7 @@ impl CPU {
         (self.pc.wrapping_add(1), (msb << 8) | lsb)
     }
 
-    fn ld(&mut self, load_type: LoadType) -> u16 {
+    fn ld(&mut self, load_type: LoadType) -> Result<u16> {
         match load_type {
             LoadType::Word(target, source) => {
                 let source_value: u16 = match source {
@@ -1062,7 +1452,9 @@ impl CPU {
                         let msb: u16 = self.bus.read_byte(self.pc.wrapping_add(2)) as u16;
                         (msb << 8) | lsb
                     }
-                    _ => { panic!("Unknown load source!"); }
+                    Reg16::SP => self.sp,
+                    Reg16::HL => self.registers.get_hl(),
+                    _ => { return Err(EmulationError::InvalidOperand { pc: self.pc }); }
                 };
                 match target {
                     Reg16::BC => {
@@ -1077,9 +1469,19 @@ impl CPU {
                     Reg16::SP => {
                         self.sp = source_value;
                     }
-                    _ => { panic!("Unknown load target!"); }
+                    Reg16::I16 => {
+                        let lsb: u16 = self.bus.read_byte(self.pc.wrapping_add(1)) as u16;
+                        let msb: u16 = self.bus.read_byte(self.pc.wrapping_add(2)) as u16;
+                        let addr = (msb << 8) | lsb;
+                        self.bus.write_byte(addr, (source_value & 0xFF) as u8);
+                        self.bus.write_byte(addr.wrapping_add(1), (source_value >> 8) as u8);
+                    }
+                    _ => { return Err(EmulationError::InvalidOperand { pc: self.pc }); }
                 }
-                self.pc.wrapping_add(3)
+                Ok(match source {
+                    Reg16::HL => self.pc.wrapping_add(1),
+                    _ => self.pc.wrapping_add(3)
+                })
             }
             LoadType::Byte(target,source) => {
                 let source_value: u8 = match source {
@@ -1159,9 +1561,9 @@ impl CPU {
                         let addr = 0xFF00_u16 + (self.registers.c as u16);
                         self.bus.write_byte(addr, source_value);
                     }
-                    _ => { panic!("D8 Target"); }
+                    _ => { return Err(EmulationError::InvalidOperand { pc: self.pc }); }
                 }
-                match (target,source) {
+                Ok(match (target,source) {
                     (_,Reg8::D8)   => self.pc.wrapping_add(2),
                     (Reg8::D8I,_)  => self.pc.wrapping_add(2),
                     (_,Reg8::D8I)  => self.pc.wrapping_add(2),
@@ -1170,8 +1572,7 @@ impl CPU {
                     (_,Reg8::D16I) => self.pc.wrapping_add(3),
 
                     (_,_)          => self.pc.wrapping_add(1)
-                }
-                
+                })
             }
         }
     }
@@ -1225,16 +1626,27 @@ impl CPU {
         self.pc.wrapping_add(1)
     }
     
-    fn add(&mut self, target: Target) -> u16 {
+    // SP plus the signed byte after the opcode, for ADD SP,e8 and LD HL,SP+e8. the flags come from
+    // adding the byte to SP's low byte as if it were unsigned
+    fn sp_offset(&mut self) -> u16 {
+        let byte = self.bus.read_byte(self.pc.wrapping_add(1));
+
+        self.registers.f.zero = false;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = (self.sp & 0xF) + (byte as u16 & 0xF) > 0xF;
+        self.registers.f.carry = (self.sp & 0xFF) + byte as u16 > 0xFF;
+
+        self.sp.wrapping_add(byte as i8 as u16)
+    }
+
+    fn add(&mut self, target: Target) -> Result<u16> {
 
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
         
         let (result, did_overflow) = self.registers.a.overflowing_add(byte);
 
@@ -1245,18 +1657,16 @@ impl CPU {
         
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn adc(&mut self, target: Target) -> u16 {
+    fn adc(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let carry = if self.registers.f.carry { 1 } else { 0 };
 
@@ -1270,18 +1680,16 @@ impl CPU {
 
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn sub(&mut self, target: Target) -> u16 {
+    fn sub(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let (result, did_overflow) = self.registers.a.overflowing_sub(byte);
 
@@ -1292,18 +1700,16 @@ impl CPU {
 
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn sbc(&mut self, target: Target) -> u16 {
+    fn sbc(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let carry = if self.registers.f.carry { 1 } else { 0 };
         let (result, overflow1) = self.registers.a.overflowing_sub(byte);
@@ -1316,18 +1722,16 @@ impl CPU {
 
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn or(&mut self, target: Target) -> u16 {
+    fn or(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let result = self.registers.a | byte;
         
@@ -1336,18 +1740,16 @@ impl CPU {
 
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn and(&mut self, target: Target) -> u16 {
+    fn and(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let result = self.registers.a & byte;
 
@@ -1358,18 +1760,16 @@ impl CPU {
 
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn xor(&mut self, target: Target) -> u16 {
+    fn xor(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let result = self.registers.a ^ byte;
 
@@ -1378,18 +1778,16 @@ impl CPU {
 
         self.registers.a = result;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
-    fn cp(&mut self, target: Target) -> u16 {
+    fn cp(&mut self, target: Target) -> Result<u16> {
         let pc_update: u16 = match target {
             Target::Value => 2,
             _ => 1
         };
 
-        let byte = {
-            *self.ref_from_target(target).unwrap()
-        };
+        let byte = self.read_target(target)?;
 
         let (result, did_overflow) = self.registers.a.overflowing_sub(byte);
 
@@ -1398,7 +1796,7 @@ impl CPU {
         self.registers.f.carry = did_overflow;
         self.registers.f.half_carry = ((self.registers.a & 0xF) as i8) - ((byte & 0xF) as i8) < 0;
 
-        self.pc.wrapping_add(pc_update)
+        Ok(self.pc.wrapping_add(pc_update))
     }
 
     fn cpl(&mut self) -> u16 {
@@ -1420,6 +1818,32 @@ impl CPU {
         self.pc.wrapping_add(1)
     }
 
+    // adjusts A back into BCD after an addition or subtraction of two BCD numbers
+    fn daa(&mut self) -> u16 {
+        let mut adjust = 0;
+        let mut carry = self.registers.f.carry;
+
+        if self.registers.f.subtract {
+            if self.registers.f.half_carry { adjust |= 0x06; }
+            if carry { adjust |= 0x60; }
+            self.registers.a = self.registers.a.wrapping_sub(adjust);
+        } else {
+            if self.registers.f.half_carry || self.registers.a & 0xF > 0x9 { adjust |= 0x06; }
+            if carry || self.registers.a > 0x99 {
+                adjust |= 0x60;
+                carry = true;
+            }
+            self.registers.a = self.registers.a.wrapping_add(adjust);
+        }
+
+        // subtract unmodified
+        self.registers.f.zero = self.registers.a == 0;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = carry;
+
+        self.pc.wrapping_add(1)
+    }
+
     fn scf(&mut self) -> u16 {
         // zero flag unmodified
         self.registers.f.subtract = false;
@@ -1470,7 +1894,7 @@ impl CPU {
         let bit0 = self.registers.a & 0x1;
         let carry_bit: u8 = if self.registers.f.carry { 1 } else { 0 };
         self.registers.a >>= 1;
-        self.registers.a |= (carry_bit << 7);
+        self.registers.a |= carry_bit << 7;
 
         self.registers.f.clear_all();
         self.registers.f.carry = bit0 != 0;
@@ -1478,7 +1902,7 @@ impl CPU {
         self.pc.wrapping_add(1)
     }
 
-    pub fn inc(&mut self, target: Target) -> u16 {
+    pub fn inc(&mut self, target: Target) -> Result<u16> {
         match target {
             Target::Reg16(t) => {
                 match t {
@@ -1497,229 +1921,204 @@ impl CPU {
                     Reg16::SP => {
                         self.sp = self.sp.wrapping_add(1);
                     }
-                    _ => { panic!("INC unknown reg16 target"); }
+                    _ => { return Err(EmulationError::InvalidOperand { pc: self.pc }); }
                 }
             }
             _ => {
-                let byte = self.ref_from_target(target).unwrap();
-                let prior = *byte;
-                *byte = byte.wrapping_add(1);
+                let mut byte = self.read_target(target)?;
+                let prior = byte;
+                byte = byte.wrapping_add(1);
+                self.write_target(target, byte)?;
 
-                self.registers.f.zero = *byte == 0;
+                self.registers.f.zero = byte == 0;
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = (prior & 0xF) + 1 > 0xF;
             }
         }
         
-        self.pc.wrapping_add(1)
+        Ok(self.pc.wrapping_add(1))
     }
 
-    fn dec(&mut self, target: Target) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
+    fn dec(&mut self, target: Target) -> Result<u16> {
+        if let Target::Reg16(t) = target {
+            match t {
+                Reg16::BC => {
+                    let value = self.registers.get_bc();
+                    self.registers.set_bc(value.wrapping_sub(1));
+                }
+                Reg16::DE => {
+                    let value = self.registers.get_de();
+                    self.registers.set_de(value.wrapping_sub(1));
+                }
+                Reg16::HL => {
+                    let value = self.registers.get_hl();
+                    self.registers.set_hl(value.wrapping_sub(1));
+                }
+                Reg16::SP => {
+                    self.sp = self.sp.wrapping_sub(1);
+                }
+                _ => { return Err(EmulationError::InvalidOperand { pc: self.pc }); }
+            }
+            return Ok(self.pc.wrapping_add(1));
+        }
 
-        if let Some(byte) = byte_ref {
-            let prior = *byte;
-            *byte = byte.wrapping_sub(1);
+        let mut byte = self.read_target(target)?;
+        let prior = byte;
+        byte = byte.wrapping_sub(1);
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.half_carry = ((prior & 0xF) as i8) - 1_i8 < 0;
-            self.registers.f.subtract = true;
-            
-            self.pc.wrapping_add(1)
-        } else {
-            panic!("DEC unknown target");
-        }
+        self.registers.f.zero = byte == 0;
+        self.registers.f.half_carry = ((prior & 0xF) as i8) - 1_i8 < 0;
+        self.registers.f.subtract = true;
+        
+        Ok(self.pc.wrapping_add(1))
     }
 
-    fn rlc(&mut self, target: Target) -> u16 {
-        let carry: u8 = if self.registers.f.carry { 1 } else { 0 };
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit7: u8 = if (*byte & 0x80) > 0 { 1 } else { 0 };
-            *byte = byte.rotate_left(1);
+    fn rlc(&mut self, target: Target) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit7: u8 = if (byte & 0x80) > 0 { 1 } else { 0 };
+        byte = byte.rotate_left(1);
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit7 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit7 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("RLC unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn rrc(&mut self, target: Target) -> u16 {
-        let carry: u8 = if self.registers.f.carry { 1 } else { 0 };
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit0: u8 = *byte & 0x1;
-            *byte = byte.rotate_right(1);
+    fn rrc(&mut self, target: Target) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit0: u8 = byte & 0x1;
+        byte = byte.rotate_right(1);
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit0 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit0 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("RRC unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn rl(&mut self, target: Target) -> u16 {
+    fn rl(&mut self, target: Target) -> Result<u16> {
         let carry: u8 = if self.registers.f.carry { 1 } else { 0 };
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit7: u8 = if (*byte & 0x80) > 0 { 1 } else { 0 };
-            *byte <<= 1;
-            *byte |= carry;
+        let mut byte = self.read_target(target)?;
+        let bit7: u8 = if (byte & 0x80) > 0 { 1 } else { 0 };
+        byte <<= 1;
+        byte |= carry;
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit7 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit7 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("RL unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn rr(&mut self, target: Target) -> u16 {
+    fn rr(&mut self, target: Target) -> Result<u16> {
         let carry: u8 = if self.registers.f.carry { 1 } else { 0 };
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit0: u8 = *byte & 0x1;
-            *byte >>= 1;
-            *byte |= carry << 7;
+        let mut byte = self.read_target(target)?;
+        let bit0: u8 = byte & 0x1;
+        byte >>= 1;
+        byte |= carry << 7;
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit0 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit0 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("RR unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn sla(&mut self, target: Target) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit7: u8 = (*byte & 0x80) >> 7;
-            *byte <<= 1;
+    fn sla(&mut self, target: Target) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit7: u8 = (byte & 0x80) >> 7;
+        byte <<= 1;
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit7 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit7 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("SLA unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn sra(&mut self, target: Target) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit7: u8 = *byte & 0x80;
-            let bit0: u8 = *byte & 0x1;
-            *byte >>= 1;
-            *byte |= bit7;
+    fn sra(&mut self, target: Target) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit7: u8 = byte & 0x80;
+        let bit0: u8 = byte & 0x1;
+        byte >>= 1;
+        byte |= bit7;
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit0 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit0 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("SRA unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn swap(&mut self, target: Target) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
+    fn swap(&mut self, target: Target) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
 
-            *byte = (*byte & 0xF) << 4 | (*byte & 0xF0) >> 4;
+        byte = (byte & 0xF) << 4 | (byte & 0xF0) >> 4;
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = false;
-            
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("SWAP unknown target");
-        }
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = false;
+        
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn srl(&mut self, target: Target) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-
-        if let Some(byte) = byte_ref {
-            let bit0: u8 = *byte & 0x1;
-            *byte >>= 1;
+    fn srl(&mut self, target: Target) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit0: u8 = byte & 0x1;
+        byte >>= 1;
+        self.write_target(target, byte)?;
 
-            self.registers.f.zero = *byte == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = false;
-            self.registers.f.carry = bit0 != 0;
+        self.registers.f.zero = byte == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = bit0 != 0;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("SRL unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn bit(&mut self, target: Target, bit: u8) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-        if let Some(byte) = byte_ref {
-            let bit = if bit > 0 { 1 << bit } else { 1 };
+    fn bit(&mut self, target: Target, bit: u8) -> Result<u16> {
+        let byte = self.read_target(target)?;
+        let bit = if bit > 0 { 1 << bit } else { 1 };
 
-            self.registers.f.zero = (*byte & bit) == 0;
-            self.registers.f.subtract = false;
-            self.registers.f.half_carry = true;
+        self.registers.f.zero = (byte & bit) == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = true;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("BIT{bit} unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn res(&mut self, target: Target, bit: u8) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-        if let Some(byte) = byte_ref {
-            let bit = if bit > 0 { 1 << bit } else { 1 };
-            *byte &= !bit;
+    fn res(&mut self, target: Target, bit: u8) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit = if bit > 0 { 1 << bit } else { 1 };
+        byte &= !bit;
+        self.write_target(target, byte)?;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("RES{bit} unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
-    fn set(&mut self, target: Target, bit: u8) -> u16 {
-        let byte_ref: Option<&mut u8> = self.ref_from_target(target);
-        if let Some(byte) = byte_ref {
-            let bit = if bit > 0 { 1 << bit } else { 1 };
-            *byte |= bit;
+    fn set(&mut self, target: Target, bit: u8) -> Result<u16> {
+        let mut byte = self.read_target(target)?;
+        let bit = if bit > 0 { 1 << bit } else { 1 };
+        byte |= bit;
+        self.write_target(target, byte)?;
 
-            self.pc.wrapping_add(2)
-        } else {
-            panic!("SET{bit} unknown target");
-        }
+        Ok(self.pc.wrapping_add(2))
     }
 
 }
\ No newline at end of file
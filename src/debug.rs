@@ -0,0 +1,311 @@
+// The `debug` command: a line-based REPL over gb_emulator::debugger. Addresses and values are
+// hex ($ and 0x prefixes are fine), counts are decimal. With a symbol file loaded, addresses can
+// also be labels (Main.loop, Main.loop+3) and are shown as labels wherever there's one. An empty
+// line repeats the last command, Ctrl-C stops a running continue/next/finish.
+
+use std::ffi::c_int;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use gb_emulator::debugger::{Access, Breakpoint, Condition, Debugger, FrameKind, Line, Register, Stop, Watchpoint};
+use gb_emulator::disasm::Syntax;
+use gb_emulator::symbols::Symbols;
+
+const HELP: &str = "\
+  s, step [n]              run one instruction (or n)
+  n, next                  step over calls, rsts and interrupts
+  finish                   run until the current frame returns
+  c, continue              run until a breakpoint or Ctrl-C
+  b, break <addr>          break at an address or label, or <bank>:<addr> for one ROM bank only
+  d, delete <n>            remove breakpoint n
+  breaks                   list breakpoints
+  watch <r|w|rw> <addr>[-<end>] [<value>[/<mask>]]
+                           stop on reads and/or writes of an address range, only of bytes that
+                           match the value under the mask (ff40 00/80: written with bit 7 clear)
+  unwatch <n>              remove watchpoint n
+  watches                  list watchpoints
+  r, regs                  show the registers
+  set <reg> <value>        set a register (a, f, b... af, bc, de, hl, sp, pc)
+  x <addr> [len]           dump memory
+  w, write <addr> <byte>.. write memory through the bus
+  l, list [addr]           disassemble around PC, or from an address
+  bt, backtrace            show the call stack
+  q, quit";
+
+// what Ctrl-C sets while the emulator runs
+static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+const SIGINT: c_int = 2;
+
+unsafe extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+extern "C" fn on_interrupt(_: c_int) {
+    if let Some(flag) = INTERRUPT.get() {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("not a hex number: {text}"))
+}
+
+fn parse_count(text: Option<&str>, default: usize) -> Result<usize, String> {
+    text.map_or(Ok(default), |t| t.parse().map_err(|_| format!("not a number: {t}")))
+}
+
+// a label, or a hex address
+fn parse_address(symbols: &Symbols, text: &str) -> Result<u16, String> {
+    match symbols.resolve(text) {
+        Some((_, addr)) => Ok(addr),
+        None => parse_hex(text).map_err(|_| format!("not an address or label: {text}")),
+    }
+}
+
+pub fn parse_breakpoint(symbols: &Symbols, text: &str) -> Result<Breakpoint, String> {
+    if let Some(breakpoint) = Breakpoint::at_label(symbols, text) {
+        return Ok(breakpoint);
+    }
+    match text.split_once(':') {
+        Some((bank, addr)) => Ok(Breakpoint { bank: Some(parse_hex(bank)? as usize), addr: parse_hex(addr)? }),
+        None => Ok(Breakpoint { bank: None, addr: parse_address(symbols, text)? }),
+    }
+}
+
+fn parse_watchpoint(symbols: &Symbols, kind: &str, range: &str, condition: Option<&str>) -> Result<Watchpoint, String> {
+    let (read, write) = match kind {
+        "r" => (true, false),
+        "w" => (false, true),
+        "rw" => (true, true),
+        _ => return Err(format!("watch kind is r, w or rw, not {kind}")),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_address(symbols, start)?, parse_address(symbols, end)?),
+        None => (parse_address(symbols, range)?, parse_address(symbols, range)?),
+    };
+    if end < start {
+        return Err(format!("empty range {range}"));
+    }
+    let byte = |text: &str| parse_hex(text).and_then(|v| u8::try_from(v).map_err(|_| format!("{text} is more than a byte")));
+    let condition = match condition {
+        Some(text) => Some(match text.split_once('/') {
+            Some((value, mask)) => Condition { mask: byte(mask)?, value: byte(value)? & byte(mask)? },
+            None => Condition { mask: 0xFF, value: byte(text)? },
+        }),
+        None => None,
+    };
+    Ok(Watchpoint { start, end, read, write, condition })
+}
+
+fn location(bank: Option<usize>, addr: u16) -> String {
+    match bank {
+        Some(bank) => format!("{bank:02X}:{addr:04X}"),
+        None => format!("--:{addr:04X}"),
+    }
+}
+
+// the label an address falls under, or where it is
+fn describe(debugger: &Debugger, addr: u16) -> String {
+    debugger.symbol(addr).unwrap_or_else(|| location(debugger.bank(addr), addr))
+}
+
+fn print_line(debugger: &Debugger, line: &Line, current: bool) {
+    if let Some(label) = debugger.symbols.name(line.bank, line.addr) {
+        println!("{label}:");
+    }
+    let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{b:02X}")).collect();
+    println!("{} {}  {:<9} {}", if current { "=>" } else { "  " }, location(line.bank, line.addr),
+        bytes.join(" "), line.instruction.text(Syntax::Rgbds, &|addr| debugger.symbol(addr)));
+}
+
+fn print_registers(debugger: &Debugger) {
+    let mut registers = Vec::new();
+    for r in [Register::AF, Register::BC, Register::DE, Register::HL, Register::SP, Register::PC] {
+        registers.push(format!("{}={:04X}", r.name(), debugger.register(r)));
+    }
+    let f = debugger.register(Register::F);
+    let flags: String = [(0x80, 'z'), (0x40, 'n'), (0x20, 'h'), (0x10, 'c')].iter()
+        .map(|&(bit, name)| if f & bit != 0 { name } else { '-' })
+        .collect();
+    let cpu = &debugger.gb.cpu;
+    println!("{}  {flags}  ime={} halted={}", registers.join(" "), cpu.ime() as u8, cpu.halted() as u8);
+}
+
+fn print_location(debugger: &Debugger) {
+    let pc = debugger.pc();
+    print_line(debugger, &debugger.disassemble_around(pc, 0, 1)[0], true);
+}
+
+fn print_stop(debugger: &Debugger, stop: Stop) {
+    match stop {
+        Stop::Breakpoint(index) => println!("breakpoint {index} at {}", debugger.breakpoints[index].unwrap()),
+        Stop::Watchpoint { hit, pc } => {
+            let access = match hit.access {
+                Access::Read => "read of",
+                Access::Write => "write of",
+            };
+            println!("watchpoint {} ({}): {access} ${:02X} at {:04X} by {}", hit.index,
+                debugger.watchpoints()[hit.index].unwrap(), hit.value, hit.addr, describe(debugger, pc));
+        }
+        Stop::Interrupted => println!("interrupted"),
+        Stop::Step | Stop::Finished => {}
+    }
+    print_location(debugger);
+}
+
+fn dump(debugger: &Debugger, addr: u16, len: usize) {
+    let data = debugger.read_memory(addr, len);
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("{b:02X}")).collect();
+        let text: String = chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+        println!("{:04X}  {:<47}  {text}", addr.wrapping_add(row as u16 * 16), bytes.join(" "));
+    }
+}
+
+fn stopped(debugger: &Debugger, e: gb_emulator::EmulationError) -> String {
+    match e.pc() {
+        Some(pc) => format!("emulation stopped: {e} ({})", describe(debugger, pc)),
+        None => format!("emulation stopped: {e}"),
+    }
+}
+
+// runs one REPL command, returns false to quit
+fn command(debugger: &mut Debugger, words: &[&str]) -> Result<bool, String> {
+    match words {
+        ["s" | "step", rest @ ..] => {
+            let mut stop = Stop::Step;
+            for _ in 0..parse_count(rest.first().copied(), 1)? {
+                stop = debugger.step().map_err(|e| stopped(debugger, e))?;
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            print_stop(debugger, stop);
+        }
+        ["n" | "next"] => {
+            let stop = debugger.step_over().map_err(|e| stopped(debugger, e))?;
+            print_stop(debugger, stop);
+        }
+        ["finish"] => {
+            if debugger.backtrace().is_empty() {
+                return Err("no frame to finish".into());
+            }
+            let stop = debugger.finish().map_err(|e| stopped(debugger, e))?;
+            print_stop(debugger, stop);
+        }
+        ["c" | "continue"] => {
+            let stop = debugger.cont().map_err(|e| stopped(debugger, e))?;
+            print_stop(debugger, stop);
+        }
+        ["b" | "break", at] => {
+            let breakpoint = parse_breakpoint(&debugger.symbols, at)?;
+            let index = debugger.add_breakpoint(breakpoint);
+            println!("breakpoint {index} at {breakpoint}");
+        }
+        ["d" | "delete", index] => {
+            let index = parse_count(Some(index), 0)?;
+            debugger.remove_breakpoint(index).ok_or(format!("no breakpoint {index}"))?;
+        }
+        ["breaks"] => {
+            for (index, breakpoint) in debugger.breakpoints.iter().enumerate() {
+                if let Some(breakpoint) = breakpoint {
+                    println!("{index}: {breakpoint}");
+                }
+            }
+        }
+        ["watch", kind, range, condition @ ..] if condition.len() <= 1 => {
+            let watchpoint = parse_watchpoint(&debugger.symbols, kind, range, condition.first().copied())?;
+            let index = debugger.add_watchpoint(watchpoint);
+            println!("watchpoint {index}: {watchpoint}");
+        }
+        ["unwatch", index] => {
+            let index = parse_count(Some(index), 0)?;
+            debugger.remove_watchpoint(index).ok_or(format!("no watchpoint {index}"))?;
+        }
+        ["watches"] => {
+            for (index, watchpoint) in debugger.watchpoints().iter().enumerate() {
+                if let Some(watchpoint) = watchpoint {
+                    println!("{index}: {watchpoint}");
+                }
+            }
+        }
+        ["r" | "regs"] => print_registers(debugger),
+        ["set", name, value] => {
+            let register = Register::from_name(name).ok_or(format!("no register {name}"))?;
+            let value = parse_hex(value)?;
+            if !register.is_16bit() && value > 0xFF {
+                return Err(format!("{name} is 8 bits"));
+            }
+            debugger.set_register(register, value);
+        }
+        ["x", addr, rest @ ..] => {
+            dump(debugger, parse_address(&debugger.symbols, addr)?, parse_count(rest.first().copied(), 64)?)
+        }
+        ["w" | "write", addr, bytes @ ..] if !bytes.is_empty() => {
+            let data = bytes.iter().map(|b| parse_hex(b).map(|v| v as u8)).collect::<Result<Vec<_>, _>>()?;
+            debugger.write_memory(parse_address(&debugger.symbols, addr)?, &data);
+        }
+        ["l" | "list"] => {
+            let pc = debugger.pc();
+            for line in debugger.disassemble_around(pc, 5, 6) {
+                print_line(debugger, &line, line.addr == pc);
+            }
+        }
+        ["l" | "list", addr] => {
+            let pc = debugger.pc();
+            for line in debugger.disassemble_around(parse_address(&debugger.symbols, addr)?, 0, 10) {
+                print_line(debugger, &line, line.addr == pc);
+            }
+        }
+        ["bt" | "backtrace"] => {
+            println!("#0  {}", describe(debugger, debugger.pc()));
+            for (depth, frame) in debugger.backtrace().iter().rev().enumerate() {
+                let how = match frame.kind {
+                    FrameKind::Call => "call",
+                    FrameKind::Rst => "rst",
+                    FrameKind::Interrupt => "interrupt",
+                };
+                let target = debugger.symbols.describe(frame.bank, frame.target)
+                    .unwrap_or_else(|| location(frame.bank, frame.target));
+                println!("#{}  {target} {how} from {}, returns to {}", depth + 1,
+                    describe(debugger, frame.call_site), describe(debugger, frame.return_address));
+            }
+        }
+        ["q" | "quit"] => return Ok(false),
+        ["h" | "help"] => println!("{HELP}"),
+        [other, ..] => return Err(format!("unknown command {other}, try help")),
+        [] => {}
+    }
+    Ok(true)
+}
+
+pub fn repl(debugger: &mut Debugger) -> io::Result<()> {
+    if INTERRUPT.set(debugger.interrupt.clone()).is_ok() {
+        unsafe { signal(SIGINT, on_interrupt) };
+    }
+
+    print_location(debugger);
+    let mut last = String::new();
+    let stdin = io::stdin();
+    loop {
+        print!("(gb) ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if stdin.lock().read_line(&mut input)? == 0 {
+            return Ok(()); // EOF
+        }
+        if input.trim().is_empty() {
+            input = last.clone();
+        }
+        let words: Vec<&str> = input.split_whitespace().collect();
+        match command(debugger, &words) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(message) => println!("{message}"),
+        }
+        last = input;
+    }
+}
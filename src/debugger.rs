@@ -0,0 +1,430 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::disasm::{disassemble, Disassembled};
+use crate::error::Result;
+use crate::gameboy::GameBoy;
+use crate::symbols::Symbols;
+
+// Runs a GameBoy under control: single steps, stepping over calls, running to a return or to a
+// breakpoint, and poking at registers and memory in between. The call stack is rebuilt as it
+// runs by watching SP, so a backtrace shows the CALLs, RSTs and interrupts that got us here.
+
+const INTERRUPT_VECTORS: [u16; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
+
+// frames past this are dropped from the bottom, code that never returns would grow it forever
+const MAX_FRAMES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    A, F, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP, PC,
+}
+
+impl Register {
+    pub const ALL: [Register; 14] = [
+        Register::A, Register::F, Register::B, Register::C, Register::D, Register::E, Register::H,
+        Register::L, Register::AF, Register::BC, Register::DE, Register::HL, Register::SP, Register::PC,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Register> {
+        Register::ALL.into_iter().find(|r| r.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Register::A => "a", Register::F => "f", Register::B => "b", Register::C => "c",
+            Register::D => "d", Register::E => "e", Register::H => "h", Register::L => "l",
+            Register::AF => "af", Register::BC => "bc", Register::DE => "de", Register::HL => "hl",
+            Register::SP => "sp", Register::PC => "pc",
+        }
+    }
+
+    pub fn is_16bit(&self) -> bool {
+        self.name().len() == 2
+    }
+}
+
+// A PC breakpoint. With a bank it only fires while that ROM bank is mapped at the address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    pub bank: Option<usize>,
+    pub addr: u16,
+}
+
+impl Breakpoint {
+    // at a label, or Label+offset. Labels in switchable ROM only break in their own bank
+    pub fn at_label(symbols: &Symbols, text: &str) -> Option<Breakpoint> {
+        let (bank, addr) = symbols.resolve(text)?;
+        Some(Breakpoint { bank: (0x4000..0x8000).contains(&addr).then_some(bank), addr })
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{bank:02X}:{:04X}", self.addr),
+            None => write!(f, "{:04X}", self.addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// matches a byte when (byte & mask) == value: mask 0x80 with value 0 is "bit 7 clear"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub mask: u8,
+    pub value: u8,
+}
+
+// A watchpoint on bus accesses to start..=end. These live on the MemoryBus, so DMA copies and
+// the CPU's read-modify-write instructions trip them as well as plain loads and stores
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub condition: Option<Condition>,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: Access, addr: u16, value: u8) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        kind && (self.start..=self.end).contains(&addr)
+            && self.condition.is_none_or(|c| value & c.mask == c.value)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match (self.read, self.write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        write!(f, "{kind} {:04X}", self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04X}", self.end)?;
+        }
+        if let Some(c) = self.condition {
+            write!(f, " {:02X}/{:02X}", c.value, c.mask)?;
+        }
+        Ok(())
+    }
+}
+
+// An access that tripped watchpoints[index]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub index: usize,
+    pub access: Access,
+    pub addr: u16,
+    pub value: u8, // what was read or written
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+// One entry in the call stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub call_site: u16,      // the CALL/RST, or the instruction an interrupt came in before
+    pub target: u16,         // where it went
+    pub bank: Option<usize>, // the ROM bank mapped at the target then
+    pub return_address: u16,
+    pub sp: u16,             // where the return address sits on the stack
+}
+
+// Why running stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    Step,              // the instruction(s) asked for ran
+    Breakpoint(usize), // PC reached breakpoints[n]
+    Watchpoint { hit: WatchHit, pc: u16 }, // the instruction at pc made the access
+    Finished,          // the frame being finished (or stepped over) returned
+    Interrupted,       // someone set `interrupt`
+}
+
+// A disassembled line of the view around PC
+pub struct Line {
+    pub addr: u16,
+    pub bank: Option<usize>,
+    pub bytes: Vec<u8>,
+    pub instruction: Disassembled,
+}
+
+pub struct Debugger {
+    pub gb: GameBoy,
+    pub breakpoints: Vec<Option<Breakpoint>>, // removed ones leave a hole so the numbers stay put
+    frames: Vec<Frame>,
+    // set from another thread (or a signal handler) to stop a continue/finish/next
+    pub interrupt: Arc<AtomicBool>,
+    pub symbols: Symbols,
+}
+
+impl Debugger {
+    pub fn new(gb: GameBoy) -> Self {
+        Debugger {
+            gb,
+            breakpoints: Vec::new(),
+            frames: Vec::new(),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            symbols: Symbols::default(),
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.gb.cpu.pc
+    }
+
+    // the ROM bank mapped at an address, None outside ROM or without a cartridge
+    pub fn bank(&self, addr: u16) -> Option<usize> {
+        self.gb.rom_bank(addr)
+    }
+
+    // the address as Label or Label+offset, using whatever ROM bank is mapped there now
+    pub fn symbol(&self, addr: u16) -> Option<String> {
+        self.symbols.describe(self.bank(addr), addr)
+    }
+
+    pub fn register(&self, register: Register) -> u16 {
+        let cpu = &self.gb.cpu;
+        let r = &cpu.registers;
+        match register {
+            Register::A => r.a as u16,
+            Register::F => u8::from(r.f) as u16,
+            Register::B => r.b as u16,
+            Register::C => r.c as u16,
+            Register::D => r.d as u16,
+            Register::E => r.e as u16,
+            Register::H => r.h as u16,
+            Register::L => r.l as u16,
+            Register::AF => r.get_af(),
+            Register::BC => r.get_bc(),
+            Register::DE => r.get_de(),
+            Register::HL => r.get_hl(),
+            Register::SP => cpu.sp(),
+            Register::PC => cpu.pc,
+        }
+    }
+
+    // 8-bit registers take the low byte of `value`
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        let cpu = &mut self.gb.cpu;
+        let r = &mut cpu.registers;
+        let byte = value as u8;
+        match register {
+            Register::A => r.a = byte,
+            Register::F => r.f = byte.into(),
+            Register::B => r.b = byte,
+            Register::C => r.c = byte,
+            Register::D => r.d = byte,
+            Register::E => r.e = byte,
+            Register::H => r.h = byte,
+            Register::L => r.l = byte,
+            Register::AF => r.set_af(value),
+            Register::BC => r.set_bc(value),
+            Register::DE => r.set_de(value),
+            Register::HL => r.set_hl(value),
+            Register::SP => cpu.set_sp(value),
+            Register::PC => cpu.pc = value,
+        }
+    }
+
+    pub fn read_memory(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.gb.cpu.bus.peek(addr.wrapping_add(i as u16))).collect()
+    }
+
+    // goes through the bus like a CPU write, so writes to ROM addresses reach the MBC. Our own
+    // writes don't trip watchpoints
+    pub fn write_memory(&mut self, addr: u16, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.gb.cpu.bus.write_byte(addr.wrapping_add(i as u16), byte);
+        }
+        self.gb.cpu.bus.take_watch_hits();
+    }
+
+    pub fn instruction_at(&self, addr: u16) -> Disassembled {
+        disassemble(&self.read_memory(addr, 3), addr)
+    }
+
+    // innermost frame last
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(Some(breakpoint));
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        self.breakpoints.get_mut(index)?.take()
+    }
+
+    pub fn watchpoints(&self) -> &[Option<Watchpoint>] {
+        &self.gb.cpu.bus.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.gb.cpu.bus.watchpoints.push(Some(watchpoint));
+        self.gb.cpu.bus.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        self.gb.cpu.bus.watchpoints.get_mut(index)?.take()
+    }
+
+    fn breakpoint_hit(&self) -> Option<usize> {
+        let pc = self.pc();
+        self.breakpoints.iter().position(|b| b.is_some_and(|b| b.addr == pc && b.bank.is_none_or(|bank| Some(bank) == self.bank(pc))))
+    }
+
+    // runs one instruction (or an interrupt dispatch)
+    pub fn step(&mut self) -> Result<Stop> {
+        self.step_tracked().map(|(stop, _)| stop)
+    }
+
+    // steps and keeps the call stack up to date, also returning the frame it pushed, if any
+    fn step_tracked(&mut self) -> Result<(Stop, Option<FrameKind>)> {
+        let pc = self.pc();
+        let sp = self.gb.cpu.sp();
+        let instruction = self.instruction_at(pc);
+        self.gb.step()?;
+
+        // the first watchpoint this instruction tripped
+        let stop = match self.gb.cpu.bus.take_watch_hits().first() {
+            Some(&hit) => Stop::Watchpoint { hit, pc },
+            None => Stop::Step,
+        };
+
+        let new_sp = self.gb.cpu.sp();
+        let new_pc = self.pc();
+        // anything whose return address has been popped is gone, by RET or otherwise
+        self.frames.retain(|f| f.sp >= new_sp);
+
+        if new_sp != sp.wrapping_sub(2) {
+            return Ok((stop, None));
+        }
+        let pushed = u16::from_le_bytes([self.gb.cpu.bus.peek(new_sp), self.gb.cpu.bus.peek(new_sp.wrapping_add(1))]);
+        let next = pc.wrapping_add(instruction.length);
+        let kind = if pushed == pc && INTERRUPT_VECTORS.contains(&new_pc) {
+            FrameKind::Interrupt
+        } else if instruction.mnemonic == "call" && pushed == next {
+            FrameKind::Call
+        } else if instruction.mnemonic == "rst" && pushed == next {
+            FrameKind::Rst
+        } else {
+            return Ok((stop, None)); // a PUSH
+        };
+
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(Frame { kind, call_site: pc, target: new_pc, bank: self.bank(new_pc), return_address: pushed, sp: new_sp });
+        Ok((stop, Some(kind)))
+    }
+
+    // steps over CALLs, RSTs and any interrupt that comes in, stopping at the next instruction
+    // of this frame unless a breakpoint or watchpoint is hit on the way
+    pub fn step_over(&mut self) -> Result<Stop> {
+        let depth = self.frames.len();
+        loop {
+            let (stop, pushed) = self.step_tracked()?;
+            if pushed.is_none() || stop != Stop::Step {
+                return Ok(stop);
+            }
+            match self.run_until(|d| d.frames.len() <= depth)? {
+                Stop::Finished => {}
+                stop => return Ok(stop),
+            }
+            // an interrupt came in before the instruction, which still has to run
+            if pushed != Some(FrameKind::Interrupt) {
+                return Ok(Stop::Step);
+            }
+        }
+    }
+
+    // runs until the innermost frame returns. With no frame there's nothing to return from,
+    // and this runs like continue
+    pub fn finish(&mut self) -> Result<Stop> {
+        let depth = self.frames.len();
+        if depth == 0 {
+            return self.cont();
+        }
+        self.run_until(|d| d.frames.len() < depth)
+    }
+
+    // runs until a breakpoint, a watchpoint, an interrupt request or an error
+    pub fn cont(&mut self) -> Result<Stop> {
+        self.run_until(|_| false)
+    }
+
+    // a breakpoint where we start doesn't stop us, we're already there
+    fn run_until(&mut self, done: impl Fn(&Debugger) -> bool) -> Result<Stop> {
+        loop {
+            let (stop, _) = self.step_tracked()?;
+            if stop != Stop::Step {
+                return Ok(stop);
+            }
+            if done(self) {
+                return Ok(Stop::Finished);
+            }
+            if let Some(index) = self.breakpoint_hit() {
+                return Ok(Stop::Breakpoint(index));
+            }
+            if self.interrupt.swap(false, Ordering::Relaxed) {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+
+    // `before` instructions leading up to `addr` and `after` from it on. Code can't be read
+    // backwards, so this looks for the furthest start point that decodes straight into `addr`
+    pub fn disassemble_around(&self, addr: u16, before: usize, after: usize) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for distance in (1..=(before * 3).min(addr as usize)).rev() {
+            let mut candidate = Vec::new();
+            let mut pc = addr as usize - distance;
+            while pc < addr as usize {
+                let line = self.line(pc as u16);
+                pc += line.instruction.length as usize;
+                candidate.push(line);
+            }
+            if pc == addr as usize {
+                lines = candidate;
+                break;
+            }
+        }
+        lines.drain(..lines.len().saturating_sub(before));
+
+        let mut pc = addr;
+        for _ in 0..after {
+            let line = self.line(pc);
+            pc = pc.wrapping_add(line.instruction.length);
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn line(&self, addr: u16) -> Line {
+        let instruction = self.instruction_at(addr);
+        let bytes = self.read_memory(addr, instruction.length as usize);
+        Line { addr, bank: self.bank(addr), bytes, instruction }
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+
+use crate::cpu::{AddHLTarget, Instruction, JumpTest, LoadType, Reg16, Reg8, StackTarget, Target, OPCODE_LENGTHS};
+
+// Turns SM83 machine code back into assembly, using the same decoder the CPU executes from.
+// Each instruction comes out as a mnemonic, its operands and its length, and renders as text
+// either in RGBDS syntax (what the .asm output of this crate is written in) or in the
+// uppercase, parenthesised style of the Nintendo manuals.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Rgbds,   // ld a, [hl+] / ldh [$FF44], a / jr nz, $0150
+    Classic, // LD A,(HL+) / LDH ($44),A / JR NZ,$0150
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(&'static str),   // a, hl, sp...
+    Indirect(&'static str),   // [hl], [bc], [hl+], [hl-], [c]
+    Immediate8(u8),
+    Immediate16(u16),
+    Address(u16),             // [a16]
+    HighAddress(u8),          // [$FF00+a8], only with ldh
+    Target(u16),              // where a jump or call goes
+    Vector(u8),               // rst $00-$38
+    Condition(&'static str),  // nz, z, nc, c
+    Bit(u8),
+    SpOffset(i8),             // sp+e8 in ld hl, sp+e8
+    Offset(i8),               // e8 in add sp, e8
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disassembled {
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub length: u16,
+}
+
+impl Disassembled {
+    // the address this jumps, calls or restarts to, when it's known without running it
+    pub fn target(&self) -> Option<u16> {
+        self.operands.iter().find_map(|op| match op {
+            Operand::Target(addr) => Some(*addr),
+            Operand::Vector(vector) => Some(*vector as u16),
+            _ => None,
+        })
+    }
+
+    // `labels` names addresses; jump targets and [a16] operands use the name when there is one
+    pub fn text(&self, syntax: Syntax, labels: &dyn Fn(u16) -> Option<String>) -> String {
+        let mnemonic = match syntax {
+            Syntax::Rgbds => self.mnemonic.to_string(),
+            Syntax::Classic => self.mnemonic.to_ascii_uppercase(),
+        };
+        if self.operands.is_empty() {
+            return mnemonic;
+        }
+        let operands: Vec<String> = self.operands.iter().map(|op| operand_text(*op, syntax, labels)).collect();
+        match syntax {
+            Syntax::Rgbds => format!("{mnemonic} {}", operands.join(", ")),
+            Syntax::Classic => format!("{mnemonic} {}", operands.join(",")),
+        }
+    }
+}
+
+impl fmt::Display for Disassembled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text(Syntax::Rgbds, &|_| None))
+    }
+}
+
+fn operand_text(operand: Operand, syntax: Syntax, labels: &dyn Fn(u16) -> Option<String>) -> String {
+    let (open, close) = match syntax {
+        Syntax::Rgbds => ("[", "]"),
+        Syntax::Classic => ("(", ")"),
+    };
+    let name = |s: &str| match syntax {
+        Syntax::Rgbds => s.to_string(),
+        Syntax::Classic => s.to_ascii_uppercase(),
+    };
+    let signed = |e: i8| if e < 0 { format!("-${:02X}", e.unsigned_abs()) } else { format!("${e:02X}") };
+
+    match operand {
+        Operand::Register(r) | Operand::Condition(r) => name(r),
+        Operand::Indirect(r) => format!("{open}{}{close}", name(r)),
+        Operand::Immediate8(n) => format!("${n:02X}"),
+        Operand::Immediate16(n) => format!("${n:04X}"),
+        Operand::Address(addr) => format!("{open}{}{close}", labels(addr).unwrap_or_else(|| format!("${addr:04X}"))),
+        Operand::HighAddress(n) => match syntax {
+            Syntax::Rgbds => format!("[{}]", labels(0xFF00 | n as u16).unwrap_or_else(|| format!("${:04X}", 0xFF00 | n as u16))),
+            Syntax::Classic => format!("(${n:02X})"),
+        },
+        Operand::Target(addr) => labels(addr).unwrap_or_else(|| format!("${addr:04X}")),
+        Operand::Vector(n) => format!("${n:02X}"), // rgbasm wants a constant here, never a label
+        Operand::Bit(n) => n.to_string(),
+        Operand::SpOffset(e) => match syntax {
+            Syntax::Rgbds => format!("sp {} ${:02X}", if e < 0 { '-' } else { '+' }, e.unsigned_abs()),
+            Syntax::Classic => format!("SP{}", if e < 0 { signed(e) } else { format!("+{}", signed(e)) }),
+        },
+        Operand::Offset(e) => signed(e),
+    }
+}
+
+fn reg8(r: &Reg8, bytes: &[u8]) -> Operand {
+    match r {
+        Reg8::A => Operand::Register("a"),
+        Reg8::B => Operand::Register("b"),
+        Reg8::C => Operand::Register("c"),
+        Reg8::D => Operand::Register("d"),
+        Reg8::E => Operand::Register("e"),
+        Reg8::H => Operand::Register("h"),
+        Reg8::L => Operand::Register("l"),
+        Reg8::D8 => Operand::Immediate8(bytes[1]),
+        Reg8::HLI => Operand::Indirect("hl"),
+        Reg8::BCI => Operand::Indirect("bc"),
+        Reg8::DEI => Operand::Indirect("de"),
+        Reg8::HLII => Operand::Indirect("hl+"),
+        Reg8::HLDI => Operand::Indirect("hl-"),
+        Reg8::D16I => Operand::Address(word(bytes)),
+        Reg8::CI => Operand::Indirect("c"),
+        Reg8::D8I => Operand::HighAddress(bytes[1]),
+    }
+}
+
+fn reg16(r: &Reg16, bytes: &[u8]) -> Operand {
+    match r {
+        Reg16::AF => Operand::Register("af"),
+        Reg16::BC => Operand::Register("bc"),
+        Reg16::DE => Operand::Register("de"),
+        Reg16::HL => Operand::Register("hl"),
+        Reg16::SP => Operand::Register("sp"),
+        Reg16::D16 => Operand::Immediate16(word(bytes)),
+        Reg16::I16 => Operand::Address(word(bytes)),
+    }
+}
+
+fn target(t: &Target, bytes: &[u8]) -> Operand {
+    match t {
+        Target::Reg8(r) => reg8(r, bytes),
+        Target::Reg16(r) => reg16(r, bytes),
+        Target::Reg16Indirect(r) => match reg16(r, bytes) {
+            Operand::Register(name) => Operand::Indirect(name),
+            operand => operand,
+        },
+        Target::Value => Operand::Immediate8(bytes[1]),
+    }
+}
+
+fn condition(test: &JumpTest) -> Option<Operand> {
+    match test {
+        JumpTest::NotZero => Some(Operand::Condition("nz")),
+        JumpTest::Zero => Some(Operand::Condition("z")),
+        JumpTest::NotCarry => Some(Operand::Condition("nc")),
+        JumpTest::Carry => Some(Operand::Condition("c")),
+        JumpTest::Always => None,
+    }
+}
+
+fn word(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[1], bytes[2]])
+}
+
+// Disassembles the instruction at the start of `bytes`, which sits at `addr` (used for
+// relative jumps). Bytes past the end of the slice read as zero.
+pub fn disassemble(bytes: &[u8], addr: u16) -> Disassembled {
+    let mut padded = [0u8; 3];
+    let len = bytes.len().min(3);
+    padded[..len].copy_from_slice(&bytes[..len]);
+    let bytes = &padded;
+
+    let opcode = bytes[0];
+    if opcode == 0xCB {
+        let (mnemonic, operands) = match Instruction::from_byte(bytes[1], true) {
+            Some(instruction) => describe(&instruction, bytes, addr),
+            None => ("db", vec![Operand::Immediate8(opcode)]),
+        };
+        return Disassembled { mnemonic, operands, length: 2 };
+    }
+
+    let (mnemonic, operands) = match Instruction::from_byte(opcode, false) {
+        Some(instruction) => describe(&instruction, bytes, addr),
+        None => ("db", vec![Operand::Immediate8(opcode)]), // $D3, $DB and the rest that don't exist
+    };
+    let length = if mnemonic == "db" { 1 } else { OPCODE_LENGTHS[opcode as usize] as u16 };
+    Disassembled { mnemonic, operands, length }
+}
+
+fn describe(instruction: &Instruction, bytes: &[u8], addr: u16) -> (&'static str, Vec<Operand>) {
+    use Instruction::*;
+    let a = Operand::Register("a");
+
+    let (mnemonic, operands) = match instruction {
+        Add(t) => ("add", vec![a, target(t, bytes)]),
+        AddHL(t) => {
+            let source = match t {
+                AddHLTarget::BC => "bc",
+                AddHLTarget::DE => "de",
+                AddHLTarget::HL => "hl",
+                AddHLTarget::SP => "sp",
+            };
+            ("add", vec![Operand::Register("hl"), Operand::Register(source)])
+        }
+        Adc(t) => ("adc", vec![a, target(t, bytes)]),
+        Sbc(t) => ("sbc", vec![a, target(t, bytes)]),
+        Sub(t) => ("sub", vec![target(t, bytes)]),
+        Or(t) => ("or", vec![target(t, bytes)]),
+        And(t) => ("and", vec![target(t, bytes)]),
+        Xor(t) => ("xor", vec![target(t, bytes)]),
+        Cp(t) => ("cp", vec![target(t, bytes)]),
+        Inc(t) => ("inc", vec![target(t, bytes)]),
+        Dec(t) => ("dec", vec![target(t, bytes)]),
+        Rlc(t) => ("rlc", vec![target(t, bytes)]),
+        Rrc(t) => ("rrc", vec![target(t, bytes)]),
+        Rl(t) => ("rl", vec![target(t, bytes)]),
+        Rr(t) => ("rr", vec![target(t, bytes)]),
+        Sla(t) => ("sla", vec![target(t, bytes)]),
+        Sra(t) => ("sra", vec![target(t, bytes)]),
+        Swap(t) => ("swap", vec![target(t, bytes)]),
+        Srl(t) => ("srl", vec![target(t, bytes)]),
+        Bit0(t) | Bit1(t) | Bit2(t) | Bit3(t) | Bit4(t) | Bit5(t) | Bit6(t) | Bit7(t) => ("bit", vec![Operand::Bit((bytes[1] >> 3) & 7), target(t, bytes)]),
+        Res0(t) | Res1(t) | Res2(t) | Res3(t) | Res4(t) | Res5(t) | Res6(t) | Res7(t) => ("res", vec![Operand::Bit((bytes[1] >> 3) & 7), target(t, bytes)]),
+        Set0(t) | Set1(t) | Set2(t) | Set3(t) | Set4(t) | Set5(t) | Set6(t) | Set7(t) => ("set", vec![Operand::Bit((bytes[1] >> 3) & 7), target(t, bytes)]),
+        AddSP => ("add", vec![Operand::Register("sp"), Operand::Offset(bytes[1] as i8)]),
+        Cpl => ("cpl", vec![]),
+        Ccf => ("ccf", vec![]),
+        Daa => ("daa", vec![]),
+        Scf => ("scf", vec![]),
+        Nop => ("nop", vec![]),
+        Rlca => ("rlca", vec![]),
+        Rla => ("rla", vec![]),
+        Rrca => ("rrca", vec![]),
+        Rra => ("rra", vec![]),
+        HALT => ("halt", vec![]),
+        STOP => ("stop", vec![]),
+        DI => ("di", vec![]),
+        EI => ("ei", vec![]),
+        RETI => ("reti", vec![]),
+        JP(test) => ("jp", condition(test).into_iter().chain([Operand::Target(word(bytes))]).collect()),
+        JR(test) => {
+            let destination = addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+            ("jr", condition(test).into_iter().chain([Operand::Target(destination)]).collect())
+        }
+        JPHL => ("jp", vec![Operand::Register("hl")]),
+        LD(LoadType::Word(d, s)) => ("ld", vec![reg16(d, bytes), reg16(s, bytes)]),
+        LDHLSP => ("ld", vec![Operand::Register("hl"), Operand::SpOffset(bytes[1] as i8)]),
+        LD(LoadType::Byte(d, s)) => {
+            let operands = vec![reg8(d, bytes), reg8(s, bytes)];
+            let high = matches!(d, Reg8::CI | Reg8::D8I) || matches!(s, Reg8::CI | Reg8::D8I);
+            (if high { "ldh" } else { "ld" }, operands)
+        }
+        POP(t) => ("pop", vec![stack(t)]),
+        PUSH(t) => ("push", vec![stack(t)]),
+        CALL(test) => ("call", condition(test).into_iter().chain([Operand::Target(word(bytes))]).collect()),
+        RET(test) => ("ret", condition(test).into_iter().collect()),
+        RST(vector) => ("rst", vec![Operand::Vector(*vector)]),
+    };
+    (mnemonic, operands)
+}
+
+fn stack(t: &StackTarget) -> Operand {
+    Operand::Register(match t {
+        StackTarget::AF => "af",
+        StackTarget::BC => "bc",
+        StackTarget::DE => "de",
+        StackTarget::HL => "hl",
+    })
+}
+
+// file offset of a banked ROM address: bank 0 is fixed at 0x0000-0x3FFF, the rest switch in at 0x4000
+pub fn rom_offset(bank: usize, addr: u16) -> usize {
+    if addr < 0x4000 { addr as usize } else { bank * 0x4000 + (addr as usize - 0x4000) }
+}
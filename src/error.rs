@@ -0,0 +1,42 @@
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, EmulationError>;
+
+// Everything that can stop the emulator, so embedders get an error back instead of an abort
+#[derive(Debug, Error)]
+pub enum EmulationError {
+    #[error("unknown opcode 0x{}{opcode:02X} at PC 0x{pc:04X}", if *.prefixed { "CB " } else { "" })]
+    UnknownOpcode { pc: u16, opcode: u8, prefixed: bool },
+
+    #[error("instruction at PC 0x{pc:04X} has an operand it can't use")]
+    InvalidOperand { pc: u16 },
+
+    #[error("invalid ROM: {0}")]
+    InvalidRom(String),
+
+    #[error("invalid save state: {0}")]
+    InvalidState(String),
+
+    #[error("{path}: {source}")]
+    Io { path: String, source: std::io::Error },
+
+    #[error("could not parse {path}: {source}")]
+    TestParse { path: String, source: serde_json::Error },
+
+    #[error("test {name} failed: {message}")]
+    TestFailed { name: String, message: String },
+}
+
+impl EmulationError {
+    pub fn io(path: impl Into<String>, source: std::io::Error) -> Self {
+        EmulationError::Io { path: path.into(), source }
+    }
+
+    // where the CPU was, for errors raised by an instruction
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            EmulationError::UnknownOpcode { pc, .. } | EmulationError::InvalidOperand { pc } => Some(*pc),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::bess;
+use crate::audio::DEFAULT_SAMPLE_RATE;
+use crate::cartridge::{Cartridge, CartridgeHeader};
+use crate::cpu::{CPU, Model};
+use crate::error::{EmulationError, Result};
+use crate::joypad::Button;
+use crate::savestate;
+use crate::serial::{self, SerialDevice};
+use crate::sgb::Sgb;
+
+// T-cycles (at normal speed) the PPU takes for a whole frame: 154 lines of 456 dots
+pub const FRAME_CYCLES: u32 = 70224;
+
+// The whole console: CPU, bus, cartridge, PPU, APU and input behind one type for frontends
+pub struct GameBoy {
+    pub cpu: CPU,
+    model: Option<Model>, // what load powers on as, instead of what the header asks for
+    sample_rate: u32,     // and the rate its audio comes out at
+}
+
+impl Default for GameBoy {
+    fn default() -> Self {
+        GameBoy::new()
+    }
+}
+
+impl GameBoy {
+    pub fn new() -> Self {
+        GameBoy { cpu: CPU::new(), model: None, sample_rate: DEFAULT_SAMPLE_RATE }
+    }
+
+    // inserts a ROM image and powers the console on. whatever is plugged into the link port stays,
+    // and so does the sample rate
+    pub fn load(&mut self, rom: Vec<u8>) -> Result<()> {
+        let cartridge = Cartridge::new(rom)?;
+        let device = std::mem::replace(&mut self.cpu.bus.serial.device, Box::new(serial::Disconnected));
+
+        self.cpu = CPU::new();
+        self.cpu.set_serial_device(device);
+        self.cpu.bus.apu.output.set_sample_rate(self.sample_rate);
+        match self.model {
+            Some(model) => self.cpu.load_cartridge_as(cartridge, model),
+            None => self.cpu.load_cartridge(cartridge),
+        }
+        Ok(())
+    }
+
+    // the model later loads power on as, None to go by the cartridge header
+    pub fn set_model(&mut self, model: Option<Model>) {
+        self.model = model;
+    }
+
+    pub fn load_file(&mut self, path: &str) -> Result<()> {
+        let rom = std::fs::read(path).map_err(|e| EmulationError::io(path, e))?;
+        self.load(rom)
+    }
+
+    // the ROM bank mapped at an address, None outside ROM or without a cartridge
+    pub fn rom_bank(&self, addr: u16) -> Option<usize> {
+        let cartridge = self.cpu.bus.cartridge.as_ref()?;
+        (addr < 0x8000).then(|| cartridge.mapped_bank(addr))
+    }
+
+    pub fn header(&self) -> Option<&CartridgeHeader> {
+        self.cpu.bus.cartridge.as_ref().map(|c| &c.header)
+    }
+
+    // runs one instruction, returns the T-cycles it took
+    pub fn step(&mut self) -> Result<u32> {
+        self.cpu.step()
+    }
+
+    // runs until the PPU finishes a frame, or a frame's worth of time passes with the LCD off
+    pub fn run_frame(&mut self) -> Result<()> {
+        self.cpu.bus.ppu.frame_ready = false;
+        let mut dots = 0;
+        while dots < FRAME_CYCLES && !self.cpu.bus.ppu.frame_ready {
+            let cycles = self.cpu.step()?;
+            dots += if self.cpu.double_speed() { cycles / 2 } else { cycles };
+        }
+        Ok(())
+    }
+
+    // 160x144 pixels, 0x00RRGGBB
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.cpu.bus.ppu.framebuffer
+    }
+
+    // the 256x224 bordered frame, when running an SGB enhanced cart
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.cpu.sgb()
+    }
+
+    // interleaved stereo samples at the output rate, returns the number of frames written
+    pub fn audio_samples(&mut self, out: &mut [i16]) -> usize {
+        self.cpu.bus.apu.output.read_samples_i16(out)
+    }
+
+    pub fn audio_samples_f32(&mut self, out: &mut [f32]) -> usize {
+        self.cpu.bus.apu.output.read_samples_f32(out)
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.cpu.bus.apu.output.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.cpu.set_buttons(pressed);
+    }
+
+    // the buttons held down, as a joypad::Button bitmask
+    pub fn buttons(&self) -> u8 {
+        self.cpu.bus.joypad.pressed()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.set_button(button, pressed);
+    }
+
+    pub fn set_player_buttons(&mut self, player: usize, pressed: u8) {
+        self.cpu.set_player_buttons(player, pressed);
+    }
+
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.set_serial_device(device);
+    }
+
+    // news from the serial device, like a page that has been printed
+    pub fn take_serial_message(&mut self) -> Option<String> {
+        self.cpu.bus.serial.device.take_message()
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.cpu.double_speed()
+    }
+
+    // battery backed cartridge RAM (and clock), None when the cart has no battery
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        self.cpu.bus.cartridge.as_ref().and_then(|c| c.save_ram())
+    }
+
+    pub fn restore_ram(&mut self, data: &[u8]) {
+        if let Some(cartridge) = &mut self.cpu.bus.cartridge {
+            cartridge.load_ram(data);
+        }
+    }
+
+    // the whole machine's state, see savestate for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save(&self.cpu)
+    }
+
+    // the state as a BESS file that SameBoy and other emulators can load, see bess
+    pub fn export_bess(&self) -> Vec<u8> {
+        bess::export(&self.cpu)
+    }
+
+    // takes our own states and BESS ones from other emulators. only states saved with the ROM
+    // that's loaded now are accepted. the serial device and audio output stay as they are
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        if !savestate::is_state(data) && bess::is_bess(data) {
+            return bess::import(&mut self.cpu, data);
+        }
+        savestate::load(&mut self.cpu, data)
+    }
+}
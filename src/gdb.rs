@@ -0,0 +1,293 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::debugger::{Access, Breakpoint, Debugger, Register, Stop, Watchpoint};
+use crate::error::EmulationError;
+
+// A GDB remote serial protocol stub, so an RSP front end can drive the debugger over TCP:
+//
+//   gb debug game.gb --gdb localhost:2345
+//
+// Registers go over as af, bc, de, hl, sp, pc (see gdb/sm83.xml, which is also served through
+// qXfer). Breakpoint addresses above 0xFFFF carry a ROM bank in the upper bits, so 0x14000 is
+// 01:4000; memory accesses only use the low 16 bits and see whatever is mapped.
+//
+// Stock gdb has no SM83 target, so the description has no <architecture> to give it, and gdb
+// falls back on its own default and turns down the register layout. What can attach is a front
+// end that takes the registers from target.xml rather than from an architecture it knows: a gdb
+// built with an SM83 port, or an editor plugin or script speaking RSP directly. tests/gdb.rs
+// goes through a session packet by packet.
+
+pub const TARGET_XML: &str = include_str!("../gdb/sm83.xml");
+
+const REGISTERS: [Register; 6] = [Register::AF, Register::BC, Register::DE, Register::HL, Register::SP, Register::PC];
+
+const INTERRUPT: u8 = 0x03;
+
+// signals in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbStub<'a> {
+    debugger: &'a mut Debugger,
+    stream: TcpStream,
+    incoming: Receiver<u8>,
+    ack: bool, // until the client asks for QStartNoAckMode
+}
+
+// waits for one connection on `addr` and serves it until the client detaches or goes away
+pub fn listen<A: ToSocketAddrs>(debugger: &mut Debugger, addr: A) -> io::Result<()> {
+    let (stream, _) = TcpListener::bind(addr)?.accept()?;
+    GdbStub::new(debugger, stream)?.serve()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn number(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+// "addr,len" as in m, M and qXfer
+fn address_length(text: &str) -> Option<(u32, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((number(addr)?, number(len)? as usize))
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(debugger: &'a mut Debugger, stream: TcpStream) -> io::Result<Self> {
+        // a reader thread passes everything on, and turns a Ctrl-C into a stop request while
+        // the emulator is running
+        let mut reader = stream.try_clone()?;
+        let interrupt = debugger.interrupt.clone();
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            let mut byte = [0u8];
+            while reader.read_exact(&mut byte).is_ok() {
+                if byte[0] == INTERRUPT {
+                    interrupt.store(true, Ordering::Relaxed);
+                }
+                if sender.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(GdbStub { debugger, stream, incoming, ack: true })
+    }
+
+    pub fn serve(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet) {
+                Some(reply) => self.send(&reply)?,
+                None => {
+                    // detach or kill
+                    self.send("OK")?;
+                    break;
+                }
+            }
+        }
+        // lets the reader thread finish
+        self.stream.shutdown(Shutdown::Both).or(Ok(()))
+    }
+
+    // the next well-formed packet's payload, None once the client hangs up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // acks and stray Ctrl-Cs between packets are dropped
+            loop {
+                match self.incoming.recv() {
+                    Ok(b'$') => break,
+                    Ok(_) => {}
+                    Err(_) => return Ok(None),
+                }
+            }
+            let mut payload = Vec::new();
+            loop {
+                match self.incoming.recv() {
+                    Ok(b'#') => break,
+                    Ok(byte) => payload.push(byte),
+                    Err(_) => return Ok(None),
+                }
+            }
+            let (Ok(high), Ok(low)) = (self.incoming.recv(), self.incoming.recv()) else {
+                return Ok(None);
+            };
+            let checksum = unhex(&String::from_utf8_lossy(&[high, low])).map(|c| c[0]);
+            let valid = checksum == Some(payload.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        let checksum = payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.stream.write_all(format!("${payload}#{checksum:02x}").as_bytes())?;
+        self.stream.flush()
+    }
+
+    // the reply to a packet, None to close the connection. Unsupported packets get an empty reply
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => format!("S{SIGTRAP:02x}"),
+            Some(b'g') => REGISTERS.iter().map(|&r| hex(&self.debugger.register(r).to_le_bytes())).collect(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'c') => self.resume(false),
+            Some(b's') => self.resume(true),
+            Some(b'Z') => self.breakpoint(&packet[1..], true),
+            Some(b'z') => self.breakpoint(&packet[1..], false),
+            Some(b'H') => "OK".into(),
+            Some(b'D' | b'k') => return None,
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".into()
+            }
+            _ if packet == "QStartNoAckMode" => {
+                self.ack = false;
+                "OK".into()
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                self.features(&packet["qXfer:features:read:target.xml:".len()..])
+            }
+            _ if packet == "qAttached" => "1".into(),
+            _ if packet == "qC" => "QC1".into(),
+            _ if packet == "qfThreadInfo" => "m1".into(),
+            _ if packet == "qsThreadInfo" => "l".into(),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let Some(bytes) = unhex(data).filter(|b| b.len() == REGISTERS.len() * 2) else {
+            return "E01".into();
+        };
+        for (register, value) in REGISTERS.iter().zip(bytes.chunks(2)) {
+            self.debugger.set_register(*register, u16::from_le_bytes([value[0], value[1]]));
+        }
+        "OK".into()
+    }
+
+    fn read_register(&self, index: &str) -> String {
+        match number(index).and_then(|i| REGISTERS.get(i as usize)) {
+            Some(&register) => hex(&self.debugger.register(register).to_le_bytes()),
+            None => "E01".into(),
+        }
+    }
+
+    fn write_register(&mut self, assignment: &str) -> String {
+        let Some((index, value)) = assignment.split_once('=') else { return "E01".into() };
+        let register = number(index).and_then(|i| REGISTERS.get(i as usize));
+        match (register, unhex(value)) {
+            (Some(&register), Some(bytes)) if bytes.len() == 2 => {
+                self.debugger.set_register(register, u16::from_le_bytes([bytes[0], bytes[1]]));
+                "OK".into()
+            }
+            _ => "E01".into(),
+        }
+    }
+
+    fn read_memory(&self, request: &str) -> String {
+        match address_length(request) {
+            Some((addr, len)) => hex(&self.debugger.read_memory(addr as u16, len.min(0x10000))),
+            None => "E01".into(),
+        }
+    }
+
+    fn write_memory(&mut self, request: &str) -> String {
+        let Some((target, data)) = request.split_once(':') else { return "E01".into() };
+        match (address_length(target), unhex(data)) {
+            (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                self.debugger.write_memory(addr as u16, &bytes);
+                "OK".into()
+            }
+            _ => "E01".into(),
+        }
+    }
+
+    // c and s, replying once the emulator stops
+    fn resume(&mut self, single_step: bool) -> String {
+        // a Ctrl-C that came in while we were already stopped doesn't count
+        self.debugger.interrupt.store(false, Ordering::Relaxed);
+        let stop = if single_step { self.debugger.step() } else { self.debugger.cont() };
+        match stop {
+            Ok(Stop::Watchpoint { hit, .. }) => {
+                let watchpoint = self.debugger.watchpoints()[hit.index];
+                let kind = match (watchpoint.map(|w| (w.read, w.write)), hit.access) {
+                    (Some((true, true)), _) => "awatch",
+                    (_, Access::Read) => "rwatch",
+                    (_, Access::Write) => "watch",
+                };
+                format!("T{SIGTRAP:02x}{kind}:{:x};", hit.addr)
+            }
+            Ok(Stop::Interrupted) => format!("S{SIGINT:02x}"),
+            Ok(_) => format!("S{SIGTRAP:02x}"),
+            Err(EmulationError::UnknownOpcode { .. }) => format!("S{SIGILL:02x}"),
+            Err(_) => format!("S{SIGTRAP:02x}"),
+        }
+    }
+
+    // Z/z type,addr,kind. Types 0 and 1 are breakpoints, 2-4 write, read and access watchpoints
+    fn breakpoint(&mut self, request: &str, insert: bool) -> String {
+        let mut fields = request.split(',');
+        let (Some(kind), Some(addr), Some(length)) = (fields.next(), fields.next().and_then(number), fields.next().and_then(number)) else {
+            return "E01".into();
+        };
+
+        match kind {
+            "0" | "1" => {
+                let bank = (addr > 0xFFFF).then_some((addr >> 16) as usize);
+                let breakpoint = Breakpoint { bank, addr: addr as u16 };
+                if insert {
+                    self.debugger.add_breakpoint(breakpoint);
+                } else if let Some(index) = self.debugger.breakpoints.iter().position(|b| *b == Some(breakpoint)) {
+                    self.debugger.remove_breakpoint(index);
+                }
+            }
+            "2" | "3" | "4" => {
+                let start = addr as u16;
+                let watchpoint = Watchpoint {
+                    start,
+                    end: start.saturating_add((length.max(1) - 1) as u16),
+                    read: kind != "2",
+                    write: kind != "3",
+                    condition: None,
+                };
+                if insert {
+                    self.debugger.add_watchpoint(watchpoint);
+                } else if let Some(index) = self.debugger.watchpoints().iter().position(|w| *w == Some(watchpoint)) {
+                    self.debugger.remove_watchpoint(index);
+                }
+            }
+            _ => return String::new(),
+        }
+        "OK".into()
+    }
+
+    // qXfer reads come in offset,length windows; `l` marks the last one
+    fn features(&self, window: &str) -> String {
+        let Some((offset, length)) = address_length(window) else { return "E01".into() };
+        let offset = (offset as usize).min(TARGET_XML.len());
+        let end = (offset + length).min(TARGET_XML.len());
+        let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+        format!("{marker}{}", &TARGET_XML[offset..end])
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+
+use crate::png::crc32_update;
+
+// A small streaming gzip encoder for trace logs: greedy LZ77 over a 32 KB window, written with
+// deflate's fixed Huffman codes. It's nowhere near zlib's ratio, but trace lines repeat so much
+// that it still shrinks them several times over without a compressor to pull in.
+
+// input is compressed a block at a time once this much has built up
+const BLOCK_SIZE: usize = 0x10000;
+// how far back a match can reach
+const WINDOW: usize = 0x8000;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// candidates looked at per position, more finds longer matches but slower
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+const END_OF_BLOCK: u16 = 256;
+
+pub struct GzipWriter<W: Write> {
+    inner: Option<W>, // taken by finish()
+    buffer: Vec<u8>,  // the last WINDOW bytes already compressed, then the pending input
+    history: usize,   // how much of `buffer` is already compressed
+    bits: u64,
+    bit_count: u32,
+    out: Vec<u8>,
+    crc: u32,
+    size: u32, // input length mod 2^32, as the trailer wants it
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        // no name or timestamp, unknown OS
+        inner.write_all(&[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF])?;
+        Ok(GzipWriter {
+            inner: Some(inner),
+            buffer: Vec::with_capacity(WINDOW + BLOCK_SIZE),
+            history: 0,
+            bits: 0,
+            bit_count: 0,
+            out: Vec::new(),
+            crc: 0xFFFF_FFFF,
+            size: 0,
+        })
+    }
+
+    // compresses what's left, writes the trailer and hands back the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let result = self.finish_stream();
+        let inner = self.inner.take().expect("finished twice");
+        result.map(|_| inner)
+    }
+
+    fn finish_stream(&mut self) -> io::Result<()> {
+        self.compress_block(true);
+        if self.bit_count > 0 {
+            self.out.push(self.bits as u8);
+            self.bits = 0;
+            self.bit_count = 0;
+        }
+        let trailer = [(!self.crc).to_le_bytes(), self.size.to_le_bytes()].concat();
+        self.out.extend_from_slice(&trailer);
+        self.flush_out()?;
+        self.inner.as_mut().expect("finished twice").flush()
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    // Huffman codes go out most significant bit first, unlike everything else
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+        self.write_literal(257 + code as u16);
+        self.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+
+        let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+        self.write_code(code as u32, 5);
+        self.write_bits((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+    }
+
+    // encodes the pending input as one fixed Huffman block, then keeps the tail as history
+    fn compress_block(&mut self, last: bool) {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.write_bits(last as u32, 1);
+        self.write_bits(1, 2); // fixed Huffman codes
+
+        let mut head = vec![usize::MAX; 1 << HASH_BITS];
+        let mut prev = vec![usize::MAX; buffer.len()];
+        for i in 0..self.history {
+            insert(&buffer, &mut head, &mut prev, i);
+        }
+
+        let mut i = self.history;
+        while i < buffer.len() {
+            let (mut best_length, mut best_distance) = (0, 0);
+            if i + MIN_MATCH <= buffer.len() {
+                let limit = (buffer.len() - i).min(MAX_MATCH);
+                let mut candidate = head[hash(&buffer, i)];
+                for _ in 0..MAX_CHAIN {
+                    if candidate == usize::MAX || i - candidate > WINDOW {
+                        break;
+                    }
+                    let length = buffer[candidate..].iter().zip(&buffer[i..i + limit]).take_while(|(a, b)| a == b).count();
+                    if length > best_length {
+                        (best_length, best_distance) = (length, i - candidate);
+                        if length == limit {
+                            break;
+                        }
+                    }
+                    candidate = prev[candidate];
+                }
+            }
+
+            if best_length >= MIN_MATCH {
+                self.write_match(best_length, best_distance);
+                for j in i..i + best_length {
+                    insert(&buffer, &mut head, &mut prev, j);
+                }
+                i += best_length;
+            } else {
+                self.write_literal(buffer[i] as u16);
+                insert(&buffer, &mut head, &mut prev, i);
+                i += 1;
+            }
+        }
+        self.write_literal(END_OF_BLOCK);
+
+        let keep = buffer.len().min(WINDOW);
+        self.buffer = buffer;
+        self.buffer.drain(..self.buffer.len() - keep);
+        self.history = keep;
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        self.inner.as_mut().expect("finished twice").write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+// the 3 bytes at `i`, hashed for finding earlier occurrences
+fn hash(buffer: &[u8], i: usize) -> usize {
+    let key = u32::from_le_bytes([buffer[i], buffer[i + 1], buffer[i + 2], 0]);
+    (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+// chains position `i` onto the others with the same hash, newest first
+fn insert(buffer: &[u8], head: &mut [usize], prev: &mut [usize], i: usize) {
+    if i + MIN_MATCH <= buffer.len() {
+        let h = hash(buffer, i);
+        prev[i] = head[h];
+        head[h] = i;
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.crc = crc32_update(self.crc, data);
+        self.size = self.size.wrapping_add(data.len() as u32);
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() - self.history >= BLOCK_SIZE {
+            self.compress_block(false);
+            self.flush_out()?;
+        }
+        Ok(data.len())
+    }
+
+    // compressed output only goes out a block at a time, this just passes on what's ready
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.inner.as_mut().expect("finished twice").flush()
+    }
+}
+
+impl<W: Write> Drop for GzipWriter<W> {
+    // finishing can fail, so call finish() to find out; this is the best effort for the rest
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.finish_stream();
+        }
+    }
+}
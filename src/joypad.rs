@@ -0,0 +1,86 @@
+use serde::{Serialize, Deserialize};
+
+pub const P1: u16 = 0xFF00;
+
+// bit positions match the P1 lines: directions on the low nibble, buttons on the high one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right, Button::Left, Button::Up, Button::Down,
+        Button::A, Button::B, Button::Select, Button::Start,
+    ];
+
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Joypad {
+    pressed: u8, // one bit per Button, 1 = held
+    select: u8,  // P14/P15 as last written, active low
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad { pressed: 0, select: 0x30 }
+    }
+
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    // these return whether a line went from high to low, which requests the joypad interrupt
+
+    pub fn write(&mut self, value: u8) -> bool {
+        let before = self.lines();
+        self.select = value & 0x30;
+        before & !self.lines() != 0
+    }
+
+    pub fn set_buttons(&mut self, pressed: u8) -> bool {
+        let before = self.lines();
+        self.pressed = pressed;
+        before & !self.lines() != 0
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let mask = if pressed {
+            self.pressed | button.mask()
+        } else {
+            self.pressed & !button.mask()
+        };
+        self.set_buttons(mask)
+    }
+
+    // the low nibble of P1: a line reads 0 when its button is held on a selected row
+    fn lines(&self) -> u8 {
+        let mut held = 0;
+        if self.select & 0x10 == 0 {
+            held |= self.pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            held |= self.pressed >> 4;
+        }
+        !held & 0x0F
+    }
+}
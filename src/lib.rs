@@ -0,0 +1,33 @@
+#![allow(clippy::upper_case_acronyms)] // opcode and register names follow the SM83 docs
+#![allow(clippy::new_without_default)] // components are built with new(), the way CPU::new always was
+
+pub mod error;
+pub mod reg;
+pub mod cpu;
+pub mod memory;
+pub mod cartridge;
+pub mod ppu;
+pub mod timer;
+pub mod apu;
+pub mod audio;
+pub mod joypad;
+pub mod serial;
+pub mod link;
+pub mod printer;
+pub mod sgb;
+pub mod png;
+pub mod savestate;
+pub mod bess;
+pub mod rewind;
+pub mod gzip;
+pub mod disasm;
+pub mod rgbds;
+pub mod symbols;
+pub mod debugger;
+pub mod trace;
+pub mod gdb;
+pub mod gameboy;
+pub mod libretro;
+
+pub use gameboy::GameBoy;
+pub use error::EmulationError;
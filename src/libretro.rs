@@ -0,0 +1,447 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+
+use crate::audio::DEFAULT_SAMPLE_RATE;
+use crate::cartridge::RTC_SAVE_SIZE;
+use crate::gameboy::{GameBoy, FRAME_CYCLES};
+use crate::joypad::Button;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::sgb::{SGB_HEIGHT, SGB_WIDTH};
+
+// The emulator as a libretro core. Building the crate produces libgb_emulator.so next to the
+// rlib; RetroArch picks cores up by name, so copy it into its cores directory as
+// gb_emulator_libretro.so. The frontend drives everything through the retro_* functions below,
+// all from one thread, which is why the core lives in a thread local.
+
+pub const RETRO_API_VERSION: c_uint = 1;
+
+pub const RETRO_DEVICE_JOYPAD: c_uint = 1;
+
+pub const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+pub const RETRO_DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+pub const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+pub const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+pub const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+pub const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+pub const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+pub const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+
+pub const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+pub const RETRO_MEMORY_RTC: c_uint = 1;
+pub const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+pub const RETRO_MEMORY_VIDEO_RAM: c_uint = 3;
+
+pub const RETRO_REGION_NTSC: c_uint = 0;
+
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const RETRO_ENVIRONMENT_GET_LOG_INTERFACE: c_uint = 27;
+pub const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+pub const RETRO_LOG_ERROR: c_uint = 3;
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+pub type LogPrintfFn = unsafe extern "C" fn(level: c_uint, fmt: *const c_char, ...);
+
+#[repr(C)]
+pub struct LogCallback {
+    pub log: Option<LogPrintfFn>,
+}
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+// libretro joypad ids for each P1 bit, in Button order
+const BUTTON_IDS: [(Button, c_uint); 8] = [
+    (Button::Right, RETRO_DEVICE_ID_JOYPAD_RIGHT),
+    (Button::Left, RETRO_DEVICE_ID_JOYPAD_LEFT),
+    (Button::Up, RETRO_DEVICE_ID_JOYPAD_UP),
+    (Button::Down, RETRO_DEVICE_ID_JOYPAD_DOWN),
+    (Button::A, RETRO_DEVICE_ID_JOYPAD_A),
+    (Button::B, RETRO_DEVICE_ID_JOYPAD_B),
+    (Button::Select, RETRO_DEVICE_ID_JOYPAD_SELECT),
+    (Button::Start, RETRO_DEVICE_ID_JOYPAD_START),
+];
+
+const AUDIO_CHUNK: usize = 1024; // stereo frames handed over per batch call
+
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample: Option<AudioSampleFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    log: Option<LogPrintfFn>,
+}
+
+struct Core {
+    gb: GameBoy,
+    rom: Vec<u8>,
+    rtc: Vec<u8>,      // RETRO_MEMORY_RTC, in the .sav footer layout
+    rtc_loaded: bool,  // the frontend fills RTC memory after load_game, so it's applied on the first run
+    stopped: bool,     // the CPU hit something it can't run; keep presenting the last frame
+    audio: Vec<i16>,
+}
+
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with_borrow_mut(|core| core.as_mut().map_or(default, f))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    let mut log = LogCallback { log: None };
+    let has_log = unsafe { callback(RETRO_ENVIRONMENT_GET_LOG_INTERFACE, &mut log as *mut LogCallback as *mut c_void) };
+    CALLBACKS.with_borrow_mut(|c| {
+        c.environment = Some(callback);
+        c.log = if has_log { log.log } else { None };
+    });
+}
+
+// errors go to the frontend's log. without one they're dropped: stderr may well be the
+// terminal the frontend is drawing in
+fn log_error(message: &str) {
+    let Some(log) = CALLBACKS.with_borrow(|c| c.log) else { return };
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    unsafe { log(RETRO_LOG_ERROR, c"gb-emulator: %s\n".as_ptr(), message.as_ptr()) };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CALLBACKS.with_borrow_mut(|c| c.video_refresh = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample(callback: AudioSampleFn) {
+    CALLBACKS.with_borrow_mut(|c| c.audio_sample = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CALLBACKS.with_borrow_mut(|c| c.audio_sample_batch = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CALLBACKS.with_borrow_mut(|c| c.input_poll = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CALLBACKS.with_borrow_mut(|c| c.input_state = Some(callback));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_init() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_deinit() {
+    CORE.with_borrow_mut(|core| *core = None);
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_info.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    unsafe {
+        *info = SystemInfo {
+            library_name: c"gb-emulator".as_ptr(),
+            library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+            valid_extensions: c"gb|gbc|sgb".as_ptr(),
+            need_fullpath: false,
+            block_extract: false,
+        };
+    }
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_av_info.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    unsafe {
+        *info = SystemAvInfo {
+            geometry: GameGeometry {
+                base_width: SCREEN_WIDTH as c_uint,
+                base_height: SCREEN_HEIGHT as c_uint,
+                max_width: SGB_WIDTH as c_uint,
+                max_height: SGB_HEIGHT as c_uint,
+                aspect_ratio: SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+            },
+            timing: SystemTiming {
+                fps: 4194304.0 / FRAME_CYCLES as f64,
+                sample_rate: DEFAULT_SAMPLE_RATE as f64,
+            },
+        };
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+// a power cycle. the old memory and cartridge RAM move over to the fresh console rather than
+// being reallocated, so the pointers the frontend got from retro_get_memory_data stay valid
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| {
+        let old = core.gb.cpu.bus.cartridge.take();
+        let mut memory = std::mem::replace(&mut core.gb.cpu.bus.memory, Box::new([0; 0x10000]));
+        if let Err(e) = core.gb.load(core.rom.clone()) {
+            // nothing was replaced, so the console carries on as it was
+            core.gb.cpu.bus.memory = memory;
+            core.gb.cpu.bus.cartridge = old;
+            log_error(&e.to_string());
+            return;
+        }
+        memory.copy_from_slice(&core.gb.cpu.bus.memory[..]);
+        core.gb.cpu.bus.memory = memory;
+        if let (Some(old), Some(cartridge)) = (old, &mut core.gb.cpu.bus.cartridge) {
+            cartridge.ram = old.ram;
+            cartridge.rtc = old.rtc;
+        }
+        core.stopped = false;
+    });
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.with_borrow(|c| (c.input_poll, c.input_state, c.video_refresh, c.audio_sample_batch));
+    let (input_poll, input_state, video_refresh, audio_sample_batch) = callbacks;
+
+    with_core((), |core| {
+        if !core.rtc_loaded {
+            if let Some(rtc) = core.gb.cpu.bus.cartridge.as_mut().and_then(|c| c.rtc.as_mut())
+                && core.rtc.iter().any(|&b| b != 0) {
+                rtc.load(&core.rtc);
+            }
+            core.rtc_loaded = true;
+        }
+
+        // the SGB reads up to four pads when a game asks for multiplayer
+        if let (Some(poll), Some(state)) = (input_poll, input_state) {
+            unsafe { poll() };
+            let players = if core.gb.sgb().is_some() { 4 } else { 1 };
+            for port in 0..players {
+                let pressed = BUTTON_IDS.iter()
+                    .filter(|(_, id)| unsafe { state(port, RETRO_DEVICE_JOYPAD, 0, *id) } != 0)
+                    .fold(0u8, |mask, (button, _)| mask | (1 << *button as u8));
+                core.gb.set_player_buttons(port as usize, pressed);
+            }
+        }
+
+        if !core.stopped && let Err(e) = core.gb.run_frame() {
+            log_error(&e.to_string());
+            core.stopped = true;
+        }
+
+        sync_rtc(core);
+
+        if let Some(refresh) = video_refresh {
+            let (pixels, width, height) = match core.gb.sgb() {
+                Some(sgb) => (&sgb.framebuffer, SGB_WIDTH, SGB_HEIGHT),
+                None => (&core.gb.cpu.bus.ppu.framebuffer, SCREEN_WIDTH, SCREEN_HEIGHT),
+            };
+            unsafe { refresh(pixels.as_ptr() as *const c_void, width as c_uint, height as c_uint, width * 4) };
+        }
+
+        loop {
+            let frames = core.gb.audio_samples(&mut core.audio);
+            if frames == 0 {
+                break;
+            }
+            if let Some(batch) = audio_sample_batch {
+                unsafe { batch(core.audio.as_ptr(), frames) };
+            }
+        }
+    });
+}
+
+// RETRO_MEMORY_RTC follows the clock, for whenever the frontend writes out the .rtc file
+fn sync_rtc(core: &mut Core) {
+    if let Some(rtc) = core.gb.cpu.bus.cartridge.as_ref().and_then(|c| c.rtc.as_ref()) {
+        core.rtc.copy_from_slice(&rtc.save());
+    }
+}
+
+// frontends size their buffers once, but a state grows a little with whatever SGB packet or
+// transfer is in flight, so there's room to spare. the tail past the state is zeroes
+const STATE_SLACK: usize = 1024;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.gb.save_state().len() + STATE_SLACK)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        let state = core.gb.save_state();
+        if data.is_null() || state.len() > size {
+            return false;
+        }
+        let out = unsafe { std::slice::from_raw_parts_mut(data as *mut u8, size) };
+        out[..state.len()].copy_from_slice(&state);
+        out[state.len()..].fill(0);
+        true
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        if data.is_null() {
+            return false;
+        }
+        let state = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+        let loaded = core.gb.load_state(state).is_ok();
+        if loaded {
+            core.stopped = false;
+            sync_rtc(core);
+        }
+        loaded
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a retro_game_info whose data/path are valid.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = (unsafe { game.as_ref() }) else { return false };
+    let rom = if !game.data.is_null() {
+        unsafe { std::slice::from_raw_parts(game.data as *const u8, game.size) }.to_vec()
+    } else if !game.path.is_null() {
+        let path = unsafe { CStr::from_ptr(game.path) }.to_string_lossy().into_owned();
+        match std::fs::read(&path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                log_error(&format!("{path}: {e}"));
+                return false;
+            }
+        }
+    } else {
+        return false;
+    };
+
+    let environment = CALLBACKS.with_borrow(|c| c.environment);
+    if let Some(environment) = environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !unsafe { environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) } {
+            log_error("frontend doesn't support XRGB8888");
+            return false;
+        }
+    }
+
+    let mut gb = GameBoy::new();
+    if let Err(e) = gb.load(rom.clone()) {
+        log_error(&e.to_string());
+        return false;
+    }
+    gb.set_sample_rate(DEFAULT_SAMPLE_RATE);
+
+    let has_rtc = gb.cpu.bus.cartridge.as_ref().is_some_and(|c| c.rtc.is_some());
+    let core = Core {
+        gb,
+        rom,
+        rtc: vec![0; if has_rtc { RTC_SAVE_SIZE } else { 0 }],
+        rtc_loaded: false,
+        stopped: false,
+        audio: vec![0; AUDIO_CHUNK * 2],
+    };
+    CORE.with_borrow_mut(|c| *c = Some(core));
+    true
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unload_game() {
+    CORE.with_borrow_mut(|core| *core = None);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+// the frontend reads and writes these in place: battery RAM and the clock to load and store
+// the .srm/.rtc files, WRAM and VRAM for cheats and achievements
+fn memory(core: &mut Core, id: c_uint) -> Option<&mut [u8]> {
+    let bus = &mut core.gb.cpu.bus;
+    match id {
+        RETRO_MEMORY_SAVE_RAM => bus.cartridge.as_mut().filter(|c| c.battery).map(|c| c.ram.as_mut_slice()),
+        RETRO_MEMORY_RTC => Some(core.rtc.as_mut_slice()),
+        RETRO_MEMORY_SYSTEM_RAM => Some(&mut bus.memory[0xC000..0xE000]),
+        RETRO_MEMORY_VIDEO_RAM => Some(&mut bus.memory[0x8000..0xA000]),
+        _ => None,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(std::ptr::null_mut(), |core| {
+        memory(core, id).filter(|m| !m.is_empty()).map_or(std::ptr::null_mut(), |m| m.as_mut_ptr() as *mut c_void)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(0, |core| memory(core, id).map_or(0, |m| m.len()))
+}
@@ -0,0 +1,261 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::Result;
+use crate::gameboy::GameBoy;
+use crate::serial::SerialDevice;
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const VERSION: u8 = 1;
+
+// T-cycles each side runs before the two instances meet up again. a normal speed
+// transfer takes 4096 cycles, so this leaves every byte at least two sync points
+pub const DEFAULT_QUANTUM: u32 = 1024;
+
+// flags in a sync message
+const SENT: u8 = 0x01;    // clocked a byte out as master during the quantum
+const WAITING: u8 = 0x02; // waiting on an external clock at the end of the quantum
+
+pub trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+// A link cable to another emulator instance over a socket.
+//
+// Both sides run in lockstep quanta and swap a small sync message at every boundary, so what
+// each one sees depends only on emulated time. A console that clocks a byte out gets the SB the
+// peer reported as waiting at the previous boundary (0xFF if it wasn't), and the peer takes the
+// byte at the next boundary, as long as it is still waiting on the clock it advertised.
+pub struct LinkCable {
+    stream: Option<Box<dyn Stream>>,
+    quantum: u32,
+    elapsed: u32,
+    waiting: Option<u8>,          // our SB, if we waited on an external clock this quantum
+    reported: Option<u8>,         // what we advertised at the last boundary
+    peer_waiting: Option<u8>,     // what the peer advertised at the last boundary
+    sent: Option<u8>,             // byte we clocked out this quantum
+    received: Option<u8>,         // byte the peer clocked into us, handed over on the next poll
+    error: Option<io::Error>,     // why the peer went away, once it has
+    message: Option<String>,      // the same for the frontend, until it takes it
+}
+
+impl LinkCable {
+    pub fn new(stream: Box<dyn Stream>, quantum: u32) -> io::Result<LinkCable> {
+        let mut cable = LinkCable {
+            stream: Some(stream),
+            quantum,
+            elapsed: 0,
+            waiting: None,
+            reported: None,
+            peer_waiting: None,
+            sent: None,
+            received: None,
+            error: None,
+            message: None,
+        };
+        cable.handshake()?;
+        Ok(cable)
+    }
+
+    pub fn listen_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<LinkCable> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        stream.set_nodelay(true)?;
+        LinkCable::new(Box::new(stream), DEFAULT_QUANTUM)
+    }
+
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<LinkCable> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        LinkCable::new(Box::new(stream), DEFAULT_QUANTUM)
+    }
+
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> io::Result<LinkCable> {
+        // a socket file left behind by an earlier run would make bind fail
+        let _ = std::fs::remove_file(&path);
+        let (stream, _) = UnixListener::bind(path)?.accept()?;
+        LinkCable::new(Box::new(stream), DEFAULT_QUANTUM)
+    }
+
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<LinkCable> {
+        LinkCable::new(Box::new(UnixStream::connect(path)?), DEFAULT_QUANTUM)
+    }
+
+    pub fn connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // what broke the connection. the console carries on as if the cable had been pulled
+    pub fn disconnect_error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    // both ends have to agree on the protocol and the quantum or they'd drift apart
+    fn handshake(&mut self) -> io::Result<()> {
+        let mut hello = [0u8; 9];
+        hello[0..4].copy_from_slice(MAGIC);
+        hello[4] = VERSION;
+        hello[5..9].copy_from_slice(&self.quantum.to_le_bytes());
+
+        let stream = self.stream.as_mut().unwrap();
+        stream.write_all(&hello)?;
+        stream.flush()?;
+
+        let mut peer = [0u8; 9];
+        stream.read_exact(&mut peer)?;
+        if peer != hello {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "link peer speaks a different protocol"));
+        }
+        Ok(())
+    }
+
+    // swaps sync messages with the peer and settles the quantum that just ended
+    fn sync(&mut self) {
+        let flags = if self.sent.is_some() { SENT } else { 0 } | if self.waiting.is_some() { WAITING } else { 0 };
+        let message = [flags, self.sent.unwrap_or(0xFF), self.waiting.unwrap_or(0xFF)];
+
+        let mut peer = [0u8; 3];
+        let result = self.stream.as_mut().map(|stream| {
+            stream.write_all(&message)?;
+            stream.flush()?;
+            stream.read_exact(&mut peer)
+        });
+
+        if let Some(Err(e)) = result {
+            self.message = Some(format!("link cable disconnected: {e}"));
+            self.error = Some(e);
+            self.stream = None;
+        }
+        if self.stream.is_none() {
+            peer = [0, 0xFF, 0xFF];
+        }
+
+        // the peer clocked a byte out; it saw us waiting if we advertised so last time
+        if peer[0] & SENT != 0 && self.reported.is_some() && self.waiting.is_some() {
+            self.received = Some(peer[1]);
+        }
+
+        self.peer_waiting = if peer[0] & WAITING != 0 { Some(peer[2]) } else { None };
+        self.reported = self.waiting;
+        self.waiting = None;
+        self.sent = None;
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        // only one byte per quantum reaches the peer, any more only see a floating line
+        if self.sent.is_some() {
+            return 0xFF;
+        }
+        self.sent = Some(byte);
+        self.peer_waiting.take().unwrap_or(0xFF)
+    }
+
+    fn external_clock(&mut self, byte: u8) -> Option<u8> {
+        if let Some(received) = self.received.take() {
+            self.reported = None;
+            return Some(received);
+        }
+        self.waiting = Some(byte);
+        None
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.elapsed += cycles;
+        while self.elapsed >= self.quantum {
+            self.elapsed -= self.quantum;
+            self.sync();
+        }
+    }
+
+    fn take_message(&mut self) -> Option<String> {
+        self.message.take()
+    }
+}
+
+// what one end of an in-process cable currently shows the other
+#[derive(Default)]
+struct Port {
+    waiting: Option<u8>,  // SB while this side waits on an external clock
+    incoming: Option<u8>, // byte the other side clocked in, not yet picked up
+}
+
+// One plug of a virtual cable. The two ends share the wire, so a transfer is settled the
+// moment the master's last bit goes out rather than at some later sync point.
+pub struct CableEnd {
+    wire: Rc<RefCell<[Port; 2]>>,
+    side: usize,
+}
+
+pub fn cable() -> (CableEnd, CableEnd) {
+    let wire = Rc::new(RefCell::new([Port::default(), Port::default()]));
+    (CableEnd { wire: wire.clone(), side: 0 }, CableEnd { wire, side: 1 })
+}
+
+impl SerialDevice for CableEnd {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let peer = &mut wire[self.side ^ 1];
+        match peer.waiting.take() {
+            Some(received) => {
+                peer.incoming = Some(byte);
+                received
+            }
+            None => 0xFF
+        }
+    }
+
+    fn external_clock(&mut self, byte: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let port = &mut wire[self.side];
+        if let Some(received) = port.incoming.take() {
+            return Some(received);
+        }
+        port.waiting = Some(byte);
+        None
+    }
+
+    // the serial port polls right after ticking the device, so a side that stopped waiting
+    // (transfer cancelled through SC) stops advertising it here
+    fn tick(&mut self, _cycles: u32) {
+        self.wire.borrow_mut()[self.side].waiting = None;
+    }
+}
+
+// Two consoles joined by a virtual cable, run in one process. Whichever console is behind in
+// emulated time always runs next, so neither gets more than one instruction ahead and a run
+// plays out the same way every time.
+pub struct LinkedPair {
+    pub consoles: [GameBoy; 2],
+    time: [u64; 2], // in 8 MHz ticks, so double speed consoles can be compared with normal ones
+}
+
+impl LinkedPair {
+    pub fn new(mut first: GameBoy, mut second: GameBoy) -> Self {
+        let (a, b) = cable();
+        first.set_serial_device(Box::new(a));
+        second.set_serial_device(Box::new(b));
+        LinkedPair { consoles: [first, second], time: [0, 0] }
+    }
+
+    // steps the console that is behind, returns its index
+    pub fn step(&mut self) -> Result<usize> {
+        let i = if self.time[0] <= self.time[1] { 0 } else { 1 };
+        let console = &mut self.consoles[i];
+        let scale = if console.double_speed() { 1 } else { 2 };
+        self.time[i] += console.step()? as u64 * scale;
+        Ok(i)
+    }
+
+    // runs both consoles until each has covered `cycles` more normal speed T-cycles
+    pub fn run_for(&mut self, cycles: u64) -> Result<()> {
+        let end = self.time[0].max(self.time[1]) + cycles * 2;
+        while self.time[0] < end || self.time[1] < end {
+            self.step()?;
+        }
+        Ok(())
+    }
+}
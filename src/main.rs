@@ -1,41 +1,713 @@
-#![allow(unused)] // temporarily allow unused variables, functions, methods
+use gb_emulator::cartridge::{Cartridge, Mbc};
+use gb_emulator::cpu::Model;
+use gb_emulator::debugger::Debugger;
+use gb_emulator::disasm::{Syntax, disassemble, rom_offset};
+use gb_emulator::rewind::Rewind;
+use gb_emulator::serial::SerialDevice;
+use gb_emulator::symbols::Symbols;
+use gb_emulator::gameboy::FRAME_CYCLES;
+use gb_emulator::trace::{self, Trace};
+use gb_emulator::{EmulationError, GameBoy, gdb, link, png, ppu, printer, rgbds};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
 
-mod reg;
-mod cpu;
-mod memory;
+mod debug;
+mod tui;
 
-use std::fs;
+const USAGE: &str = "\
+usage: gb <command> [options]
+
+commands:
+  run <rom> [--link-listen <addr> | --link-connect <addr> | --printer <dir>] [--symbols <file>] [--load <slot>|<file>]
+            [--rewind <seconds>] [--rewind-mb <n>] [--model dmg|sgb|cgb]
+      play a ROM in the terminal. link addresses are host:port for TCP or unix:<path> for a Unix socket.
+      0-9 pick a save state slot, s saves to it and l loads it. holding r rewinds, by up to 30 seconds
+      and 64 MB of snapshots unless --rewind and --rewind-mb say otherwise (--rewind 0 turns it off)
+  test sm83 [opcode...]
+      run the SM83 JSON tests from sm83/v1, for the given opcodes (hex, cbXX for prefixed)
+  test roms <dir> [--frames <n>]
+      run every test ROM under a directory and report which passed
+  disasm <rom> [--bank <n> | --bank <first>-<last>] [--syntax rgbds|classic] [--symbols <file>]
+      list the instructions in a ROM's banks, with labels for jump and call targets
+  disasm <rom> --output <dir>
+      trace the code from the entry point and vectors and write RGBDS source that rebuilds the ROM
+  debug <rom> [--break [<bank>:]<addr> | --break <label>] [--gdb <addr>] [--symbols <file>] [--model dmg|sgb|cgb]
+      run a ROM under the debugger REPL, stopped at the first instruction (or at a breakpoint).
+      with --gdb, wait for a GDB remote protocol client on a host:port instead (see src/gdb.rs)
+  trace <rom> [--output <file>] [--frames <n>] [--ly <hex> | --ly off] [--symbols <file>] [--model dmg|sgb|cgb]
+      log each instruction in Gameboy Doctor's format, to stdout or a file (gzipped if it ends in .gz).
+      LY reads as 90 like in the reference logs unless --ly says otherwise; a test ROM's result stops it.
+      with --symbols, each line ends in a comment naming the label PC is under
+  info <rom>
+      dump the cartridge header
+  headless <rom> [--frames <n>] [--screenshot <out.png>] [--symbols <file>] [--load <slot>|<file>] [--save <slot>]
+                 [--save-bess <file>] [--model dmg|sgb|cgb]
+      run for a number of frames with no output, optionally saving the last frame.
+      --save-bess writes the final state in the BESS format SameBoy and other emulators load
+
+symbols come from an RGBDS or no$gmb .sym file, game.sym next to game.gb unless --symbols names
+another (trace only uses them when asked). addresses are then shown as labels like Main.loop+3.
+--model picks the console to run as instead of the one the cartridge header asks for, so --model cgb
+plays a DMG game with the CGB's compatibility palettes.
+save state slots 0-9 are kept next to the ROM as game.ss0 to game.ss9. --load also takes a state file,
+including BESS states saved by other emulators
+
+exit codes: 0 success, 1 a test failed, 2 bad usage, 3 the ROM couldn't be loaded or emulation stopped";
+
+// exit codes, so scripts can tell a failing test from a broken invocation
+const EXIT_FAILED: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_ERROR: u8 = 3;
+
+// a snapshot every 10th of a second, so stepping back a frame replays at most 5
+const REWIND_INTERVAL: usize = 6;
+const REWIND_SECONDS: u32 = 30;
+const REWIND_MB: u32 = 64;
+
+// how long a test ROM gets to report before it counts as hung, about 30 seconds of emulated time
+const TEST_FRAMES: u32 = 1800;
+
+/* Instructions Under Test: the opcodes `test sm83` runs when none are given */
+const IUT_ADDITIONAL: [u8; 106] = [0x04, 0x05, 0x07, 0x14, 0x15, 0x17, 0x24, 0x25, 0x34, 0x35, 0x37,
+                                  0x0C, 0x0D, 0x0F, 0x1C, 0x1F, 0x1D, 0x2C, 0x2D, 0x2F, 0x3C, 0x3D,
+                                  0x3F, 0xC6, 0xCE, 0xD6, 0xDE, 0xE6, 0xF6, 0xEE, 0xFE, 0xC2, 0xC3,
+                                  0xD2, 0xCA, 0xDA, 0xE9, 0x18, 0x20, 0x28, 0x30, 0x38, 0x21, 0x01,
+                                  0x31, 0x06, 0x16, 0x26, 0x36, 0x0E, 0x1E, 0x2E, 0x3E, 0x02, 0x12,
+                                  0x22, 0x32, 0x0A, 0x1A, 0x2A, 0x3A, 0xEA, 0xFA, 0xE0, 0xE2, 0xF0,
+                                  0xF2, 0xC1, 0xD1, 0xE1, 0xF1, 0xC5, 0xD5, 0xE5, 0xF5, 0xC4, 0xD4,
+                                  0xCC, 0xDC, 0xCD, 0xC0, 0xC9, 0xD0, 0xC8, 0xD8, 0x03, 0x13, 0x23,
+                                  0x33, 0x0B, 0x1B, 0x2B, 0x3B, 0x27, 0x08, 0xE8, 0xF8, 0xF9, 0xC7,
+                                  0xCF, 0xD7, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF];
+
+enum CliError {
+    Usage(String),
+    Emulation(EmulationError),
+    Crashed(EmulationError, String), // with the label the CPU was under
+}
+
+impl From<EmulationError> for CliError {
+    fn from(e: EmulationError) -> Self {
+        CliError::Emulation(e)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{message}\n\n{USAGE}"),
+            CliError::Emulation(e) => write!(f, "{e}"),
+            CliError::Crashed(e, label) => write!(f, "{e} (in {label})"),
+        }
+    }
+}
+
+type CliResult = Result<ExitCode, CliError>;
+
+fn usage<T>(message: impl Into<String>) -> Result<T, CliError> {
+    Err(CliError::Usage(message.into()))
+}
+
+// positional arguments and --option values, in any order
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    // `valued` lists the options this command takes, each followed by a value
+    fn parse(args: &[String], valued: &[&str]) -> Result<Args, CliError> {
+        let mut parsed = Args { positional: Vec::new(), options: HashMap::new() };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                parsed.positional.push(arg.clone());
+            } else if valued.contains(&arg.as_str()) {
+                let Some(value) = args.next() else { return usage(format!("{arg} needs a value")) };
+                parsed.options.insert(arg.clone(), value.clone());
+            } else {
+                return usage(format!("unknown option {arg}"));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn number(&self, name: &str, default: u32) -> Result<u32, CliError> {
+        match self.option(name) {
+            Some(value) => value.parse().or_else(|_| usage(format!("{name} expects a number, got {value}"))),
+            None => Ok(default),
+        }
+    }
+
+    // exactly one positional argument, the ROM or directory the command works on
+    fn single(&self, what: &str) -> Result<&str, CliError> {
+        match self.positional.as_slice() {
+            [arg] => Ok(arg),
+            [] => usage(format!("missing {what}")),
+            [_, extra, ..] => usage(format!("unexpected argument {extra}")),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let rest = args.get(1..).unwrap_or(&[]);
+
+    let result = match args.first().map(String::as_str) {
+        Some("run") => run(rest),
+        Some("test") => test(rest),
+        Some("disasm") => disasm(rest),
+        Some("debug") => debug(rest),
+        Some("trace") => trace(rest),
+        Some("info") => info(rest),
+        Some("headless") => headless(rest),
+        Some("help" | "-h" | "--help") => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(command) => usage(format!("unknown command {command}")),
+        None => usage("missing command"),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e @ CliError::Usage(_)) => {
+            eprintln!("{e}");
+            ExitCode::from(EXIT_USAGE)
+        }
+        Err(e @ (CliError::Emulation(_) | CliError::Crashed(..))) => {
+            eprintln!("{e}");
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn read_rom(path: &str) -> Result<Vec<u8>, CliError> {
+    Ok(fs::read(path).map_err(|e| EmulationError::io(path, e))?)
+}
+
+// --symbols, or the .sym file next to the ROM when there is one
+fn load_symbols(args: &Args, rom: &str) -> Result<Symbols, CliError> {
+    match args.option("--symbols") {
+        Some(path) => Ok(Symbols::load(path)?),
+        None => Ok(Symbols::beside(rom).unwrap_or_default()),
+    }
+}
+
+// save state slots sit next to the ROM, like the .sav
+fn state_path(rom: &str, slot: u8) -> PathBuf {
+    Path::new(rom).with_extension(format!("ss{slot}"))
+}
+
+fn slot(args: &Args, name: &str) -> Result<Option<u8>, CliError> {
+    match args.option(name) {
+        Some(value) => match value.parse() {
+            Ok(slot @ 0..=9) => Ok(Some(slot)),
+            _ => usage(format!("{name} expects a slot from 0 to 9, got {value}")),
+        },
+        None => Ok(None),
+    }
+}
+
+// --model powers on as a given console instead of the one the header asks for
+fn model(args: &Args) -> Result<Option<Model>, CliError> {
+    match args.option("--model") {
+        Some("dmg") => Ok(Some(Model::Dmg)),
+        Some("sgb") => Ok(Some(Model::Sgb)),
+        Some("cgb") => Ok(Some(Model::Cgb)),
+        Some(value) => usage(format!("--model expects dmg, sgb or cgb, got {value}")),
+        None => Ok(None),
+    }
+}
+
+// --load takes a slot, or the path of a state file such as a BESS state from another emulator
+fn load_state(gb: &mut GameBoy, args: &Args, rom: &str) -> Result<(), CliError> {
+    let path = match args.option("--load") {
+        Some(value) => match value.parse() {
+            Ok(slot @ 0..=9) => state_path(rom, slot),
+            _ => PathBuf::from(value),
+        },
+        None => return Ok(()),
+    };
+    let data = fs::read(&path).map_err(|e| EmulationError::io(path.display().to_string(), e))?;
+    Ok(gb.load_state(&data)?)
+}
+
+// names the label an instruction went wrong under, when the symbols cover it
+fn crashed(gb: &GameBoy, symbols: &Symbols, e: EmulationError) -> CliError {
+    match e.pc().and_then(|pc| symbols.describe(gb.rom_bank(pc), pc)) {
+        Some(label) => CliError::Crashed(e, label),
+        None => e.into(),
+    }
+}
+
+fn run(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["--link-listen", "--link-connect", "--printer", "--symbols", "--load", "--rewind",
+        "--rewind-mb", "--model"])?;
+    let rom = args.single("ROM")?;
+    let symbols = load_symbols(&args, rom)?;
+
+    let mut gb = GameBoy::new();
+    gb.set_model(model(&args)?);
+    gb.load_file(rom)?;
 
-fn main() {
+    // battery backed RAM lives next to the ROM, as game.sav
+    let save_path = Path::new(rom).with_extension("sav");
+    if let Ok(data) = fs::read(&save_path) {
+        gb.restore_ram(&data);
+    }
 
-    /* Create an instance of a CPU */
-    let mut gb_cpu = cpu::CPU::new();
+    let link = match (args.option("--link-listen"), args.option("--link-connect")) {
+        (Some(_), Some(_)) => return usage("--link-listen and --link-connect can't be used together"),
+        (Some(addr), None) => Some((addr, true)),
+        (None, Some(addr)) => Some((addr, false)),
+        (None, None) => None,
+    };
+    if let Some((addr, listen)) = link {
+        let cable = match (addr.strip_prefix("unix:"), listen) {
+            (Some(path), true) => link::LinkCable::listen_unix(path),
+            (Some(path), false) => link::LinkCable::connect_unix(path),
+            (None, true) => link::LinkCable::listen_tcp(addr),
+            (None, false) => link::LinkCable::connect_tcp(addr),
+        };
+        gb.set_serial_device(Box::new(cable.map_err(|e| EmulationError::io(addr, e))?));
+    }
 
-    /* Instructions Under Test */
-    /*
-    let mut iut: Vec<u8> = (0x40..0xC0).collect();
-    let iut_additional: [u8; 89] = [0x04, 0x05, 0x07, 0x14, 0x15, 0x17, 0x24, 0x25, 0x34, 0x35, 0x37,
-                                   0x0C, 0x0D, 0x0F, 0x1C, 0x1F, 0x1D, 0x2C, 0x2D, 0x2F, 0x3C, 0x3D,
-                                   0x3F, 0xC6, 0xCE, 0xD6, 0xDE, 0xE6, 0xF6, 0xEE, 0xFE, 0xC2, 0xC3,
-                                   0xD2, 0xCA, 0xDA, 0xE9, 0x18, 0x20, 0x28, 0x30, 0x38, 0x21, 0x01,
-                                   0x31, 0x06, 0x16, 0x26, 0x36, 0x0E, 0x1E, 0x2E, 0x3E, 0x02, 0x12,
-                                   0x22, 0x32, 0x0A, 0x1A, 0x2A, 0x3A, 0xEA, 0xFA, 0xE0, 0xE2, 0xF0,
-                                   0xF2, 0xC1, 0xD1, 0xE1, 0xF1, 0xC5, 0xD5, 0xE5, 0xF5, 0xC4, 0xD4,
-                                   0xCC, 0xDC, 0xCD, 0xC0, 0xC9, 0xD0, 0xC8, 0xD8, 0x03, 0x13, 0x23,
-                                   0x33];
-    iut.extend(&iut_additional);
+    if let Some(dir) = args.option("--printer") {
+        gb.set_serial_device(Box::new(printer::Printer::new(dir)));
+    }
 
-    let iut_prefixed: Vec<u8> = (0x00..=0xFF).collect();
-    */
+    load_state(&mut gb, &args, rom)?;
 
-    //let mut iut: Vec<u8> = vec![0x09, 0x19, 0x29, 0x39];
-    //gb_cpu.run_sm83_tests(&iut, false);
+    let seconds = args.number("--rewind", REWIND_SECONDS)?;
+    let cap = args.number("--rewind-mb", REWIND_MB)? as usize * 1024 * 1024;
+    let rewind = (seconds > 0).then(|| Rewind::new(REWIND_INTERVAL, seconds as usize * 60, cap));
+
+    let mut saved = gb.save_ram();
+    tui::run(&mut gb, |slot| state_path(rom, slot), rewind, |gb, frame| {
+        // flush the save about once a second when the game has written to it
+        if frame % 60 == 0 {
+            let ram = gb.save_ram();
+            if let Some(data) = &ram && ram != saved {
+                fs::write(&save_path, data).map_err(|e| EmulationError::io(save_path.display().to_string(), e))?;
+                saved = ram;
+            }
+        }
+        Ok(())
+    }).map_err(|e| crashed(&gb, &symbols, e))?;
+
+    // and once more on the way out
+    if let Some(data) = gb.save_ram() && Some(&data) != saved.as_ref() {
+        fs::write(&save_path, data).map_err(|e| EmulationError::io(save_path.display().to_string(), e))?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn test(args: &[String]) -> CliResult {
+    match args.first().map(String::as_str) {
+        Some("sm83") => test_sm83(&args[1..]),
+        Some("roms") => test_roms(&args[1..]),
+        Some(suite) => usage(format!("unknown test suite {suite}")),
+        None => usage("missing test suite (sm83 or roms)"),
+    }
+}
+
+fn test_sm83(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &[])?;
+
+    let mut unprefixed: Vec<u8> = Vec::new();
+    let mut prefixed: Vec<u8> = Vec::new();
+    if args.positional.is_empty() {
+        unprefixed = (0x40..0xC0).collect();
+        unprefixed.extend(&IUT_ADDITIONAL);
+        prefixed = (0x00..=0xFF).collect();
+    }
+    for arg in &args.positional {
+        let lower = arg.to_ascii_lowercase();
+        let (hex, list) = match lower.strip_prefix("cb") {
+            Some(hex) if !hex.is_empty() => (hex, &mut prefixed),
+            _ => (lower.trim_start_matches("0x"), &mut unprefixed),
+        };
+        match u8::from_str_radix(hex, 16) {
+            Ok(opcode) => list.push(opcode),
+            Err(_) => return usage(format!("{arg} is not an opcode")),
+        }
+    }
+
+    let mut gb = GameBoy::new();
+    let mut opcodes = unprefixed.iter().map(|&op| (op, false)).chain(prefixed.iter().map(|&op| (op, true)));
+    let result = opcodes.try_for_each(|(opcode, prefixed)| {
+        gb.cpu.run_sm83_tests(opcode, prefixed)?;
+        println!("0x{}{opcode:02x} passed!", if prefixed { "cb" } else { "" });
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(e @ EmulationError::TestFailed { .. }) => {
+            eprintln!("{e}");
+            Ok(ExitCode::from(EXIT_FAILED))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// collects what a test ROM writes to the serial port
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl SerialDevice for Capture {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.0.borrow_mut().push(byte);
+        0xFF
+    }
+}
+
+enum Outcome {
+    Passed,
+    Failed,
+    TimedOut,
+}
+
+// Blargg's ROMs print "Passed"/"Failed" over serial, and the ones that test sound or run too
+// long for that put a status byte at 0xA000 behind the DE B0 61 signature. Mooneye's ROMs load
+// the Fibonacci numbers into B C D E H L on success and 0x42 into all of them on failure.
+fn outcome(gb: &GameBoy, serial: &[u8]) -> Option<Outcome> {
+    let text = String::from_utf8_lossy(serial);
+    if text.contains("Passed") {
+        return Some(Outcome::Passed);
+    }
+    if text.contains("Failed") {
+        return Some(Outcome::Failed);
+    }
+
+    let bus = &gb.cpu.bus;
+    let signature = [bus.read_byte(0xA001), bus.read_byte(0xA002), bus.read_byte(0xA003)];
+    if signature == [0xDE, 0xB0, 0x61] {
+        match bus.read_byte(0xA000) {
+            0x80 => {} // still running
+            0x00 => return Some(Outcome::Passed),
+            _ => return Some(Outcome::Failed),
+        }
+    }
+
+    let r = &gb.cpu.registers;
+    match [r.b, r.c, r.d, r.e, r.h, r.l] {
+        [3, 5, 8, 13, 21, 34] => Some(Outcome::Passed),
+        [0x42, 0x42, 0x42, 0x42, 0x42, 0x42] => Some(Outcome::Failed),
+        _ => None,
+    }
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if matches!(path.extension().and_then(|e| e.to_str()), Some("gb" | "gbc")) {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn test_roms(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["--frames"])?;
+    let dir = args.single("test ROM directory")?;
+    let frames = args.number("--frames", TEST_FRAMES)?;
+
+    let mut roms = Vec::new();
+    find_roms(Path::new(dir), &mut roms).map_err(|e| EmulationError::io(dir, e))?;
+    roms.sort();
+    if roms.is_empty() {
+        return Err(EmulationError::InvalidRom(format!("no .gb or .gbc files under {dir}")).into());
+    }
+
+    let mut passed = 0;
+    for path in &roms {
+        let serial = Rc::new(RefCell::new(Vec::new()));
+        let mut gb = GameBoy::new();
+        gb.set_serial_device(Box::new(Capture(serial.clone())));
+
+        let mut result = gb.load_file(&path.to_string_lossy()).map(|_| Outcome::TimedOut);
+        for _ in 0..frames {
+            if let Err(e) = gb.run_frame() {
+                result = Err(e);
+                break;
+            }
+            if let Some(outcome) = outcome(&gb, &serial.borrow()) {
+                result = Ok(outcome);
+                break;
+            }
+        }
+
+        match result {
+            Ok(Outcome::Passed) => {
+                passed += 1;
+                println!("PASS     {}", path.display());
+            }
+            Ok(Outcome::Failed) => println!("FAIL     {}", path.display()),
+            Ok(Outcome::TimedOut) => println!("TIMEOUT  {}", path.display()),
+            Err(e) => println!("ERROR    {}: {e}", path.display()),
+        }
+    }
+
+    println!("{passed}/{} passed", roms.len());
+    Ok(if passed == roms.len() { ExitCode::SUCCESS } else { ExitCode::from(EXIT_FAILED) })
+}
+
+// "3" or "1-4", inclusive
+fn bank_range(arg: &str, banks: usize) -> Result<(usize, usize), CliError> {
+    let parse = |n: &str| n.trim().parse::<usize>().or_else(|_| usage(format!("{arg} is not a bank or bank range")));
+    let (first, last) = match arg.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => (parse(arg)?, parse(arg)?),
+    };
+    if first > last || last >= banks {
+        return usage(format!("bank range {arg} is outside the ROM's {banks} banks"));
+    }
+    Ok((first, last))
+}
+
+// A linear listing of whole banks. Jump, call and rst targets get labels (named after the
+// bank and address they land on) which are printed where they point and used as operands.
+fn disasm(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["--bank", "--syntax", "--output", "--symbols"])?;
+    let path = args.single("ROM")?;
+    let rom = read_rom(path)?;
+    let symbols = load_symbols(&args, path)?;
+    if let Some(dir) = args.option("--output") {
+        if args.option("--bank").is_some() || args.option("--syntax").is_some() {
+            return usage("--output always writes every bank in RGBDS syntax");
+        }
+        return write_project(&rom, path, dir);
+    }
+
+    let banks = rom.len().div_ceil(0x4000).max(1);
+    let (first, last) = match args.option("--bank") {
+        Some(range) => bank_range(range, banks)?,
+        None => (0, banks - 1),
+    };
+    let syntax = match args.option("--syntax") {
+        None | Some("rgbds") => Syntax::Rgbds,
+        Some("classic") => Syntax::Classic,
+        Some(other) => return usage(format!("unknown syntax {other} (rgbds or classic)")),
+    };
+
+    // which bank an address in code running from `bank` refers to. code in bank 0 can't know
+    // what's switched in above 0x4000, so those stay plain addresses
+    let resolve = |bank: usize, addr: u16| match addr {
+        0x0000..=0x3FFF => Some((0, addr)),
+        0x4000..=0x7FFF if bank != 0 => Some((bank, addr)),
+        _ => None,
+    };
+
+    let mut listing = Vec::new();
+    let mut labels: HashMap<(usize, u16), String> = HashMap::new();
+    for bank in first..=last {
+        let mut addr: u16 = if bank == 0 { 0 } else { 0x4000 };
+        let end = ((bank + 1) * 0x4000).min(rom.len());
+        while rom_offset(bank, addr) < end {
+            let offset = rom_offset(bank, addr);
+            let instruction = disassemble(&rom[offset..end], addr);
+            if let Some(target) = instruction.target() && let Some(key) = resolve(bank, target) {
+                let kind = if instruction.mnemonic == "call" || instruction.mnemonic == "rst" { "Call" } else { "Jump" };
+                let label = labels.entry(key).or_insert_with(|| format!("{kind}_{:03X}_{:04X}", key.0, key.1));
+                if kind == "Call" {
+                    *label = format!("Call_{:03X}_{:04X}", key.0, key.1);
+                }
+            }
+            let length = (instruction.length as usize).min(end - offset);
+            listing.push((bank, addr, offset, length, instruction));
+            addr = addr.wrapping_add(length as u16);
+        }
+    }
+
+    let mut current_bank = usize::MAX;
+    for (bank, addr, offset, length, instruction) in &listing {
+        if *bank != current_bank {
+            println!("\n; bank {bank:03X}");
+            current_bank = *bank;
+        }
+        if let Some(label) = symbols.name(Some(*bank), *addr).or(labels.get(&(*bank, *addr)).map(String::as_str)) {
+            println!("{label}:");
+        }
+        // a symbol at the target, then our own label for it, then whatever symbol it falls under
+        let name = |target: u16| match resolve(*bank, target) {
+            Some(key) => symbols.name(Some(key.0), key.1).map(String::from)
+                .or_else(|| labels.get(&key).cloned())
+                .or_else(|| symbols.describe(Some(key.0), key.1)),
+            None => symbols.describe(None, target),
+        };
+        let hex: Vec<String> = rom[*offset..offset + length].iter().map(|b| format!("{b:02X}")).collect();
+        println!("    {:<24} ; {bank:02X}:{addr:04X}  {}", instruction.text(syntax, &name), hex.join(" "));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn write_project(rom: &[u8], path: &str, dir: &str) -> CliResult {
+    let name = Path::new(path).file_stem().map_or("game".into(), |s| s.to_string_lossy());
+    let project = rgbds::disassemble_rom(rom, &name);
+    fs::create_dir_all(dir).map_err(|e| EmulationError::io(dir, e))?;
+    for (file, contents) in &project.files {
+        let file = Path::new(dir).join(file);
+        fs::write(&file, contents).map_err(|e| EmulationError::io(file.display().to_string(), e))?;
+    }
+    println!("wrote {} files to {dir}", project.files.len());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn debug(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["--break", "--gdb", "--symbols", "--model"])?;
+    let rom = args.single("ROM")?;
+
+    let mut gb = GameBoy::new();
+    gb.set_model(model(&args)?);
+    gb.load_file(rom)?;
+    let mut debugger = Debugger::new(gb);
+    debugger.symbols = load_symbols(&args, rom)?;
+    if let Some(at) = args.option("--break") {
+        let breakpoint = debug::parse_breakpoint(&debugger.symbols, at).or_else(|e| usage(format!("--break: {e}")))?;
+        debugger.add_breakpoint(breakpoint);
+        if let Err(e) = debugger.cont() {
+            println!("emulation stopped: {}", crashed(&debugger.gb, &debugger.symbols, e));
+        }
+    }
+    if let Some(addr) = args.option("--gdb") {
+        eprintln!("waiting for a gdb client on {addr}");
+        gdb::listen(&mut debugger, addr).map_err(|e| EmulationError::io(addr, e))?;
+    } else {
+        debug::repl(&mut debugger).map_err(|e| EmulationError::io("stdin", e))?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn trace(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["--output", "--frames", "--ly", "--symbols", "--model"])?;
+    let rom = args.single("ROM")?;
+    let frames = args.number("--frames", TEST_FRAMES)?;
+    let ly_stub = match args.option("--ly") {
+        None => Some(trace::LY_STUB),
+        Some("off") => None,
+        Some(value) => match u8::from_str_radix(value, 16) {
+            Ok(ly) => Some(ly),
+            Err(_) => return usage(format!("--ly expects a hex byte or off, got {value}")),
+        },
+    };
+
+    let serial = Rc::new(RefCell::new(Vec::new()));
+    let mut gb = GameBoy::new();
+    gb.set_serial_device(Box::new(Capture(serial.clone())));
+    gb.set_model(model(&args)?);
+    gb.load_file(rom)?;
+    gb.cpu.bus.ly_stub = ly_stub;
+
+    let mut trace = match args.option("--output") {
+        Some(path) => Trace::create(path)?,
+        None => Trace::new(Box::new(std::io::BufWriter::new(std::io::stdout())), "stdout", false)?,
+    };
+    // the Doctor's format has no room for labels, so they're only added when asked for
+    let symbols = match args.option("--symbols") {
+        Some(path) => Symbols::load(path)?,
+        None => Symbols::default(),
+    };
+    if !symbols.is_empty() {
+        trace.set_symbols(symbols.clone());
+    }
+
+    // what the trace covers is worth keeping even when the CPU hits something it can't run
+    let mut result = Ok(());
+    'frames: for _ in 0..frames {
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES {
+            match trace.step(&mut gb) {
+                Ok(c) => cycles += c,
+                Err(e) => {
+                    result = Err(e);
+                    break 'frames;
+                }
+            }
+        }
+        if outcome(&gb, &serial.borrow()).is_some() {
+            break;
+        }
+    }
+    trace.finish()?;
+    result.map_err(|e| crashed(&gb, &symbols, e))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn info(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &[])?;
+    let path = args.single("ROM")?;
+    let rom = read_rom(path)?;
+    let cartridge = Cartridge::new(rom.clone())?;
+    let header = &cartridge.header;
+
+    let mbc = match cartridge.mbc {
+        Mbc::None => "none",
+        Mbc::Mbc1 => "MBC1",
+        Mbc::Mbc2 => "MBC2",
+        Mbc::Mbc3 => "MBC3",
+        Mbc::Mbc5 => "MBC5",
+    };
+    let mut extras = Vec::new();
+    if !cartridge.ram.is_empty() {
+        extras.push("RAM");
+    }
+    if cartridge.battery {
+        extras.push("battery");
+    }
+    if cartridge.rtc.is_some() {
+        extras.push("RTC");
+    }
+
+    println!("title:          {}", header.title);
+    println!("type:           0x{:02X} ({mbc}{}{})", header.cartridge_type,
+        if extras.is_empty() { "" } else { " + " }, extras.join(" + "));
+    println!("ROM size:       {} KB (0x{:02X})", (32 << header.rom_size as u32), header.rom_size);
+    println!("RAM size:       {} KB (0x{:02X})", cartridge.ram.len() / 1024, header.ram_size);
+    println!("CGB:            {:?}", header.cgb);
+    println!("SGB:            {}", if header.supports_sgb() { "yes" } else { "no" });
+    println!("old licensee:   0x{:02X}", header.old_licensee);
+    println!("header checksum 0x{:02X} ({})", header.header_checksum,
+        if header.checksum_valid(&rom) { "ok" } else { "bad" });
+    println!("file size:      {} bytes", rom.len());
+    Ok(ExitCode::SUCCESS)
+}
 
-    gb_cpu.load_rom("gb-test-roms/cpu_instrs/individual/09-op r,r.gb");
-    gb_cpu.run();
+fn headless(args: &[String]) -> CliResult {
+    let args = Args::parse(args, &["--frames", "--screenshot", "--symbols", "--load", "--save", "--save-bess",
+        "--model"])?;
+    let rom = args.single("ROM")?;
+    let frames = args.number("--frames", 60)?;
+    let symbols = load_symbols(&args, rom)?;
 
-    /* Run SM83 JSON tests on u8 opcodes specified above */
-    
-    //gb_cpu.run_sm83_tests(&iut_prefixed, true);
+    let mut gb = GameBoy::new();
+    gb.set_model(model(&args)?);
+    gb.load_file(rom)?;
+    load_state(&mut gb, &args, rom)?;
+    for _ in 0..frames {
+        gb.run_frame().map_err(|e| crashed(&gb, &symbols, e))?;
+    }
+    if let Some(slot) = slot(&args, "--save")? {
+        let path = state_path(rom, slot);
+        fs::write(&path, gb.save_state()).map_err(|e| EmulationError::io(path.display().to_string(), e))?;
+    }
+    if let Some(path) = args.option("--save-bess") {
+        fs::write(path, gb.export_bess()).map_err(|e| EmulationError::io(path, e))?;
+    }
 
+    if let Some(path) = args.option("--screenshot") {
+        png::write(path, ppu::SCREEN_WIDTH, ppu::SCREEN_HEIGHT, gb.framebuffer())
+            .map_err(|e| EmulationError::io(path, e))?;
+    }
+    Ok(ExitCode::SUCCESS)
 }
@@ -1,18 +1,422 @@
+use crate::ppu;
+use crate::timer;
+use crate::apu;
+use crate::joypad;
+use crate::serial;
+use crate::sgb;
+use crate::cartridge;
+use crate::debugger::{Access, WatchHit, Watchpoint};
+use crate::savestate::{big_array, big_array_2d, boxed_array};
+use std::cell::RefCell;
+use serde::{Serialize, Deserialize};
+
+const VBK: u16 = 0xFF4F;
+const SVBK: u16 = 0xFF70;
+const BCPS: u16 = 0xFF68;
+const BCPD: u16 = 0xFF69;
+const OCPS: u16 = 0xFF6A;
+const OCPD: u16 = 0xFF6B;
+const DMA: u16 = 0xFF46;
+const KEY1: u16 = 0xFF4D;
+const HDMA1: u16 = 0xFF51;
+const HDMA2: u16 = 0xFF52;
+const HDMA3: u16 = 0xFF53;
+const HDMA4: u16 = 0xFF54;
+const HDMA5: u16 = 0xFF55;
+
+// each 16 byte HDMA block takes 8 M-cycles at normal speed (the same real time in double speed)
+pub const HDMA_BLOCK_CYCLES: u32 = 32;
+// the CPU stays stopped for roughly 2050 M-cycles while the clock switches speed
+pub const SPEED_SWITCH_CYCLES: u32 = 8200;
+
+// VRAM bank 0 and WRAM banks 0/1 live in `memory`; the extra CGB banks are kept alongside
+#[derive(Serialize, Deserialize)]
 pub struct MemoryBus {
-    pub memory: [u8; 0x10000]
+    #[serde(with = "boxed_array")]
+    pub memory: Box<[u8; 0x10000]>, // boxed so a CPU can move about, or be deserialized, on a small stack
+    pub ppu: ppu::Ppu,
+    pub timer: timer::Timer,
+    pub apu: apu::Apu,
+    pub joypad: joypad::Joypad,
+    pub serial: serial::Serial,
+    pub sgb: Option<sgb::Sgb>,
+    pub cartridge: Option<cartridge::Cartridge>, // without one, ROM and external RAM are plain memory
+    pub cgb_mode: bool,
+    pub double_speed: bool,
+    #[serde(with = "big_array")]
+    pub vram1: [u8; 0x2000],
+    #[serde(with = "big_array_2d")]
+    pub wram_banks: [[u8; 0x1000]; 6], // WRAM banks 2-7
+    vram_bank: u8,
+    wram_bank: u8,
+    speed_switch_armed: bool,
+    hdma_source: u16,
+    hdma_dest: u16,
+    hdma_blocks: u8,   // blocks left in the current HBlank DMA
+    hdma_active: bool,
+    stall_cycles: u32, // CPU cycles owed to DMA or a speed switch
+    // debugging hooks, which save states leave alone
+    #[serde(skip)]
+    pub watchpoints: Vec<Option<Watchpoint>>, // removed ones leave a hole so the numbers stay put
+    #[serde(skip)]
+    watch_hits: RefCell<Vec<WatchHit>>,       // reads only have &self
+    #[serde(skip)]
+    pub ly_stub: Option<u8>, // what LY reads as instead, Gameboy Doctor traces expect 0x90
+    #[serde(skip)]
+    pub flat: bool,          // 64K of plain RAM with nothing mapped, for the SM83 JSON tests
 }
 
 impl MemoryBus {
+    pub fn new() -> Self {
+        MemoryBus {
+            memory: Box::new([0; 0x10000]),
+            ppu: ppu::Ppu::new(),
+            timer: timer::Timer::new(),
+            apu: apu::Apu::new(),
+            joypad: joypad::Joypad::new(),
+            serial: serial::Serial::new(),
+            sgb: None,
+            cartridge: None,
+            cgb_mode: false,
+            double_speed: false,
+            vram1: [0; 0x2000],
+            wram_banks: [[0; 0x1000]; 6],
+            vram_bank: 0,
+            wram_bank: 1,
+            speed_switch_armed: false,
+            hdma_source: 0,
+            hdma_dest: 0,
+            hdma_blocks: 0,
+            hdma_active: false,
+            stall_cycles: 0,
+            watchpoints: Vec::new(),
+            watch_hits: RefCell::new(Vec::new()),
+            ly_stub: None,
+            flat: false,
+        }
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
-        // the index of an array must be of type usize
-        self.memory[addr as usize]
+        let value = self.peek(addr);
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Read, addr, value);
+        }
+        value
+    }
+
+    // a read that watchpoints don't see, for debuggers looking at memory
+    pub fn peek(&self, addr: u16) -> u8 {
+        if self.flat {
+            return self.memory[addr as usize];
+        }
+        if let Some(cartridge) = &self.cartridge && is_cartridge(addr) {
+            return cartridge.read(addr);
+        }
+
+        match addr {
+            0x8000..=0x9FFF if self.vram_bank == 1 => self.vram1[(addr - 0x8000) as usize],
+            0xD000..=0xDFFF if self.wram_bank > 1 => {
+                self.wram_banks[(self.wram_bank - 2) as usize][(addr - 0xD000) as usize]
+            }
+            VBK if self.cgb_mode => 0xFE | self.vram_bank,
+            SVBK if self.cgb_mode => 0xF8 | self.wram_bank,
+            BCPS if self.cgb_mode => self.ppu.bcps | 0x40,
+            BCPD if self.cgb_mode => self.ppu.read_bcpd(ppu::Ppu::mode(&self.memory)),
+            OCPS if self.cgb_mode => self.ppu.ocps | 0x40,
+            OCPD if self.cgb_mode => self.ppu.read_ocpd(ppu::Ppu::mode(&self.memory)),
+            KEY1 if self.cgb_mode => {
+                0x7E | if self.double_speed { 0x80 } else { 0 } | self.speed_switch_armed as u8
+            }
+            HDMA1..=HDMA4 if self.cgb_mode => 0xFF,
+            HDMA5 if self.cgb_mode => {
+                // bit 7 reads back as 0 while an HBlank DMA is still running
+                let remaining = self.hdma_blocks.wrapping_sub(1) & 0x7F;
+                if self.hdma_active { remaining } else { 0x80 | remaining }
+            }
+            // in SGB multiplayer mode, P1 with no row selected gives the current joypad's number
+            joypad::P1 => match &self.sgb {
+                Some(sgb) if sgb.multiplayer() && self.joypad.select() == 0x30 => 0xFF - sgb.player,
+                _ => self.joypad.read()
+            },
+            serial::SB | serial::SC => self.serial.read(addr),
+            0xFF04 => self.timer.div(),
+            0xFF44 => self.ly_stub.unwrap_or(self.memory[ppu::LY]),
+            0xFF10..=0xFF3F | 0xFF76 | 0xFF77 => self.apu.read(addr),
+            // the index of an array must be of type usize
+            _ => self.memory[addr as usize]
+        }
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
-        self.memory[addr as usize] = value;
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Write, addr, value);
+        }
+
+        if self.flat {
+            self.memory[addr as usize] = value;
+            return;
+        }
+
+        if let Some(cartridge) = &mut self.cartridge && is_cartridge(addr) {
+            cartridge.write(addr, value);
+            return;
+        }
+
+        match addr {
+            0x8000..=0x9FFF if self.vram_bank == 1 => self.vram1[(addr - 0x8000) as usize] = value,
+            0xD000..=0xDFFF if self.wram_bank > 1 => {
+                self.wram_banks[(self.wram_bank - 2) as usize][(addr - 0xD000) as usize] = value;
+            }
+            VBK if self.cgb_mode => self.vram_bank = value & 0x01,
+            SVBK if self.cgb_mode => self.wram_bank = if value & 0x07 == 0 { 1 } else { value & 0x07 },
+            BCPS if self.cgb_mode => self.ppu.bcps = value & 0xBF,
+            BCPD if self.cgb_mode => self.ppu.write_bcpd(value, ppu::Ppu::mode(&self.memory)),
+            OCPS if self.cgb_mode => self.ppu.ocps = value & 0xBF,
+            OCPD if self.cgb_mode => self.ppu.write_ocpd(value, ppu::Ppu::mode(&self.memory)),
+            KEY1 if self.cgb_mode => self.speed_switch_armed = value & 0x01 != 0,
+            HDMA1 if self.cgb_mode => self.hdma_source = (self.hdma_source & 0x00F0) | (value as u16) << 8,
+            HDMA2 if self.cgb_mode => self.hdma_source = (self.hdma_source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA3 if self.cgb_mode => self.hdma_dest = (self.hdma_dest & 0x00F0) | ((value & 0x1F) as u16) << 8,
+            HDMA4 if self.cgb_mode => self.hdma_dest = (self.hdma_dest & 0x1F00) | (value & 0xF0) as u16,
+            HDMA5 if self.cgb_mode => self.start_hdma(value),
+            joypad::P1 => {
+                let mut interrupt = self.joypad.write(value);
+                if let Some(sgb) = &mut self.sgb && sgb.write_p1(value) {
+                    interrupt |= self.joypad.set_buttons(sgb.buttons[sgb.player as usize]);
+                }
+                if interrupt {
+                    self.memory[ppu::IF] |= 0x10;
+                }
+            }
+            serial::SB | serial::SC => self.serial.write(addr, value),
+            0xFF04 => {
+                // resetting DIV with the frame sequencer bit set counts as a falling edge
+                if self.timer.counter & (1 << self.frame_sequencer_bit()) != 0 {
+                    self.apu.frame_sequencer_step();
+                }
+                self.timer.reset_div(&mut self.memory);
+            }
+            0xFF10..=0xFF3F => self.apu.write(addr, value),
+            DMA => {
+                self.memory[addr as usize] = value;
+                self.oam_dma(value);
+            }
+            // LY is read only, and so are the mode and coincidence bits of STAT
+            0xFF44 => {}
+            0xFF41 => self.memory[addr as usize] = (value & 0x78) | (self.memory[addr as usize] & 0x07),
+            _ => self.memory[addr as usize] = value
+        }
+    }
+
+    fn watch(&self, access: Access, addr: u16, value: u8) {
+        for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+            if let Some(watchpoint) = watchpoint && watchpoint.matches(access, addr, value) {
+                self.watch_hits.borrow_mut().push(WatchHit { index, access, addr, value });
+            }
+        }
+    }
+
+    // the watchpoints that fired since the last call
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.watch_hits.take()
+    }
+
+    // advances the rest of the system by the number of cycles the CPU just spent
+    pub fn tick(&mut self, cycles: u32) {
+        let div_before = self.timer.counter as u32;
+        self.timer.tick(cycles, &mut self.memory);
+
+        if self.serial.tick(cycles) {
+            self.memory[ppu::IF] |= 0x08;
+        }
+
+        // the frame sequencer is clocked by each falling edge of a DIV bit
+        let bit = self.frame_sequencer_bit() + 1;
+        let edges = ((div_before + cycles) >> bit) - (div_before >> bit);
+        for _ in 0..edges {
+            self.apu.frame_sequencer_step();
+        }
+
+        // the PPU and APU keep running at normal speed while the CPU runs at double speed
+        let dots = if self.double_speed { cycles / 2 } else { cycles };
+        self.apu.tick(dots);
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.tick(dots);
+        }
+        let ly_before = self.memory[ppu::LY];
+        let entered_hblank = self.ppu.tick(dots, &mut self.memory, &self.vram1);
+
+        let entered_vblank = (ly_before as usize) < ppu::SCREEN_HEIGHT && self.memory[ppu::LY] as usize >= ppu::SCREEN_HEIGHT;
+        if let Some(sgb) = &mut self.sgb && entered_vblank {
+            sgb.vblank(&self.ppu.shades);
+        }
+
+        if entered_hblank && self.hdma_active {
+            self.hdma_block();
+        }
+    }
+
+    pub fn set_button(&mut self, button: joypad::Button, pressed: bool) {
+        let mask = if pressed {
+            self.player_buttons(0) | button.mask()
+        } else {
+            self.player_buttons(0) & !button.mask()
+        };
+        self.set_player_buttons(0, mask);
+    }
+
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.set_player_buttons(0, pressed);
+    }
+
+    // players 2-4 only exist with an SGB in multiplayer mode; P1 shows whoever it has selected
+    pub fn set_player_buttons(&mut self, player: usize, pressed: u8) {
+        let selected = match &mut self.sgb {
+            Some(sgb) => {
+                sgb.buttons[player] = pressed;
+                sgb.player as usize == player
+            }
+            None => player == 0,
+        };
+        if selected && self.joypad.set_buttons(pressed) {
+            self.memory[ppu::IF] |= 0x10;
+        }
+    }
+
+    // whether P1 shows a button held on a selected row, which is what wakes the CPU from STOP
+    pub fn button_held(&self) -> bool {
+        self.joypad.read() & 0x0F != 0x0F
+    }
+
+    fn player_buttons(&self, player: usize) -> u8 {
+        match &self.sgb {
+            Some(sgb) => sgb.buttons[player],
+            None => self.joypad.pressed(),
+        }
+    }
+
+    // DIV bit 4 (bit 5 in double speed) of the upper byte drives the 512 Hz frame sequencer
+    fn frame_sequencer_bit(&self) -> u32 {
+        if self.double_speed { 13 } else { 12 }
+    }
+
+    // cycles the CPU has to sit out for DMA or a speed switch, cleared once taken
+    pub fn take_stall_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    // STOP with KEY1 armed toggles the CPU speed, returns whether a switch happened
+    pub fn speed_switch(&mut self) -> bool {
+        if !self.cgb_mode || !self.speed_switch_armed {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.timer.reset_div(&mut self.memory);
+        self.stall_cycles += SPEED_SWITCH_CYCLES;
+        true
+    }
+
+    // 0xFF00-0xFF7F for BESS states: what reads give back, except where the hardware holds on
+    // to more than it shows, like the HDMA addresses and the APU's write only bits
+    pub fn io_registers(&self) -> [u8; 0x80] {
+        let mut io = [0; 0x80];
+        for (i, value) in io.iter_mut().enumerate() {
+            let addr = 0xFF00 + i as u16;
+            *value = match addr {
+                0xFF10..=0xFF3F => self.apu.written(addr),
+                0xFF44 => self.memory[ppu::LY],
+                HDMA1 if self.cgb_mode => (self.hdma_source >> 8) as u8,
+                HDMA2 if self.cgb_mode => self.hdma_source as u8,
+                HDMA3 if self.cgb_mode => (self.hdma_dest >> 8) as u8,
+                HDMA4 if self.cgb_mode => self.hdma_dest as u8,
+                _ => self.peek(addr),
+            };
+        }
+        io
     }
 
-    pub fn get_ref(&mut self, addr: u16) -> &mut u8 {
-        &mut self.memory[addr as usize]
+    // writes back a BESS I/O snapshot as the CPU would, except for registers whose writes start
+    // something (OAM DMA, HDMA, SGB packets, APU triggers) and the ones the CPU can't write
+    pub fn restore_io_registers(&mut self, io: &[u8; 0x80]) {
+        for (i, &value) in io.iter().enumerate() {
+            let addr = 0xFF00 + i as u16;
+            match addr {
+                joypad::P1 => { self.joypad.write(value); }
+                serial::SB => {}
+                serial::SC => self.serial.restore(io[(serial::SB - 0xFF00) as usize], value),
+                0xFF04 => self.timer.counter = (value as u16) << 8,
+                0xFF41 | 0xFF44 | DMA => self.memory[addr as usize] = value,
+                KEY1 if self.cgb_mode => {
+                    self.double_speed = value & 0x80 != 0;
+                    self.speed_switch_armed = value & 0x01 != 0;
+                }
+                0xFF10..=0xFF3F | HDMA5 | BCPD | OCPD | 0xFF76 | 0xFF77 => {}
+                _ => self.write_byte(addr, value),
+            }
+        }
+        self.apu.restore(io[0x10..0x40].try_into().unwrap());
+        self.hdma_active = false;
+        self.ppu.resume(&self.memory);
     }
-}
\ No newline at end of file
+
+    fn start_hdma(&mut self, value: u8) {
+        let blocks = (value & 0x7F) + 1;
+
+        if value & 0x80 == 0 {
+            if self.hdma_active {
+                // writing bit 7 clear during an HBlank DMA cancels it
+                self.hdma_active = false;
+                return;
+            }
+
+            // general purpose DMA copies everything at once and halts the CPU meanwhile
+            self.hdma_blocks = blocks;
+            while self.hdma_blocks > 0 {
+                self.hdma_block();
+            }
+        } else {
+            self.hdma_blocks = blocks;
+            self.hdma_active = true;
+
+            // with the LCD off there is no HBlank to wait for, so one block goes straight away
+            if self.memory[ppu::LCDC] & 0x80 == 0 {
+                self.hdma_block();
+            }
+        }
+    }
+
+    // copies one 16 byte block into the current VRAM bank
+    fn hdma_block(&mut self) {
+        for _ in 0..0x10 {
+            let byte = self.read_byte(self.hdma_source);
+            self.write_byte(0x8000 | (self.hdma_dest & 0x1FFF), byte);
+            self.hdma_source = self.hdma_source.wrapping_add(1);
+            self.hdma_dest = self.hdma_dest.wrapping_add(1);
+        }
+
+        self.hdma_blocks -= 1;
+        if self.hdma_blocks == 0 {
+            self.hdma_active = false;
+        }
+        self.stall_cycles += if self.double_speed { HDMA_BLOCK_CYCLES * 2 } else { HDMA_BLOCK_CYCLES };
+    }
+
+    // OAM DMA, copied in one go rather than over 160 M-cycles
+    fn oam_dma(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        for i in 0..0xA0 {
+            let byte = self.read_byte(source + i);
+            self.memory[0xFE00 + i as usize] = byte;
+            if !self.watchpoints.is_empty() {
+                self.watch(Access::Write, 0xFE00 + i, byte);
+            }
+        }
+    }
+}
+
+// ROM and external RAM, which the cartridge's MBC decides about
+fn is_cartridge(addr: u16) -> bool {
+    matches!(addr, 0x0000..=0x7FFF | 0xA000..=0xBFFF)
+}
@@ -0,0 +1,407 @@
+use crate::savestate::big_array;
+
+use serde::{Serialize, Deserialize};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/* LCD registers, stored in the I/O area of the memory map */
+pub const LCDC: usize = 0xFF40;
+pub const STAT: usize = 0xFF41;
+pub const SCY: usize = 0xFF42;
+pub const SCX: usize = 0xFF43;
+pub const LY: usize = 0xFF44;
+pub const LYC: usize = 0xFF45;
+pub const BGP: usize = 0xFF47;
+pub const OBP0: usize = 0xFF48;
+pub const OBP1: usize = 0xFF49;
+pub const WY: usize = 0xFF4A;
+pub const WX: usize = 0xFF4B;
+pub const IF: usize = 0xFF0F;
+
+const OAM: usize = 0xFE00;
+
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const HBLANK_DOTS: u32 = 204;
+const LINE_DOTS: u32 = 456;
+
+const MODE_HBLANK: u8 = 0;
+const MODE_VBLANK: u8 = 1;
+const MODE_OAM_SCAN: u8 = 2;
+const MODE_DRAWING: u8 = 3;
+
+// greyscale shades for the original DMG screen
+const DMG_COLORS: [u32; 4] = [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000];
+
+// palettes the CGB boot ROM loads for DMG carts it has no specific entry for (RGB555)
+const COMPAT_BG_PALETTE: [u16; 4] = [0x7FFF, 0x1BEF, 0x6180, 0x0000];
+const COMPAT_OBJ_PALETTE: [u16; 4] = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+
+#[derive(Serialize, Deserialize)]
+pub struct Ppu {
+    pub framebuffer: Vec<u32>, // 0x00RRGGBB per pixel, row major
+    pub shades: Vec<u8>,       // DMG shade (0-3) behind each pixel, which the SGB colourises
+    pub frame_ready: bool,
+    pub cgb_mode: bool,    // attributes, VRAM bank 1 and colour palettes in use
+    pub compat_mode: bool, // CGB hardware running a DMG cart through BGP/OBP0/OBP1
+    #[serde(with = "big_array")]
+    pub bg_palette_ram: [u8; 64],
+    #[serde(with = "big_array")]
+    pub obj_palette_ram: [u8; 64],
+    pub bcps: u8,
+    pub ocps: u8,
+    mode_clock: u32,
+    window_line: u8,
+    stat_line: bool,
+    lcd_on: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            framebuffer: vec![DMG_COLORS[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            cgb_mode: false,
+            compat_mode: false,
+            bg_palette_ram: [0xFF; 64],
+            obj_palette_ram: [0xFF; 64],
+            bcps: 0,
+            ocps: 0,
+            mode_clock: 0,
+            window_line: 0,
+            stat_line: false,
+            lcd_on: false,
+        }
+    }
+
+    // loads the palettes the boot ROM leaves behind when a DMG cart runs on a CGB
+    pub fn load_compat_palettes(&mut self) {
+        for (i, color) in COMPAT_BG_PALETTE.iter().enumerate() {
+            self.bg_palette_ram[i * 2] = (color & 0xFF) as u8;
+            self.bg_palette_ram[i * 2 + 1] = (color >> 8) as u8;
+        }
+        for palette in 0..2 {
+            for (i, color) in COMPAT_OBJ_PALETTE.iter().enumerate() {
+                self.obj_palette_ram[palette * 8 + i * 2] = (color & 0xFF) as u8;
+                self.obj_palette_ram[palette * 8 + i * 2 + 1] = (color >> 8) as u8;
+            }
+        }
+    }
+
+    /* BCPS/BCPD and OCPS/OCPD palette ports */
+
+    pub fn read_bcpd(&self, mode: u8) -> u8 {
+        if mode == MODE_DRAWING { return 0xFF; }
+        self.bg_palette_ram[(self.bcps & 0x3F) as usize]
+    }
+
+    pub fn write_bcpd(&mut self, value: u8, mode: u8) {
+        if mode != MODE_DRAWING {
+            self.bg_palette_ram[(self.bcps & 0x3F) as usize] = value;
+        }
+        // the index still advances when the write itself is blocked
+        if self.bcps & 0x80 != 0 {
+            self.bcps = 0x80 | (self.bcps.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    pub fn read_ocpd(&self, mode: u8) -> u8 {
+        if mode == MODE_DRAWING { return 0xFF; }
+        self.obj_palette_ram[(self.ocps & 0x3F) as usize]
+    }
+
+    pub fn write_ocpd(&mut self, value: u8, mode: u8) {
+        if mode != MODE_DRAWING {
+            self.obj_palette_ram[(self.ocps & 0x3F) as usize] = value;
+        }
+        if self.ocps & 0x80 != 0 {
+            self.ocps = 0x80 | (self.ocps.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    // advances the PPU by a number of dots, updating LY/STAT and requesting interrupts.
+    // returns whether a visible line entered HBlank, which is what drives CGB HDMA
+    pub fn tick(&mut self, cycles: u32, memory: &mut [u8; 0x10000], vram1: &[u8; 0x2000]) -> bool {
+        let mut entered_hblank = false;
+
+        if memory[LCDC] & 0x80 == 0 {
+            // LCD off: LY is held at 0 and STAT reports HBlank
+            self.lcd_on = false;
+            self.mode_clock = 0;
+            self.window_line = 0;
+            self.stat_line = false;
+            memory[LY] = 0;
+            memory[STAT] &= !0x03;
+            return false;
+        }
+
+        if !self.lcd_on {
+            self.lcd_on = true;
+            self.set_mode(memory, MODE_OAM_SCAN);
+        }
+
+        self.mode_clock += cycles;
+
+        loop {
+            let mode = memory[STAT] & 0x03;
+            match mode {
+                MODE_OAM_SCAN if self.mode_clock >= OAM_SCAN_DOTS => {
+                    self.mode_clock -= OAM_SCAN_DOTS;
+                    self.set_mode(memory, MODE_DRAWING);
+                }
+                MODE_DRAWING if self.mode_clock >= DRAWING_DOTS => {
+                    self.mode_clock -= DRAWING_DOTS;
+                    self.render_line(memory, vram1);
+                    self.set_mode(memory, MODE_HBLANK);
+                    entered_hblank = true;
+                }
+                MODE_HBLANK if self.mode_clock >= HBLANK_DOTS => {
+                    self.mode_clock -= HBLANK_DOTS;
+                    memory[LY] += 1;
+                    if memory[LY] as usize == SCREEN_HEIGHT {
+                        self.set_mode(memory, MODE_VBLANK);
+                        memory[IF] |= 0x01;
+                        self.frame_ready = true;
+                    } else {
+                        self.set_mode(memory, MODE_OAM_SCAN);
+                    }
+                }
+                MODE_VBLANK if self.mode_clock >= LINE_DOTS => {
+                    self.mode_clock -= LINE_DOTS;
+                    if memory[LY] == 153 {
+                        memory[LY] = 0;
+                        self.window_line = 0;
+                        self.set_mode(memory, MODE_OAM_SCAN);
+                    } else {
+                        memory[LY] += 1;
+                    }
+                }
+                _ => break,
+            }
+            self.update_stat(memory);
+        }
+        self.update_stat(memory);
+
+        entered_hblank
+    }
+
+    // carries on from the LY and STAT mode a BESS state left in memory, at the start of that mode.
+    // the STAT line counts as already high so loading can't raise a spurious interrupt
+    pub fn resume(&mut self, memory: &[u8; 0x10000]) {
+        self.lcd_on = memory[LCDC] & 0x80 != 0;
+        self.mode_clock = 0;
+        self.window_line = 0;
+        self.stat_line = true;
+    }
+
+    pub fn mode(memory: &[u8; 0x10000]) -> u8 {
+        memory[STAT] & 0x03
+    }
+
+    fn set_mode(&mut self, memory: &mut [u8; 0x10000], mode: u8) {
+        memory[STAT] = (memory[STAT] & !0x03) | mode;
+    }
+
+    // refreshes the LYC flag and raises the STAT interrupt on a rising edge of the combined line
+    fn update_stat(&mut self, memory: &mut [u8; 0x10000]) {
+        let stat = memory[STAT];
+        let coincidence = memory[LY] == memory[LYC];
+        memory[STAT] = if coincidence { stat | 0x04 } else { stat & !0x04 };
+
+        let line = (coincidence && stat & 0x40 != 0)
+            || match stat & 0x03 {
+                MODE_HBLANK => stat & 0x08 != 0,
+                MODE_VBLANK => stat & 0x10 != 0,
+                MODE_OAM_SCAN => stat & 0x20 != 0,
+                _ => false,
+            };
+
+        if line && !self.stat_line {
+            memory[IF] |= 0x02;
+        }
+        self.stat_line = line;
+    }
+
+    fn render_line(&mut self, memory: &[u8; 0x10000], vram1: &[u8; 0x2000]) {
+        let ly = memory[LY];
+        if ly as usize >= SCREEN_HEIGHT { return; }
+
+        let lcdc = memory[LCDC];
+        let mut bg_index = [0u8; SCREEN_WIDTH];
+        let mut bg_priority = [false; SCREEN_WIDTH];
+        let row = ly as usize * SCREEN_WIDTH;
+
+        // on DMG, LCDC bit 0 blanks the background; on CGB it only drops its priority
+        if self.cgb_mode || lcdc & 0x01 != 0 {
+            let scy = memory[SCY];
+            let scx = memory[SCX];
+            let wy = memory[WY];
+            let wx = memory[WX] as i16 - 7;
+            let window_visible = lcdc & 0x20 != 0 && ly >= wy && wx < SCREEN_WIDTH as i16;
+
+            for x in 0..SCREEN_WIDTH {
+                let in_window = window_visible && x as i16 >= wx;
+                let (map_base, px, py) = if in_window {
+                    let map = if lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+                    (map, (x as i16 - wx) as u8, self.window_line)
+                } else {
+                    let map = if lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+                    (map, scx.wrapping_add(x as u8), scy.wrapping_add(ly))
+                };
+
+                let map_addr = map_base + (py as usize / 8) * 32 + (px as usize / 8);
+                let tile = memory[map_addr];
+                let attr = if self.cgb_mode { vram1[map_addr - 0x8000] } else { 0 };
+
+                let mut tile_x = px % 8;
+                let mut tile_y = py % 8;
+                if attr & 0x20 != 0 { tile_x = 7 - tile_x; }
+                if attr & 0x40 != 0 { tile_y = 7 - tile_y; }
+
+                let tile_addr = if lcdc & 0x10 != 0 {
+                    0x8000 + tile as usize * 16
+                } else {
+                    (0x9000 + (tile as i8 as isize) * 16) as usize
+                };
+                let line_addr = tile_addr + tile_y as usize * 2;
+                let (lo, hi) = if attr & 0x08 != 0 {
+                    (vram1[line_addr - 0x8000], vram1[line_addr - 0x8000 + 1])
+                } else {
+                    (memory[line_addr], memory[line_addr + 1])
+                };
+
+                let bit = 7 - tile_x;
+                let index = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                bg_index[x] = index;
+                bg_priority[x] = attr & 0x80 != 0;
+
+                self.framebuffer[row + x] = if self.cgb_mode {
+                    cgb_color(&self.bg_palette_ram, attr & 0x07, index)
+                } else {
+                    self.shades[row + x] = shade(memory[BGP], index);
+                    self.dmg_color(memory[BGP], index, false, 0)
+                };
+            }
+
+            if window_visible {
+                self.window_line += 1;
+            }
+        } else {
+            for x in 0..SCREEN_WIDTH {
+                self.shades[row + x] = shade(memory[BGP], 0);
+                self.framebuffer[row + x] = self.dmg_color(memory[BGP], 0, false, 0);
+            }
+        }
+
+        if lcdc & 0x02 != 0 {
+            self.render_sprites(memory, vram1, &bg_index, &bg_priority);
+        }
+    }
+
+    fn render_sprites(&mut self, memory: &[u8; 0x10000], vram1: &[u8; 0x2000], bg_index: &[u8; SCREEN_WIDTH], bg_priority: &[bool; SCREEN_WIDTH]) {
+        let ly = memory[LY] as i16;
+        let lcdc = memory[LCDC];
+        let height: i16 = if lcdc & 0x04 != 0 { 16 } else { 8 };
+
+        // OAM scan keeps the first 10 sprites (in OAM order) that overlap this line
+        let mut sprites: Vec<usize> = (0..40)
+            .filter(|&i| {
+                let y = memory[OAM + i * 4] as i16 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(10)
+            .collect();
+
+        // DMG resolves overlaps by X coordinate first, CGB purely by OAM index
+        if !self.cgb_mode {
+            sprites.sort_by_key(|&i| memory[OAM + i * 4 + 1]);
+        }
+
+        let row = ly as usize * SCREEN_WIDTH;
+        let mut claimed = [false; SCREEN_WIDTH];
+
+        for &i in sprites.iter() {
+            let y = memory[OAM + i * 4] as i16 - 16;
+            let x = memory[OAM + i * 4 + 1] as i16 - 8;
+            let mut tile = memory[OAM + i * 4 + 2];
+            let attr = memory[OAM + i * 4 + 3];
+
+            if height == 16 { tile &= 0xFE; }
+
+            let mut line = ly - y;
+            if attr & 0x40 != 0 { line = height - 1 - line; }
+
+            let line_addr = 0x8000 + tile as usize * 16 + line as usize * 2;
+            let (lo, hi) = if self.cgb_mode && attr & 0x08 != 0 {
+                (vram1[line_addr - 0x8000], vram1[line_addr - 0x8000 + 1])
+            } else {
+                (memory[line_addr], memory[line_addr + 1])
+            };
+
+            for px in 0..8 {
+                let sx = x + px;
+                if sx < 0 || sx >= SCREEN_WIDTH as i16 { continue; }
+                let sx = sx as usize;
+
+                let bit = if attr & 0x20 != 0 { px } else { 7 - px };
+                let index = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+
+                // a higher priority sprite claims the pixel even if the BG ends up covering it
+                if index == 0 || claimed[sx] { continue; }
+                claimed[sx] = true;
+
+                let bg_wins = if self.cgb_mode {
+                    lcdc & 0x01 != 0 && bg_index[sx] != 0 && (bg_priority[sx] || attr & 0x80 != 0)
+                } else {
+                    attr & 0x80 != 0 && bg_index[sx] != 0
+                };
+                if bg_wins { continue; }
+
+                self.framebuffer[row + sx] = if self.cgb_mode {
+                    cgb_color(&self.obj_palette_ram, attr & 0x07, index)
+                } else {
+                    let palette = if attr & 0x10 != 0 { memory[OBP1] } else { memory[OBP0] };
+                    self.shades[row + sx] = shade(palette, index);
+                    self.dmg_color(palette, index, true, (attr >> 4) & 1)
+                };
+            }
+        }
+    }
+
+    // maps a colour index through a DMG palette register, then to the screen (or compat palette)
+    fn dmg_color(&self, palette: u8, index: u8, obj: bool, obj_palette: u8) -> u32 {
+        let shade = shade(palette, index);
+        if self.compat_mode {
+            if obj {
+                cgb_color(&self.obj_palette_ram, obj_palette, shade)
+            } else {
+                cgb_color(&self.bg_palette_ram, 0, shade)
+            }
+        } else {
+            DMG_COLORS[shade as usize]
+        }
+    }
+}
+
+fn shade(palette: u8, index: u8) -> u8 {
+    (palette >> (index * 2)) & 0x03
+}
+
+// looks up an RGB555 colour in palette RAM
+fn cgb_color(palette_ram: &[u8; 64], palette: u8, index: u8) -> u32 {
+    let offset = palette as usize * 8 + index as usize * 2;
+    rgb555(palette_ram[offset] as u16 | (palette_ram[offset + 1] as u16) << 8)
+}
+
+// expands an RGB555 colour to 0x00RRGGBB
+pub fn rgb555(color: u16) -> u32 {
+    let color = color as u32;
+    let r = color & 0x1F;
+    let g = (color >> 5) & 0x1F;
+    let b = (color >> 10) & 0x1F;
+
+    let expand = |c: u32| (c << 3) | (c >> 2);
+    (expand(r) << 16) | (expand(g) << 8) | expand(b)
+}
@@ -0,0 +1,270 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::serial::SerialDevice;
+
+// packet commands
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const UNPROCESSED: u8 = 0x08;
+
+// every answer to the keepalive byte carries this, it's how games find a printer on the cable
+const ALIVE: u8 = 0x81;
+
+const WIDTH: usize = 160;
+const TILES_PER_ROW: usize = WIDTH / 8;
+const BUFFER_SIZE: usize = 0x2000;
+
+// how long the head stays busy after a print command, in T-cycles
+const PRINT_CYCLES: u32 = 4194304 / 2;
+
+// greys for the four shades the print palette picks from
+const GREYS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// A Game Boy Printer on the end of the link cable.
+//
+// Packets are `88 33 | command | compression | length (LE) | data | checksum (LE) | 00 00`, where
+// the printer answers the last two bytes with 0x81 and its status. Data packets fill a band
+// buffer of 2bpp tiles, and each print command turns it into image rows. Consecutive prints
+// with no feed after them go on the same page; a page is written out as a PGM once paper feeds.
+pub struct Printer {
+    pub output_dir: PathBuf,
+    pub pages_printed: usize,
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    buffer: Vec<u8>,     // decompressed tile data waiting to be printed
+    page: Vec<u8>,       // printed greyscale rows, WIDTH pixels each
+    busy_cycles: u32,
+    message: Option<String>, // what became of the last page, until the frontend takes it
+}
+
+impl Printer {
+    pub fn new(output_dir: impl Into<PathBuf>) -> Self {
+        Printer {
+            output_dir: output_dir.into(),
+            pages_printed: 0,
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            busy_cycles: 0,
+            message: None,
+        }
+    }
+
+    // writes out a page that is still waiting on a paper feed
+    pub fn finish(&mut self) {
+        if !self.page.is_empty() {
+            self.write_page();
+        }
+    }
+
+    fn process(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            DATA => {
+                let data = std::mem::take(&mut self.packet);
+                if self.compressed {
+                    decompress(&data, &mut self.buffer);
+                } else {
+                    self.buffer.extend_from_slice(&data);
+                }
+                self.buffer.truncate(BUFFER_SIZE);
+                self.status |= UNPROCESSED;
+            }
+            PRINT if self.packet.len() >= 4 => {
+                let sheets = self.packet[0];
+                let margins = self.packet[1];
+                let palette = self.packet[2];
+
+                // zero sheets only feeds paper
+                if sheets > 0 {
+                    self.print(palette);
+                }
+                if margins & 0x0F != 0 {
+                    self.finish();
+                }
+
+                self.buffer.clear();
+                self.status = (self.status & !UNPROCESSED) | PRINTING;
+                self.busy_cycles = PRINT_CYCLES;
+            }
+            // only asks for the status byte
+            STATUS => {}
+            _ => {}
+        }
+    }
+
+    // renders the band buffer into rows of the current page
+    fn print(&mut self, palette: u8) {
+        let rows = self.buffer.len() / (TILES_PER_ROW * 16);
+        for tile_row in 0..rows {
+            for line in 0..8 {
+                for x in 0..WIDTH {
+                    let tile = tile_row * TILES_PER_ROW + x / 8;
+                    let offset = tile * 16 + line * 2;
+                    let bit = 7 - (x % 8);
+                    let low = (self.buffer[offset] >> bit) & 1;
+                    let high = (self.buffer[offset + 1] >> bit) & 1;
+                    let shade = (palette >> (((high << 1) | low) * 2)) & 0x03;
+                    self.page.push(GREYS[shade as usize]);
+                }
+            }
+        }
+    }
+
+    fn write_page(&mut self) {
+        self.pages_printed += 1;
+        let path = self.output_dir.join(format!("page-{:03}.pgm", self.pages_printed));
+
+        let result = fs::create_dir_all(&self.output_dir).and_then(|_| {
+            let mut file = fs::File::create(&path)?;
+            write!(file, "P5\n{} {}\n255\n", WIDTH, self.page.len() / WIDTH)?;
+            file.write_all(&self.page)
+        });
+        self.message = Some(match result {
+            Ok(()) => format!("printed {}", path.display()),
+            Err(e) => format!("could not write {}: {e}", path.display()),
+        });
+        self.page.clear();
+    }
+}
+
+// the printer's RLE: a control byte with bit 7 set repeats the next byte (n & 0x7F) + 2 times,
+// otherwise the next n + 1 bytes are copied as they are
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            if let Some(&byte) = data.get(i) {
+                out.extend(std::iter::repeat_n(byte, (control & 0x7F) as usize + 2));
+            }
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut response = 0x00;
+
+        self.state = match self.state {
+            State::Magic1 => if byte == 0x88 { State::Magic2 } else { State::Magic1 },
+            State::Magic2 => if byte == 0x33 { State::Command } else { State::Magic1 },
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.packet.clear();
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.length == 0 { State::ChecksumLow } else { State::Data }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize { State::ChecksumLow } else { State::Data }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                State::Alive
+            }
+            State::Alive => {
+                response = ALIVE;
+                self.process();
+                State::Status
+            }
+            State::Status => {
+                response = self.status;
+                State::Magic1
+            }
+        };
+
+        response
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.busy_cycles > 0 {
+            self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+            if self.busy_cycles == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+    }
+
+    fn take_message(&mut self) -> Option<String> {
+        self.message.take()
+    }
+}
+
+// the paper that's out when the power goes off is still a page
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
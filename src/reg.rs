@@ -1,6 +1,6 @@
-use crate::cpu;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Registers {
     pub a: u8,
     pub b: u8,
@@ -12,7 +12,7 @@ pub struct Registers {
     pub l: u8,
 }
 
-#[derive(Debug,Clone,Copy)]
+#[derive(Debug,Clone,Copy, Serialize, Deserialize)]
 pub struct FlagsRegister {
     pub zero: bool, // z
     pub subtract: bool, // n
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+
+use crate::error::Result;
+use crate::gameboy::GameBoy;
+
+// Rewinding: a save state every `interval` frames, plus the buttons held on each frame in between,
+// so any frame can be rebuilt by loading the state before it and replaying them. Only the newest
+// state is kept whole. Each older one is stored as its XOR against the next newer state, where
+// everything that didn't change is zero, with the zero runs squeezed out. Past the depth or the
+// memory cap the oldest snapshots are dropped.
+
+struct Snapshot {
+    delta: Vec<u8>,  // gets this state back from the next newer one, empty for the newest
+    inputs: Vec<u8>, // the buttons for each frame run since this state
+}
+
+pub struct Rewind {
+    interval: usize,
+    depth: usize,      // frames it keeps being able to step back, at least
+    memory_cap: usize, // bytes for the deltas, inputs and newest state together
+    snapshots: VecDeque<Snapshot>,
+    latest: Vec<u8>,   // the newest snapshot's state in full
+    frames: usize,     // frames kept, each snapshot's own and the ones replayed after it
+    memory: usize,
+}
+
+impl Rewind {
+    // `interval` trades memory for the frames a step back has to replay
+    pub fn new(interval: usize, depth: usize, memory_cap: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            depth,
+            memory_cap,
+            snapshots: VecDeque::new(),
+            latest: Vec::new(),
+            frames: 0,
+            memory: 0,
+        }
+    }
+
+    // call after every frame, with the buttons for it still set
+    pub fn record(&mut self, gb: &GameBoy) {
+        match self.snapshots.back_mut() {
+            Some(newest) if newest.inputs.len() + 1 < self.interval => {
+                newest.inputs.push(gb.buttons());
+                self.memory += 1;
+            }
+            _ => self.snapshot(gb.save_state()),
+        }
+        self.frames += 1;
+        self.trim();
+    }
+
+    // puts `gb` back one frame, returns false when there's nothing older left
+    pub fn step_back(&mut self, gb: &mut GameBoy) -> Result<bool> {
+        if self.frames <= 1 {
+            return Ok(false);
+        }
+        let newest = self.snapshots.back_mut().unwrap();
+        if newest.inputs.pop().is_some() {
+            self.memory -= 1;
+        } else {
+            self.snapshots.pop_back();
+            let newest = self.snapshots.back_mut().unwrap();
+            let delta = std::mem::take(&mut newest.delta);
+            let older = decode(&self.latest, &delta);
+            self.memory = self.memory - delta.len() - self.latest.len() + older.len();
+            self.latest = older;
+        }
+        self.frames -= 1;
+
+        gb.load_state(&self.latest)?;
+        for &buttons in &self.snapshots.back().unwrap().inputs {
+            gb.set_buttons(buttons);
+            gb.run_frame()?;
+        }
+        Ok(true)
+    }
+
+    // how many frames step_back can go
+    pub fn frames(&self) -> usize {
+        self.frames.saturating_sub(1)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    // forgets everything, for when the console jumps somewhere else like loading a state
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.latest = Vec::new();
+        self.frames = 0;
+        self.memory = 0;
+    }
+
+    fn snapshot(&mut self, state: Vec<u8>) {
+        if let Some(newest) = self.snapshots.back_mut() {
+            newest.delta = encode(&self.latest, &state);
+            self.memory += newest.delta.len();
+        }
+        self.memory = self.memory - self.latest.len() + state.len();
+        self.latest = state;
+        self.snapshots.push_back(Snapshot { delta: Vec::new(), inputs: Vec::new() });
+    }
+
+    // drops the oldest snapshot while what's left still covers the depth, or while over the
+    // memory cap. the newest stays whatever the cap
+    fn trim(&mut self) {
+        while self.snapshots.len() > 1 {
+            let oldest = &self.snapshots[0];
+            let oldest_frames = 1 + oldest.inputs.len();
+            if self.frames - oldest_frames <= self.depth && self.memory <= self.memory_cap {
+                break;
+            }
+            self.frames -= oldest_frames;
+            self.memory -= oldest.delta.len() + oldest.inputs.len();
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+// `older` XORed with `newer` as (zero run, literal length, literal) triples with LEB128 numbers,
+// after the length of `older`. bytes past the end of `newer` count as zero
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| older[i] ^ newer.get(i).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    write_number(&mut delta, older.len());
+
+    let mut i = 0;
+    while i < older.len() {
+        let start = i;
+        while i < older.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let literal = i;
+        // a lone zero stays in the literal, where it's cheaper than ending it
+        while i < older.len() && (xor(i) != 0 || (i + 1 < older.len() && xor(i + 1) != 0)) {
+            i += 1;
+        }
+        write_number(&mut delta, literal - start);
+        write_number(&mut delta, i - literal);
+        delta.extend((literal..i).map(xor));
+    }
+    delta
+}
+
+fn decode(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut at = 0;
+    let len = read_number(delta, &mut at);
+    let mut older: Vec<u8> = (0..len).map(|i| newer.get(i).copied().unwrap_or(0)).collect();
+
+    let mut i = 0;
+    while at < delta.len() {
+        i += read_number(delta, &mut at);
+        let literal = read_number(delta, &mut at);
+        for (byte, x) in older[i..i + literal].iter_mut().zip(&delta[at..at + literal]) {
+            *byte ^= x;
+        }
+        i += literal;
+        at += literal;
+    }
+    older
+}
+
+fn write_number(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_number(data: &[u8], at: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*at];
+        *at += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
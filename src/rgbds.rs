@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use crate::disasm::{disassemble, Disassembled, Operand, Syntax};
+
+// Recursive-descent disassembly of a whole ROM into RGBDS source.
+//
+// Tracing starts at the entry point, the rst vectors and the interrupt vectors and follows every
+// jp, jr, call and rst whose target can be worked out statically. Anything never reached stays
+// data and comes out as `db`, so the output rebuilds byte for byte with
+//
+//   rgbasm -o game.o game.asm && rgblink -o game.gb game.o
+//
+// (no rgbfix: the header and its checksums are already in the data).
+
+const BANK_SIZE: usize = 0x4000;
+
+// the logo, title and the rest of the header between the entry jump and 0x0150
+const HEADER: std::ops::Range<usize> = 0x0104..0x0150;
+
+// MBC ROM bank select writes land in 0x2000-0x3FFF
+const BANK_SELECT: std::ops::RangeInclusive<u16> = 0x2000..=0x3FFF;
+
+const VECTORS: [(u16, &str); 14] = [
+    (0x0000, "RST_00"), (0x0008, "RST_08"), (0x0010, "RST_10"), (0x0018, "RST_18"),
+    (0x0020, "RST_20"), (0x0028, "RST_28"), (0x0030, "RST_30"), (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"), (0x0048, "LCDCInterrupt"), (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"), (0x0060, "JoypadInterrupt"), (0x0100, "Boot"),
+];
+
+const DATA_PER_LINE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Byte {
+    Unknown,
+    Opcode,  // first byte of an instruction
+    Operand, // the rest of it
+    Data,
+}
+
+// The source files for a ROM: `files` holds (name, contents), with the main file first
+pub struct Project {
+    pub files: Vec<(String, String)>,
+}
+
+struct Analysis<'a> {
+    rom: &'a [u8],
+    bytes: Vec<Byte>,
+    labels: HashMap<usize, String>, // ROM offset -> name
+    queue: Vec<(usize, Option<usize>)>, // ROM offset to trace from, and the bank mapped at 0x4000 then
+}
+
+fn offset(bank: usize, addr: u16) -> usize {
+    if addr < 0x4000 { addr as usize } else { bank * BANK_SIZE + (addr as usize - 0x4000) }
+}
+
+fn address(offset: usize) -> u16 {
+    if offset < BANK_SIZE { offset as u16 } else { (0x4000 + offset % BANK_SIZE) as u16 }
+}
+
+// jumps that don't fall through to the next instruction
+fn ends_block(instruction: &Disassembled) -> bool {
+    let conditional = instruction.operands.iter().any(|op| matches!(op, Operand::Condition(_)));
+    match instruction.mnemonic {
+        "jp" | "jr" | "ret" => !conditional,
+        "reti" | "db" => true,
+        _ => false,
+    }
+}
+
+impl<'a> Analysis<'a> {
+    fn new(rom: &'a [u8]) -> Self {
+        let mut bytes = vec![Byte::Unknown; rom.len()];
+        for i in HEADER.filter(|&i| i < rom.len()) {
+            bytes[i] = Byte::Data;
+        }
+        let mut analysis = Analysis { rom, bytes, labels: HashMap::new(), queue: Vec::new() };
+        for (addr, name) in VECTORS {
+            if (addr as usize) < rom.len() {
+                analysis.labels.insert(addr as usize, name.to_string());
+                analysis.queue.push((addr as usize, None));
+            }
+        }
+        analysis
+    }
+
+    // where an address in code from `bank` (with `selected` switched in) ends up in the ROM
+    fn resolve(&self, addr: u16, selected: Option<usize>) -> Option<usize> {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            0x4000..=0x7FFF => offset(selected?, addr),
+            _ => return None,
+        };
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    // vector names win over calls, calls over jumps and jumps over data
+    fn label(&mut self, offset: usize, kind: &str) {
+        let rank = |name: &str| ["Data_", "Jump_", "Call_"].iter().position(|p| name.starts_with(p)).unwrap_or(3);
+        let name = format!("{kind}_{:03X}_{:04X}", offset / BANK_SIZE, address(offset));
+        if self.labels.get(&offset).is_none_or(|existing| rank(existing) < rank(&name)) {
+            self.labels.insert(offset, name);
+        }
+    }
+
+    fn trace(&mut self) {
+        while let Some((start, selected)) = self.queue.pop() {
+            let bank = start / BANK_SIZE;
+            let bank_end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+            // code in a switchable bank runs with that bank mapped, wherever it came from
+            let mut selected = if bank > 0 { Some(bank) } else { selected };
+            let mut a: Option<u8> = None; // last constant loaded into A, for following bank switches
+
+            let mut pc = start;
+            while pc < bank_end && self.bytes[pc] == Byte::Unknown {
+                let instruction = disassemble(&self.rom[pc..bank_end], address(pc));
+                let length = instruction.length as usize;
+                // stops at the end of the bank, or where this would overlap something already decided
+                if pc + length > bank_end || self.bytes[pc..pc + length].iter().any(|&b| b != Byte::Unknown) {
+                    break;
+                }
+                if instruction.mnemonic == "db" {
+                    break;
+                }
+
+                self.bytes[pc] = Byte::Opcode;
+                for b in &mut self.bytes[pc + 1..pc + length] {
+                    *b = Byte::Operand;
+                }
+
+                match (instruction.mnemonic, instruction.operands.as_slice()) {
+                    ("ld", [Operand::Register("a"), Operand::Immediate8(n)]) => a = Some(*n),
+                    ("ld", [Operand::Address(addr), Operand::Register("a")]) if BANK_SELECT.contains(addr) => {
+                        selected = a.map(|n| (n as usize).max(1));
+                    }
+                    (_, [Operand::Register("a"), ..]) => a = None,
+                    _ => {}
+                }
+
+                if let Some(target) = instruction.target()
+                    && let Some(destination) = self.resolve(target, selected) {
+                    let kind = if matches!(instruction.mnemonic, "call" | "rst") { "Call" } else { "Jump" };
+                    if instruction.mnemonic != "rst" {
+                        self.label(destination, kind);
+                    }
+                    self.queue.push((destination, selected));
+                }
+                // reads from ROM are tables; writes to it are MBC registers and get no label
+                if let [_, Operand::Address(addr)] = instruction.operands.as_slice()
+                    && let Some(destination) = self.resolve(*addr, selected) {
+                    self.label(destination, "Data");
+                }
+
+                pc += length;
+                if ends_block(&instruction) {
+                    break;
+                }
+            }
+        }
+    }
+
+    // a label is only emitted where a line starts: at an instruction, or in data
+    fn placeable(&self, offset: usize) -> bool {
+        offset < self.bytes.len() && self.bytes[offset] != Byte::Operand
+    }
+
+    fn write_bank(&self, bank: usize) -> String {
+        let start = bank * BANK_SIZE;
+        let end = ((bank + 1) * BANK_SIZE).min(self.rom.len());
+        let mut out = String::new();
+        if bank == 0 {
+            out.push_str("SECTION \"ROM Bank $000\", ROM0[$0000]\n");
+        } else {
+            out.push_str(&format!("SECTION \"ROM Bank ${bank:03X}\", ROMX[$4000], BANK[${bank:X}]\n"));
+        }
+
+        let mut pc = start;
+        while pc < end {
+            if let Some(label) = self.labels.get(&pc).filter(|_| self.placeable(pc)) {
+                out.push_str(&format!("\n{label}:\n"));
+            }
+
+            if self.bytes[pc] == Byte::Opcode {
+                let instruction = disassemble(&self.rom[pc..end], address(pc));
+                let text = match (instruction.mnemonic, self.rom[pc]) {
+                    // rgbasm always pads stop with a zero byte
+                    ("stop", _) if self.rom[pc + 1] != 0 => format!("db $10, ${:02X}", self.rom[pc + 1]),
+                    // and turns ld to or from $FF00-$FFFF into ldh, which is a byte shorter
+                    ("ld", 0xEA | 0xFA) if self.rom[pc + 2] == 0xFF => {
+                        format!("db ${:02X}, ${:02X}, $FF", self.rom[pc], self.rom[pc + 1])
+                    }
+                    _ => instruction.text(Syntax::Rgbds, &|addr| self.operand_label(&instruction, bank, addr)),
+                };
+                out.push_str(&format!("    {text}\n"));
+                pc += instruction.length as usize;
+                continue;
+            }
+
+            // data runs until the next label or instruction
+            let mut run = pc + 1;
+            while run < end && run - pc < DATA_PER_LINE && self.bytes[run] != Byte::Opcode
+                && !self.labels.contains_key(&run) {
+                run += 1;
+            }
+            let bytes: Vec<String> = self.rom[pc..run].iter().map(|b| format!("${b:02X}")).collect();
+            out.push_str(&format!("    db {}\n", bytes.join(", ")));
+            pc = run;
+        }
+        out
+    }
+
+    // the name to write for an operand address, where that assembles back to the same bytes
+    fn operand_label(&self, instruction: &Disassembled, bank: usize, addr: u16) -> Option<String> {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            0x4000..=0x7FFF if bank > 0 => offset(bank, addr),
+            _ => return None,
+        };
+        // jr can't reach across sections
+        if instruction.mnemonic == "jr" && offset / BANK_SIZE != bank {
+            return None;
+        }
+        self.labels.get(&offset).filter(|_| self.placeable(offset)).cloned()
+    }
+}
+
+// Disassembles `rom` into a main file named `name`.asm that includes one file per bank
+pub fn disassemble_rom(rom: &[u8], name: &str) -> Project {
+    let mut analysis = Analysis::new(rom);
+    analysis.trace();
+
+    let banks = rom.len().div_ceil(BANK_SIZE);
+    let code = analysis.bytes.iter().filter(|&&b| b != Byte::Data && b != Byte::Unknown).count();
+
+    let mut main = format!("; disassembled from {name}: {banks} banks, {code} of {} bytes traced as code\n", rom.len());
+    main.push_str(&format!("; build with: rgbasm -o {name}.o {name}.asm && rgblink -o {name}.gb {name}.o\n\n"));
+
+    let mut files = Vec::new();
+    for bank in 0..banks {
+        let file = format!("bank_{bank:03x}.asm");
+        main.push_str(&format!("INCLUDE \"{file}\"\n"));
+        files.push((file, analysis.write_bank(bank)));
+    }
+    files.insert(0, (format!("{name}.asm"), main));
+    Project { files }
+}
@@ -0,0 +1,153 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::cpu::CPU;
+use crate::error::{EmulationError, Result};
+
+// Full-system save states: everything the CPU owns (registers, IME and HALT, the bus, cartridge
+// banks, RAM and clock, PPU, APU, timer, serial, joypad and SGB) serialized with bincode behind
+// a small header:
+//
+//   "GBSTATE\0"   magic
+//   u32 LE        format version, bumped whenever a serialized struct changes shape
+//   u32 LE        CRC-32 of the ROM the state was saved with, 0 without a cartridge
+//   ...           bincode payload
+//
+// The ROM itself isn't saved; loading takes it from the cartridge already inserted, and refuses
+// a state made with a different one. The serial device, the audio output buffer, watchpoints
+// and the LY stub belong to whoever is running the console and carry over from before the load.
+
+const MAGIC: &[u8; 8] = b"GBSTATE\0";
+pub const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 16;
+
+fn rom_crc(cpu: &CPU) -> u32 {
+    cpu.bus.cartridge.as_ref().map_or(0, |c| c.rom_crc)
+}
+
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&rom_crc(cpu).to_le_bytes());
+    bincode::serialize_into(&mut data, cpu).expect("save states serialize into memory");
+    data
+}
+
+// replaces `cpu` with the saved one, keeping what isn't part of a state
+pub fn load(cpu: &mut CPU, data: &[u8]) -> Result<()> {
+    let invalid = |message: &str| EmulationError::InvalidState(message.into());
+    if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+        return Err(invalid("not a save state"));
+    }
+    let word = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+    let version = word(8);
+    if version != VERSION {
+        return Err(EmulationError::InvalidState(format!("version {version}, this build reads version {VERSION}")));
+    }
+    if word(12) != rom_crc(cpu) {
+        return Err(invalid("saved with a different ROM"));
+    }
+
+    let mut saved: CPU = bincode::deserialize(&data[HEADER_SIZE..])
+        .map_err(|e| EmulationError::InvalidState(e.to_string()))?;
+
+    if let (Some(cartridge), Some(current)) = (&mut saved.bus.cartridge, &mut cpu.bus.cartridge) {
+        cartridge.rom = std::mem::take(&mut current.rom);
+        cartridge.rom_crc = current.rom_crc;
+    }
+    replace(cpu, saved);
+    Ok(())
+}
+
+// puts a loaded console in place of `cpu`. frontends hold pointers into the address space and
+// cartridge RAM (retro_get_memory_data), so the loaded contents are copied into those buffers
+// rather than the buffers being swapped out from under them
+pub(crate) fn replace(cpu: &mut CPU, mut loaded: CPU) {
+    keep_host_side(&mut loaded, cpu);
+
+    cpu.bus.memory.copy_from_slice(&loaded.bus.memory[..]);
+    std::mem::swap(&mut loaded.bus.memory, &mut cpu.bus.memory);
+    if let (Some(cartridge), Some(current)) = (&mut loaded.bus.cartridge, &mut cpu.bus.cartridge)
+        && cartridge.ram.len() == current.ram.len() {
+        current.ram.copy_from_slice(&cartridge.ram);
+        std::mem::swap(&mut cartridge.ram, &mut current.ram);
+    }
+    *cpu = loaded;
+}
+
+pub(crate) fn is_state(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+// moves what belongs to the host rather than the console over from `current` to `loaded`
+fn keep_host_side(loaded: &mut CPU, current: &mut CPU) {
+    let (loaded, current) = (&mut loaded.bus, &mut current.bus);
+    std::mem::swap(&mut loaded.serial.device, &mut current.serial.device);
+    std::mem::swap(&mut loaded.apu.output, &mut current.apu.output);
+    loaded.watchpoints = std::mem::take(&mut current.watchpoints);
+    loaded.ly_stub = current.ly_stub;
+}
+
+// serde only implements arrays up to 32 elements; these go through slices and Vecs instead.
+// `#[serde(with = "big_array")]` on a [T; N] field
+pub(crate) mod big_array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(array: &[T; N], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        array.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(deserializer: D) -> std::result::Result<[T; N], D::Error> {
+        let items = Vec::<T>::deserialize(deserializer)?;
+        let len = items.len();
+        items.try_into().map_err(|_| D::Error::invalid_length(len, &"an array of the saved size"))
+    }
+}
+
+// Box<[T; N]>, without the array ever being on the stack
+pub(crate) mod boxed_array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(array: &[T; N], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        array.as_slice().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(deserializer: D) -> std::result::Result<Box<[T; N]>, D::Error> {
+        let items = Vec::<T>::deserialize(deserializer)?;
+        let len = items.len();
+        items.into_boxed_slice().try_into().map_err(|_| D::Error::invalid_length(len, &"an array of the saved size"))
+    }
+}
+
+// Vec<[T; N]> of big arrays
+pub(crate) mod big_array_vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(arrays: &[[T; N]], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_seq(arrays.iter().map(|a| a.as_slice()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(deserializer: D) -> std::result::Result<Vec<[T; N]>, D::Error> {
+        Vec::<Vec<T>>::deserialize(deserializer)?.into_iter()
+            .map(|item| {
+                let len = item.len();
+                item.try_into().map_err(|_| D::Error::invalid_length(len, &"an array of the saved size"))
+            })
+            .collect()
+    }
+}
+
+// [[T; N]; M] with big inner arrays
+pub(crate) mod big_array_2d {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize, const M: usize>(arrays: &[[T; N]; M], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        big_array_vec::serialize(arrays, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize, const M: usize>(deserializer: D) -> std::result::Result<[[T; N]; M], D::Error> {
+        let arrays = big_array_vec::deserialize(deserializer)?;
+        let len = arrays.len();
+        arrays.try_into().map_err(|_| D::Error::invalid_length(len, &"the saved number of arrays"))
+    }
+}
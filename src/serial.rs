@@ -0,0 +1,157 @@
+use std::io::Write;
+
+use serde::{Serialize, Deserialize};
+
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+
+// T-cycles per bit on the internal clock: 8192 Hz, or 262144 Hz with the CGB fast clock bit.
+// these count CPU cycles, so double speed doubles the transfer rate as well
+const NORMAL_BIT_CYCLES: u32 = 512;
+const FAST_BIT_CYCLES: u32 = 16;
+
+// Whatever sits on the other end of the link cable
+pub trait SerialDevice {
+    // the Game Boy has clocked `byte` out on its internal clock; returns the byte shifted back in
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    // polled while the Game Boy waits on an external clock with `byte` in SB. returns the byte
+    // clocked in once the device has driven a full transfer
+    fn external_clock(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+
+    // lets a device keep its own notion of time, in CPU T-cycles
+    fn tick(&mut self, _cycles: u32) {}
+
+    // something for the frontend to show the user, handed over once
+    fn take_message(&mut self) -> Option<String> {
+        None
+    }
+}
+
+// No cable plugged in: the input line floats high, so every transfer reads 0xFF
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn exchange(&mut self, _byte: u8) -> u8 {
+        0xFF
+    }
+}
+
+// Echoes everything sent to stdout, which is how the test ROMs report results
+pub struct Console;
+
+impl SerialDevice for Console {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        print!("{}", byte as char);
+        std::io::stdout().flush().ok();
+        0xFF
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Serial {
+    #[serde(skip, default = "disconnected")]
+    pub device: Box<dyn SerialDevice>,
+    pub cgb: bool,
+    data: u8,    // SB
+    control: u8, // SC
+    bits_left: u8,
+    timer: u32,
+}
+
+fn disconnected() -> Box<dyn SerialDevice> {
+    Box::new(Disconnected)
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            device: Box::new(Disconnected),
+            cgb: false,
+            data: 0,
+            control: 0,
+            bits_left: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB => self.data,
+            // bit 1 (clock speed) only exists on CGB
+            _ => self.control | if self.cgb { 0x7C } else { 0x7E },
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB => self.data = value,
+            _ => {
+                self.control = value & if self.cgb { 0x83 } else { 0x81 };
+                if self.transferring() {
+                    self.bits_left = 8;
+                    self.timer = 0;
+                }
+            }
+        }
+    }
+
+    // SB and SC as a BESS state has them. the state doesn't say how far a transfer had got, so one
+    // in flight picks up with all eight bits still to go, and nothing is sent to the device
+    pub fn restore(&mut self, data: u8, control: u8) {
+        self.data = data;
+        self.control = control & if self.cgb { 0x83 } else { 0x81 };
+        self.bits_left = if self.transferring() { 8 } else { 0 };
+        self.timer = 0;
+    }
+
+    // advances an ongoing transfer, returns true when it completes (serial interrupt)
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        self.device.tick(cycles);
+
+        if !self.transferring() {
+            return false;
+        }
+
+        if !self.internal_clock() {
+            // the other side drives the clock and decides when the byte is done
+            return match self.device.external_clock(self.data) {
+                Some(byte) => {
+                    self.finish(byte);
+                    true
+                }
+                None => false
+            };
+        }
+
+        let bit_cycles = if self.control & 0x02 != 0 { FAST_BIT_CYCLES } else { NORMAL_BIT_CYCLES };
+        self.timer += cycles;
+        while self.timer >= bit_cycles && self.bits_left > 0 {
+            self.timer -= bit_cycles;
+            self.bits_left -= 1;
+        }
+
+        if self.bits_left == 0 {
+            let byte = self.device.exchange(self.data);
+            self.finish(byte);
+            return true;
+        }
+        false
+    }
+
+    fn finish(&mut self, byte: u8) {
+        self.data = byte;
+        self.control &= 0x7F;
+        self.bits_left = 0;
+    }
+
+    fn transferring(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    fn internal_clock(&self) -> bool {
+        self.control & 0x01 != 0
+    }
+}
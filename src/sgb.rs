@@ -0,0 +1,444 @@
+use crate::ppu::{self, SCREEN_WIDTH, SCREEN_HEIGHT};
+use crate::savestate::{big_array, big_array_vec};
+
+use serde::{Serialize, Deserialize};
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+// where the Game Boy screen sits inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// the attribute map colours the screen in 8x8 cells
+const CELLS_X: usize = SCREEN_WIDTH / 8;
+const CELLS_Y: usize = SCREEN_HEIGHT / 8;
+
+const TRANSFER_SIZE: usize = 0x1000;
+const ATF_SIZE: usize = 90;
+const ATF_COUNT: usize = 45;
+
+/* command codes, the top five bits of a packet's first byte */
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// the palette the SGB BIOS starts every game with (RGB555)
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Mask {
+    None,
+    Freeze, // keep showing the last frame
+    Black,
+    Color0, // fill the screen with colour 0
+}
+
+// VRAM transfers happen at the next VBlank, from whatever is on screen then
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Transfer {
+    Palettes,
+    Tiles(usize), // 0 or 1: which half of the 256 border tiles
+    Border,
+    Attributes,
+}
+
+// Super Game Boy: takes command packets the game pulses out over P14/P15, colours the DMG
+// screen with four palettes picked per 8x8 cell and draws it into a 256x224 frame with a border.
+#[derive(Serialize, Deserialize)]
+pub struct Sgb {
+    pub framebuffer: Vec<u32>, // SGB_WIDTH x SGB_HEIGHT, 0x00RRGGBB
+    pub frame_ready: bool,
+    pub mask: Mask,
+    pub buttons: [u8; 4], // joypad::Button bits per player
+    pub players: u8,
+    pub player: u8,       // the joypad P1 currently reads
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    #[serde(with = "big_array")]
+    attributes: [u8; CELLS_X * CELLS_Y],
+    #[serde(with = "big_array_vec")]
+    attribute_files: Vec<[u8; ATF_SIZE]>,
+    border_tiles: Vec<u8>,      // 256 SNES 4bpp tiles
+    border_map: Vec<u16>,       // 32x28 entries
+    border_palettes: [[u16; 16]; 4],
+    pending: Option<Transfer>,
+    // packet reception
+    receiving: bool,
+    packet: [u8; 16],
+    bits: usize,
+    pulse: Option<bool>,
+    command: Vec<u8>,
+    last_lines: u8,
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            framebuffer: vec![0; SGB_WIDTH * SGB_HEIGHT],
+            frame_ready: false,
+            mask: Mask::None,
+            buttons: [0; 4],
+            players: 1,
+            player: 0,
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; 512],
+            attributes: [0; CELLS_X * CELLS_Y],
+            attribute_files: vec![[0; ATF_SIZE]; ATF_COUNT],
+            border_tiles: vec![0; 256 * 32],
+            border_map: vec![0; 32 * 28],
+            border_palettes: [[0; 16]; 4],
+            pending: None,
+            receiving: false,
+            packet: [0; 16],
+            bits: 0,
+            pulse: None,
+            command: Vec::new(),
+            last_lines: 0x30,
+        }
+    }
+
+    // watches P1 writes for packet bits. a reset pulse (both lines low) starts a packet, then
+    // each bit is P14 low (0) or P15 low (1) followed by both high; 128 bits and a stop bit make
+    // a packet. returns whether the selected player changed
+    pub fn write_p1(&mut self, value: u8) -> bool {
+        let lines = value & 0x30;
+        let previous = std::mem::replace(&mut self.last_lines, lines);
+        let player = self.player;
+
+        match lines {
+            0x00 => {
+                self.receiving = true;
+                self.packet = [0; 16];
+                self.bits = 0;
+                self.pulse = None;
+            }
+            0x10 | 0x20 if self.receiving => self.pulse = Some(lines == 0x10),
+            0x30 if self.receiving => {
+                if let Some(bit) = self.pulse.take() {
+                    if self.bits < 128 {
+                        self.packet[self.bits / 8] |= (bit as u8) << (self.bits % 8);
+                        self.bits += 1;
+                    } else {
+                        self.receiving = false;
+                        self.packet_received();
+                    }
+                }
+            }
+            // in multiplayer mode, deselecting the buttons moves on to the next joypad
+            0x30 if previous == 0x10 && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+        self.player != player
+    }
+
+    // P1 with both rows deselected reports which joypad is up
+    pub fn multiplayer(&self) -> bool {
+        self.players > 1
+    }
+
+    fn packet_received(&mut self) {
+        if self.command.is_empty() && self.packet[0] & 0x07 == 0 {
+            return;
+        }
+        self.command.extend_from_slice(&self.packet);
+
+        let length = (self.command[0] & 0x07) as usize;
+        if self.command.len() >= length * 16 {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => {
+                for i in 0..4 {
+                    let index = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x1FF;
+                    self.palettes[i] = self.system_palettes[index as usize];
+                }
+                let attr = data[9];
+                if attr & 0x80 != 0 {
+                    self.apply_attribute_file(attr & 0x3F);
+                }
+                if attr & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            PAL_TRN => self.pending = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => self.pending = Some(Transfer::Tiles((data[1] & 0x01) as usize)),
+            PCT_TRN => self.pending = Some(Transfer::Border),
+            ATTR_TRN => self.pending = Some(Transfer::Attributes),
+            ATTR_SET => {
+                self.apply_attribute_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None,
+                };
+            }
+            // sound, SNES program uploads and the like aren't emulated
+            _ => {}
+        }
+    }
+
+    // colour 0 is shared by every palette, so it is written to all four
+    fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x7FFF;
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[a][i] = color(i);
+            self.palettes[b][i] = color(i + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+            // with only the inside or only the outside changed, the border follows it
+            let border = match control {
+                0x01 => inside,
+                0x04 => outside,
+                _ => (set[1] >> 2) & 0x03,
+            };
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_edge {
+                        (control & 0x02 != 0 || control == 0x01 || control == 0x04).then_some(border)
+                    } else if within {
+                        (control & 0x01 != 0).then_some(inside)
+                    } else {
+                        (control & 0x04 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * CELLS_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let sets = data[1] as usize;
+        for &line in data[2..].iter().take(sets) {
+            let index = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+            if line & 0x80 != 0 {
+                if index < CELLS_Y {
+                    self.attributes[index * CELLS_X..(index + 1) * CELLS_X].fill(palette);
+                }
+            } else if index < CELLS_X {
+                for y in 0..CELLS_Y {
+                    self.attributes[y * CELLS_X + index] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on_line = (data[1] >> 4) & 0x03;
+        let horizontal = data[1] & 0x40 != 0;
+        let split = data[2] as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * CELLS_X + x] = match position.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = data[1] as usize;
+        let mut y = data[2] as usize;
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(CELLS_X * CELLS_Y);
+        let vertical = data[5] & 0x01 != 0;
+
+        for i in 0..count {
+            let Some(&byte) = data.get(6 + i / 4) else { break };
+            if x >= CELLS_X || y >= CELLS_Y { break; }
+
+            self.attributes[y * CELLS_X + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+
+            if vertical {
+                y += 1;
+                if y == CELLS_Y { y = 0; x += 1; }
+            } else {
+                x += 1;
+                if x == CELLS_X { x = 0; y += 1; }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, index: u8) {
+        let Some(file) = self.attribute_files.get(index as usize) else { return };
+        for (i, cell) in self.attributes.iter_mut().enumerate() {
+            *cell = (file[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+        }
+    }
+
+    // called as the DMG screen enters VBlank with the shades of the frame it just drew
+    pub fn vblank(&mut self, shades: &[u8]) {
+        if let Some(transfer) = self.pending.take() {
+            let data = screen_to_tiles(shades);
+            match transfer {
+                Transfer::Palettes => {
+                    for (palette, bytes) in self.system_palettes.iter_mut().zip(data.chunks_exact(8)) {
+                        for (i, color) in palette.iter_mut().enumerate() {
+                            *color = u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]) & 0x7FFF;
+                        }
+                    }
+                }
+                Transfer::Tiles(half) => {
+                    self.border_tiles[half * TRANSFER_SIZE..(half + 1) * TRANSFER_SIZE].copy_from_slice(&data);
+                }
+                Transfer::Border => {
+                    for (entry, bytes) in self.border_map.iter_mut().zip(data.chunks_exact(2)) {
+                        *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    }
+                    // the border uses SNES palettes 4-7
+                    for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+                        for (j, color) in palette.iter_mut().enumerate() {
+                            let offset = 0x800 + i * 32 + j * 2;
+                            *color = u16::from_le_bytes([data[offset], data[offset + 1]]) & 0x7FFF;
+                        }
+                    }
+                }
+                Transfer::Attributes => {
+                    for (file, bytes) in self.attribute_files.iter_mut().zip(data.chunks_exact(ATF_SIZE)) {
+                        file.copy_from_slice(bytes);
+                    }
+                }
+            }
+        }
+
+        if self.mask != Mask::Freeze {
+            self.render(shades);
+            self.frame_ready = true;
+        }
+    }
+
+    fn render(&mut self, shades: &[u8]) {
+        let backdrop = ppu::rgb555(self.palettes[0][0]);
+
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let border = self.border_pixel(x, y);
+                let in_screen = (SCREEN_X..SCREEN_X + SCREEN_WIDTH).contains(&x)
+                    && (SCREEN_Y..SCREEN_Y + SCREEN_HEIGHT).contains(&y);
+
+                self.framebuffer[y * SGB_WIDTH + x] = match border {
+                    Some(color) => color,
+                    None if in_screen => self.screen_pixel(shades, x - SCREEN_X, y - SCREEN_Y, backdrop),
+                    None => backdrop,
+                };
+            }
+        }
+    }
+
+    fn screen_pixel(&self, shades: &[u8], x: usize, y: usize, backdrop: u32) -> u32 {
+        match self.mask {
+            Mask::Black => 0,
+            Mask::Color0 => backdrop,
+            _ => {
+                let shade = shades[y * SCREEN_WIDTH + x] as usize;
+                let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+                if shade == 0 { backdrop } else { ppu::rgb555(self.palettes[palette][shade]) }
+            }
+        }
+    }
+
+    // the border pixel at a point of the frame, None where colour 0 lets the screen show through
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u32> {
+        let entry = self.border_map[(y / 8) * 32 + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let palette = (((entry >> 10) & 0x07) as usize).saturating_sub(4);
+        let row = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        let bit = if entry & 0x4000 != 0 { x % 8 } else { 7 - x % 8 };
+
+        // SNES 4bpp tiles keep planes 0/1 in the first 16 bytes and planes 2/3 in the next
+        let base = tile * 32 + row * 2;
+        let planes = [
+            self.border_tiles[base],
+            self.border_tiles[base + 1],
+            self.border_tiles[base + 16],
+            self.border_tiles[base + 17],
+        ];
+        let index = planes.iter().enumerate()
+            .fold(0, |index, (plane, byte)| index | ((byte >> bit) & 1) << plane) as usize;
+
+        (index != 0).then(|| ppu::rgb555(self.border_palettes[palette][index]))
+    }
+}
+
+// VRAM transfers send 4 KB as the first 256 tiles on screen, read left to right, top to bottom.
+// taking them from the finished frame means scrolling and BGP apply, as they do on hardware
+fn screen_to_tiles(shades: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; TRANSFER_SIZE];
+    for tile in 0..256 {
+        let (tx, ty) = (tile % CELLS_X, tile / CELLS_X);
+        for row in 0..8 {
+            let (mut lo, mut hi) = (0u8, 0u8);
+            for px in 0..8 {
+                let shade = shades[(ty * 8 + row) * SCREEN_WIDTH + tx * 8 + px];
+                lo |= (shade & 1) << (7 - px);
+                hi |= ((shade >> 1) & 1) << (7 - px);
+            }
+            data[tile * 16 + row * 2] = lo;
+            data[tile * 16 + row * 2 + 1] = hi;
+        }
+    }
+    data
+}
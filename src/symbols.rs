@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{EmulationError, Result};
+
+// Symbol files as rgblink -n and no$gmb write them, one `bank:addr name` per line:
+//
+//   ; File generated by rgblink
+//   00:0150 Main
+//   00:0158 Main.loop
+//   01:4000 LoadTiles
+//
+// Addresses resolve to the nearest label at or below them in the same memory area, so code
+// inside a routine shows up as Main.loop+3. Anything that isn't a symbol line is skipped.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub addr: u16,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+    by_addr: BTreeMap<u16, Vec<usize>>, // indices into `symbols`, in file order
+}
+
+// where each memory area starts: a label never covers addresses past the end of its own area
+const AREAS: [u16; 12] = [0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFEA0, 0xFF00, 0xFF80, 0xFFFF];
+
+fn area(addr: u16) -> u16 {
+    AREAS.into_iter().rev().find(|&start| start <= addr).unwrap()
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::default();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let Some((location, name)) = line.split_once(char::is_whitespace) else { continue };
+            let Some((bank, addr)) = location.split_once(':') else { continue };
+            let (Ok(bank), Ok(addr)) = (usize::from_str_radix(bank, 16), u16::from_str_radix(addr, 16)) else { continue };
+            symbols.add(Symbol { bank, addr, name: name.trim().to_string() });
+        }
+        symbols
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Symbols> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| EmulationError::io(path.display().to_string(), e))?;
+        Ok(Symbols::parse(&text))
+    }
+
+    // game.sym next to game.gb, if there is one
+    pub fn beside(rom: impl AsRef<Path>) -> Option<Symbols> {
+        Symbols::load(rom.as_ref().with_extension("sym")).ok()
+    }
+
+    pub fn add(&mut self, symbol: Symbol) {
+        self.by_addr.entry(symbol.addr).or_default().push(self.symbols.len());
+        self.symbols.push(symbol);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    // "Label" or "Label+offset" (offset in decimal, as they're displayed) to a bank and address
+    pub fn resolve(&self, text: &str) -> Option<(usize, u16)> {
+        let (name, offset) = match text.rsplit_once('+') {
+            Some((name, offset)) => (name, offset.parse::<u16>().ok()?),
+            None => (text, 0),
+        };
+        self.get(name).map(|s| (s.bank, s.addr.wrapping_add(offset)))
+    }
+
+    // The labels at exactly this address. `bank` is the ROM bank mapped there: in the switchable
+    // area the bank has to match, and when it isn't known nothing there is named
+    fn at(&self, bank: Option<usize>, addr: u16) -> impl Iterator<Item = &Symbol> {
+        let indices = self.by_addr.get(&addr).map_or(&[][..], Vec::as_slice);
+        indices.iter().map(|&i| &self.symbols[i]).filter(move |s| {
+            !(0x4000..0x8000).contains(&addr) || bank == Some(s.bank)
+        })
+    }
+
+    pub fn name(&self, bank: Option<usize>, addr: u16) -> Option<&str> {
+        self.at(bank, addr).next().map(|s| s.name.as_str())
+    }
+
+    // the nearest label at or below the address in its area, as Label or Label+offset
+    pub fn describe(&self, bank: Option<usize>, addr: u16) -> Option<String> {
+        let start = area(addr);
+        self.by_addr.range(start..=addr).rev().find_map(|(&base, _)| {
+            let symbol = self.at(bank, base).next()?;
+            Some(match addr - base {
+                0 => symbol.name.clone(),
+                offset => format!("{}+{offset}", symbol.name),
+            })
+        })
+    }
+}
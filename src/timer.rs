@@ -0,0 +1,63 @@
+use serde::{Serialize, Deserialize};
+
+pub const DIV: usize = 0xFF04;
+pub const TIMA: usize = 0xFF05;
+pub const TMA: usize = 0xFF06;
+pub const TAC: usize = 0xFF07;
+
+// DIV is the upper byte of a 16-bit counter that runs off the CPU clock,
+// so it (and TIMA with it) speeds up in CGB double speed mode
+#[derive(Serialize, Deserialize)]
+pub struct Timer {
+    pub counter: u16,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer { counter: 0 }
+    }
+
+    pub fn div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    // any write to DIV clears the whole counter, which can clock TIMA early
+    pub fn reset_div(&mut self, memory: &mut [u8; 0x10000]) {
+        if self.timer_input(memory[TAC]) {
+            Timer::increment_tima(memory);
+        }
+        self.counter = 0;
+    }
+
+    pub fn tick(&mut self, cycles: u32, memory: &mut [u8; 0x10000]) {
+        // the selected counter bits toggle at most every 8 cycles, so stepping by 4 catches every edge
+        for _ in 0..cycles / 4 {
+            let before = self.timer_input(memory[TAC]);
+            self.counter = self.counter.wrapping_add(4);
+            if before && !self.timer_input(memory[TAC]) {
+                Timer::increment_tima(memory);
+            }
+        }
+    }
+
+    // TIMA counts falling edges of one counter bit, gated by the TAC enable bit
+    fn timer_input(&self, tac: u8) -> bool {
+        let bit = match tac & 0x03 {
+            0b00 => 9,  // 4096 Hz
+            0b01 => 3,  // 262144 Hz
+            0b10 => 5,  // 65536 Hz
+            _ => 7,     // 16384 Hz
+        };
+        tac & 0x04 != 0 && (self.counter >> bit) & 1 != 0
+    }
+
+    fn increment_tima(memory: &mut [u8; 0x10000]) {
+        let (tima, overflow) = memory[TIMA].overflowing_add(1);
+        if overflow {
+            memory[TIMA] = memory[TMA];
+            memory[0xFF0F] |= 0x04;
+        } else {
+            memory[TIMA] = tima;
+        }
+    }
+}
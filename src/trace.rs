@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::cpu::CPU;
+use crate::error::{EmulationError, Result};
+use crate::gameboy::GameBoy;
+use crate::gzip::GzipWriter;
+use crate::symbols::Symbols;
+
+// An execution trace in the format Gameboy Doctor (and the logs other emulators publish)
+// compares against, one line per instruction before it runs:
+//
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// Interrupt dispatches and HALTed cycles aren't instructions and don't get a line. The reference
+// logs are taken with LY always reading 0x90, which is what `LY_STUB` is for. With symbols the
+// line ends in a comment naming the label PC is under, which the reference logs won't have.
+
+pub const LY_STUB: u8 = 0x90;
+
+pub fn line(cpu: &CPU) -> String {
+    let r = &cpu.registers;
+    let pc = cpu.pc;
+    let mem = |offset: u16| cpu.bus.peek(pc.wrapping_add(offset));
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{pc:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        r.a, u8::from(r.f), r.b, r.c, r.d, r.e, r.h, r.l, cpu.sp(), mem(0), mem(1), mem(2), mem(3),
+    )
+}
+
+enum Output {
+    Plain(Box<dyn Write>),
+    Gzip(GzipWriter<Box<dyn Write>>),
+}
+
+pub struct Trace {
+    out: Output,
+    path: String, // for errors
+    symbols: Option<Symbols>,
+}
+
+impl Trace {
+    // `path` is only used to name the trace in errors
+    pub fn new(out: Box<dyn Write>, path: impl Into<String>, gzip: bool) -> Result<Trace> {
+        let path = path.into();
+        let out = if gzip {
+            Output::Gzip(GzipWriter::new(out).map_err(|e| EmulationError::io(path.clone(), e))?)
+        } else {
+            Output::Plain(out)
+        };
+        Ok(Trace { out, path, symbols: None })
+    }
+
+    // a path ending in .gz is gzipped as it's written
+    pub fn create(path: &str) -> Result<Trace> {
+        let file = File::create(path).map_err(|e| EmulationError::io(path, e))?;
+        Trace::new(Box::new(BufWriter::new(file)), path, path.ends_with(".gz"))
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    // logs the instruction about to run, if it is one, then steps
+    pub fn step(&mut self, gb: &mut GameBoy) -> Result<u32> {
+        if gb.cpu.executes_next() {
+            let mut line = line(&gb.cpu);
+            let pc = gb.cpu.pc;
+            if let Some(label) = self.symbols.as_ref().and_then(|s| s.describe(gb.rom_bank(pc), pc)) {
+                line = format!("{line} ; {label}");
+            }
+            let written = match &mut self.out {
+                Output::Plain(out) => writeln!(out, "{line}"),
+                Output::Gzip(out) => writeln!(out, "{line}"),
+            };
+            written.map_err(|e| self.error(e))?;
+        }
+        gb.step()
+    }
+
+    // writes out whatever is buffered, and the gzip trailer
+    pub fn finish(self) -> Result<()> {
+        let flushed = match self.out {
+            Output::Plain(mut out) => out.flush(),
+            Output::Gzip(out) => out.finish().and_then(|mut out| out.flush()),
+        };
+        flushed.map_err(|e| EmulationError::io(self.path, e))
+    }
+
+    fn error(&self, e: io::Error) -> EmulationError {
+        EmulationError::io(self.path.clone(), e)
+    }
+}
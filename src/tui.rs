@@ -0,0 +1,277 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::{cursor, execute, terminal};
+
+use gb_emulator::error::{EmulationError, Result};
+use gb_emulator::joypad::Button;
+use gb_emulator::rewind::Rewind;
+use gb_emulator::sgb::{SGB_HEIGHT, SGB_WIDTH};
+use gb_emulator::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use gb_emulator::GameBoy;
+
+// 70224 T-cycles at 4194304 Hz, which is 59.73 frames a second
+const FRAME_TIME: Duration = Duration::from_nanos(16_742_706);
+
+// when falling further behind than this, stop trying to catch up
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// most terminals only report key presses, so a press holds its button until the key repeats.
+// this has to outlast the repeat delay, which is usually 250-500ms
+const HOLD_FRAMES: u32 = 30;
+
+// how long "saved to slot 1" and the like stay in the status line
+const MESSAGE_FRAMES: u32 = 120;
+
+// Puts the terminal into raw mode on an alternate screen, and back again when dropped, so an
+// error or panic mid-game doesn't leave the shell unusable
+struct Screen {
+    key_releases: bool, // the terminal reports key releases, so buttons don't need HOLD_FRAMES
+}
+
+impl Screen {
+    fn open() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_releases {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Screen { key_releases })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.key_releases {
+            execute!(out, PopKeyboardEnhancementFlags).ok();
+        }
+        execute!(out, cursor::Show, terminal::LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+// arrows for the d-pad, X and Z for A and B, Enter for Start and Backspace for Select
+fn button(code: KeyCode) -> Option<Button> {
+    match code {
+        KeyCode::Right => Some(Button::Right),
+        KeyCode::Left => Some(Button::Left),
+        KeyCode::Up => Some(Button::Up),
+        KeyCode::Down => Some(Button::Down),
+        KeyCode::Char('x' | 'X') => Some(Button::A),
+        KeyCode::Char('z' | 'Z') => Some(Button::B),
+        KeyCode::Backspace | KeyCode::Char(' ') => Some(Button::Select),
+        KeyCode::Enter => Some(Button::Start),
+        _ => None,
+    }
+}
+
+fn quits(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+// Draws frames as rows of '▀', the foreground colour painting the upper pixel and the
+// background the lower one, so each character cell covers two pixel rows. Only the cells that
+// changed since the last frame are sent, which keeps it usable over SSH.
+struct Renderer {
+    width: usize,
+    height: usize,
+    previous: Vec<u64>, // top and bottom pixel per cell, u64::MAX when unknown
+    out: String,
+}
+
+impl Renderer {
+    fn new() -> Self {
+        Renderer { width: 0, height: 0, previous: Vec::new(), out: String::new() }
+    }
+
+    fn draw(&mut self, pixels: &[u32], width: usize, height: usize, status: &str) -> io::Result<()> {
+        let rows = height.div_ceil(2);
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.previous = vec![u64::MAX; width * rows];
+            self.out.push_str("\x1b[2J");
+        }
+
+        let mut colours = (u32::MAX, u32::MAX);
+        for row in 0..rows {
+            let mut in_place = false;
+            for x in 0..width {
+                let top = pixels[row * 2 * width + x];
+                let bottom = if row * 2 + 1 < height { pixels[(row * 2 + 1) * width + x] } else { 0 };
+                let cell = ((top as u64) << 32) | bottom as u64;
+                if self.previous[row * width + x] == cell {
+                    in_place = false;
+                    continue;
+                }
+                self.previous[row * width + x] = cell;
+
+                if !in_place {
+                    write!(self.out, "\x1b[{};{}H", row + 1, x + 1).ok();
+                    in_place = true;
+                }
+                if colours.0 != top {
+                    write!(self.out, "\x1b[38;2;{};{};{}m", top >> 16, (top >> 8) & 0xFF, top & 0xFF).ok();
+                }
+                if colours.1 != bottom {
+                    write!(self.out, "\x1b[48;2;{};{};{}m", bottom >> 16, (bottom >> 8) & 0xFF, bottom & 0xFF).ok();
+                }
+                colours = (top, bottom);
+                self.out.push('▀');
+            }
+        }
+
+        write!(self.out, "\x1b[0m\x1b[{};1H\x1b[2K{status}", rows + 1).ok();
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(self.out.as_bytes())?;
+        stdout.flush()?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+// how long a press counts as held, forever on terminals that will report the release
+fn hold_frames(key_releases: bool) -> u32 {
+    if key_releases { u32::MAX } else { HOLD_FRAMES }
+}
+
+// s saves to the selected slot, l loads from it
+fn save_or_load(gb: &mut GameBoy, rewind: &mut Option<Rewind>, code: KeyCode, slot: u8, path: PathBuf) -> Option<String> {
+    match code {
+        KeyCode::Char('s') => Some(match std::fs::write(&path, gb.save_state()) {
+            Ok(()) => format!("saved slot {slot}"),
+            Err(e) => format!("slot {slot}: {e}"),
+        }),
+        KeyCode::Char('l') => Some(match std::fs::read(&path) {
+            Ok(data) => match gb.load_state(&data) {
+                Ok(()) => {
+                    // the frames recorded so far led somewhere else
+                    if let Some(rewind) = rewind {
+                        rewind.clear();
+                    }
+                    format!("loaded slot {slot}")
+                }
+                Err(e) => format!("slot {slot}: {e}"),
+            },
+            Err(_) => format!("slot {slot} is empty"),
+        }),
+        _ => None,
+    }
+}
+
+// Plays `gb` in the terminal until Esc, q or Ctrl-C. Digits pick a save state slot, which
+// `slot_path` names the file for, and holding r runs backwards through what `rewind` recorded.
+// `on_frame` runs after every frame with the frame count, for things like flushing the save file.
+pub fn run(gb: &mut GameBoy, slot_path: impl Fn(u8) -> PathBuf, mut rewind: Option<Rewind>,
+    mut on_frame: impl FnMut(&mut GameBoy, u64) -> Result<()>) -> Result<()> {
+    let screen = Screen::open().map_err(|e| EmulationError::io("terminal", e))?;
+    let mut renderer = Renderer::new();
+    let title = gb.header().map(|h| h.title.clone()).unwrap_or_default();
+
+    let mut held = [0u32; 8]; // frames left on each button, indexed by its P1 bit
+    let mut rewinding = 0;    // and on the rewind key
+    let mut next_frame = Instant::now();
+    let mut fps_start = Instant::now();
+    let mut fps_frames = 0;
+    let mut fps = 0.0;
+    let mut slot = 0;
+    let mut message = (String::new(), 0); // and the frames it has left
+
+    for frame in 1.. {
+        while event::poll(Duration::ZERO).map_err(|e| EmulationError::io("terminal", e))? {
+            let Event::Key(key) = event::read().map_err(|e| EmulationError::io("terminal", e))? else { continue };
+            if quits(&key) {
+                return Ok(());
+            }
+            if key.kind == KeyEventKind::Press {
+                if let KeyCode::Char(digit @ '0'..='9') = key.code {
+                    slot = digit as u8 - b'0';
+                    message = (format!("slot {slot}"), MESSAGE_FRAMES);
+                } else if let Some(text) = save_or_load(gb, &mut rewind, key.code, slot, slot_path(slot)) {
+                    message = (text, MESSAGE_FRAMES);
+                }
+            }
+            if key.code == KeyCode::Char('r') && rewind.is_some() {
+                rewinding = if key.kind == KeyEventKind::Release { 0 } else { hold_frames(screen.key_releases) };
+            }
+            if let Some(button) = button(key.code) {
+                held[button as usize] = match key.kind {
+                    KeyEventKind::Release => 0,
+                    _ => hold_frames(screen.key_releases),
+                };
+            }
+        }
+
+        let pressed = held.iter().enumerate().filter(|(_, frames)| **frames > 0).fold(0, |mask, (bit, _)| mask | (1 << bit));
+        gb.set_buttons(pressed);
+        for frames in held.iter_mut().filter(|frames| **frames != u32::MAX) {
+            *frames = frames.saturating_sub(1);
+        }
+
+        match &mut rewind {
+            Some(rewind) if rewinding > 0 => {
+                if rewinding != u32::MAX {
+                    rewinding -= 1;
+                }
+                if !rewind.step_back(gb)? {
+                    message = ("nothing further back to rewind to".into(), MESSAGE_FRAMES);
+                }
+            }
+            _ => {
+                gb.run_frame()?;
+                if let Some(rewind) = &mut rewind {
+                    rewind.record(gb);
+                }
+            }
+        }
+        on_frame(gb, frame)?;
+        if let Some(text) = gb.take_serial_message() {
+            message = (text, MESSAGE_FRAMES);
+        }
+
+        fps_frames += 1;
+        if fps_start.elapsed() >= Duration::from_secs(1) {
+            fps = fps_frames as f64 / fps_start.elapsed().as_secs_f64();
+            fps_start = Instant::now();
+            fps_frames = 0;
+        }
+        let status = if let Some(rewind) = &rewind && rewinding > 0 {
+            format!("{title}  rewinding, {:.1}s left", rewind.frames() as f64 / 60.0)
+        } else if message.1 > 0 {
+            message.1 -= 1;
+            format!("{title}  {fps:.1} fps  {}", message.0)
+        } else {
+            format!("{title}  {fps:.1} fps  arrows: d-pad  x/z: A/B  enter: start  backspace: select  \
+                0-9/s/l: state slot {slot}{}  q: quit", if rewind.is_some() { "  r: rewind" } else { "" })
+        };
+
+        let result = match gb.sgb() {
+            Some(sgb) => renderer.draw(&sgb.framebuffer, SGB_WIDTH, SGB_HEIGHT, &status),
+            None => renderer.draw(gb.framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT, &status),
+        };
+        result.map_err(|e| EmulationError::io("terminal", e))?;
+
+        // sleep off what's left of the frame, or skip ahead if the terminal has fallen behind
+        next_frame += FRAME_TIME;
+        let now = Instant::now();
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+        } else if now - next_frame > MAX_LAG {
+            next_frame = now;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use gb_emulator::apu::Apu;
+
+const NR10: u16 = 0xFF10;
+const NR11: u16 = 0xFF11;
+const NR12: u16 = 0xFF12;
+const NR13: u16 = 0xFF13;
+const NR14: u16 = 0xFF14;
+const NR30: u16 = 0xFF1A;
+const NR34: u16 = 0xFF1E;
+const NR42: u16 = 0xFF21;
+const NR43: u16 = 0xFF22;
+const NR44: u16 = 0xFF23;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const WAVE_RAM: u16 = 0xFF30;
+
+fn powered() -> Apu {
+    let mut apu = Apu::new();
+    apu.write(NR52, 0x80);
+    apu
+}
+
+// the channel on bits of NR52
+fn playing(apu: &Apu) -> u8 {
+    apu.read(NR52) & 0x0F
+}
+
+#[test]
+fn length_counters_silence_a_channel_when_they_run_out() {
+    let mut apu = powered();
+    apu.write(NR12, 0xF0);
+    // 62 of 64 already gone, so two length clocks are left
+    apu.write(NR11, 62);
+    apu.write(NR14, 0xC0);
+    assert_eq!(playing(&apu), 0x01);
+
+    // steps 0 and 2 of the frame sequencer clock lengths, step 1 doesn't
+    apu.frame_sequencer_step();
+    apu.frame_sequencer_step();
+    assert_eq!(playing(&apu), 0x01);
+    apu.frame_sequencer_step();
+    assert_eq!(playing(&apu), 0x00);
+
+    // without the length enable bit the same note plays on
+    apu.write(NR11, 62);
+    apu.write(NR14, 0x80);
+    for _ in 0..16 {
+        apu.frame_sequencer_step();
+    }
+    assert_eq!(playing(&apu), 0x01);
+}
+
+#[test]
+fn sweep_overflow_disables_channel_1() {
+    // frequency $500 plus half of itself fits in 11 bits, but the check after the update doesn't
+    let mut apu = powered();
+    apu.write(NR10, 0x11);
+    apu.write(NR12, 0xF0);
+    apu.write(NR13, 0x00);
+    apu.write(NR14, 0x85);
+    assert_eq!(playing(&apu), 0x01);
+    for _ in 0..3 {
+        apu.frame_sequencer_step();
+    }
+    assert_eq!(playing(&apu), 0x00);
+
+    // one that overflows straight away is off as soon as it's triggered
+    apu.write(NR13, 0xFF);
+    apu.write(NR14, 0x87);
+    assert_eq!(playing(&apu), 0x00);
+}
+
+#[test]
+fn powering_off_clears_the_registers() {
+    let mut apu = powered();
+    apu.write(NR11, 0x80);
+    apu.write(NR12, 0xF3);
+    apu.write(NR50, 0x77);
+    apu.write(NR51, 0xFF);
+    apu.write(NR14, 0x80);
+    apu.write(WAVE_RAM, 0x12);
+
+    apu.write(NR52, 0x00);
+    assert_eq!(apu.read(NR52), 0x70);
+    assert_eq!(apu.read(NR11), 0x3F);
+    assert_eq!(apu.read(NR12), 0x00);
+    assert_eq!(apu.read(NR50), 0x00);
+    assert_eq!(apu.read(NR51), 0x00);
+
+    // writes go nowhere until it's back on, except to wave RAM, which power doesn't touch
+    apu.write(NR50, 0x77);
+    apu.write(WAVE_RAM + 1, 0x34);
+    apu.write(NR52, 0x80);
+    assert_eq!(apu.read(NR50), 0x00);
+    assert_eq!(apu.read(WAVE_RAM), 0x12);
+    assert_eq!(apu.read(WAVE_RAM + 1), 0x34);
+}
+
+#[test]
+fn wave_ram_is_only_reachable_through_the_playing_byte() {
+    for cgb in [false, true] {
+        let mut apu = powered();
+        apu.cgb = cgb;
+        for i in 0..16 {
+            apu.write(WAVE_RAM + i, i as u8 * 0x11);
+        }
+        assert_eq!(apu.read(WAVE_RAM + 5), 0x55);
+
+        apu.write(NR30, 0x80);
+        apu.write(NR34, 0x87);
+        // the CGB shows the byte being played whatever the address, the DMG only as it's fetched
+        assert_eq!(apu.read(WAVE_RAM + 5), if cgb { 0x00 } else { 0xFF });
+        apu.write(WAVE_RAM + 5, 0xAB);
+
+        apu.write(NR30, 0x00);
+        assert_eq!(apu.read(WAVE_RAM), if cgb { 0xAB } else { 0x00 });
+        assert_eq!(apu.read(WAVE_RAM + 5), 0x55);
+    }
+}
+
+// the noise channel's output after each LFSR shift, with the fastest clock so it shifts every 8 cycles
+fn noise(width_7: bool, shifts: usize) -> Vec<u8> {
+    let mut apu = powered();
+    apu.write(NR42, 0xF0);
+    apu.write(NR43, if width_7 { 0x08 } else { 0x00 });
+    apu.write(NR44, 0x80);
+    (0..shifts).map(|_| {
+        apu.tick(8);
+        apu.channel_outputs()[3]
+    }).collect()
+}
+
+fn repeats_every(samples: &[u8], period: usize) -> bool {
+    samples.iter().zip(&samples[period..]).all(|(a, b)| a == b)
+}
+
+#[test]
+fn noise_lfsr_repeats_after_127_or_32767_shifts() {
+    let short = noise(true, 127 * 3);
+    assert!(repeats_every(&short, 127));
+    assert!(short.contains(&0) && short.contains(&15));
+
+    let long = noise(false, 32767 + 1000);
+    assert!(repeats_every(&long, 32767));
+    assert!(!repeats_every(&long, 127));
+}
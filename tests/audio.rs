@@ -0,0 +1,69 @@
+use gb_emulator::audio::{AudioBuffer, CLOCK_RATE};
+
+const FRAME_CYCLES: u32 = 70224;
+
+// runs `frames` frames with nothing playing and drains what they produce, left channel only
+fn run(buffer: &mut AudioBuffer, frames: usize) -> Vec<f32> {
+    let mut out = Vec::new();
+    let mut samples = vec![0.0; 4096];
+    for _ in 0..frames {
+        buffer.end_frame(FRAME_CYCLES);
+        loop {
+            let read = buffer.read_samples_f32(&mut samples);
+            if read == 0 {
+                break;
+            }
+            out.extend(samples[..read * 2].iter().step_by(2));
+        }
+    }
+    out
+}
+
+#[test]
+fn output_keeps_to_the_sample_rate() {
+    for rate in [32000, 44100, 48000] {
+        let mut buffer = AudioBuffer::new(rate);
+        let frames = 600;
+        let produced = run(&mut buffer, frames).len() as f64;
+        let expected = frames as f64 * FRAME_CYCLES as f64 * rate as f64 / CLOCK_RATE as f64;
+        // whatever is left over is a fraction of one sample
+        assert!((produced - expected).abs() < 1.0, "{rate} Hz: {produced} samples, expected {expected}");
+    }
+}
+
+#[test]
+fn a_step_comes_out_at_full_height_then_decays_to_zero() {
+    let mut buffer = AudioBuffer::new(48000);
+    buffer.add_delta(0, 0.5, 0.5);
+    let samples = run(&mut buffer, 60);
+
+    let peak = samples.iter().cloned().fold(0.0, f32::max);
+    assert!((peak - 0.5).abs() < 0.05, "peak {peak}");
+    // the DC blocking filter pulls a held level back to 0 within a few tens of milliseconds
+    assert!(samples[48000 / 2].abs() < 0.001, "still at {} after half a second", samples[48000 / 2]);
+}
+
+#[test]
+fn the_cgb_filter_discharges_faster() {
+    let held = |cgb: bool| {
+        let mut buffer = AudioBuffer::new(48000);
+        buffer.set_cgb(cgb);
+        buffer.add_delta(0, 0.5, 0.5);
+        run(&mut buffer, 1)[20]
+    };
+    let (dmg, cgb) = (held(false), held(true));
+    assert!(cgb < dmg, "cgb {cgb} should be below dmg {dmg}");
+    assert!(cgb > 0.0);
+}
+
+#[test]
+fn loading_a_rom_keeps_the_sample_rate() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]); // jr $0100
+    let mut gb = gb_emulator::GameBoy::new();
+    gb.set_sample_rate(32000);
+    gb.load(rom.clone()).unwrap();
+    assert_eq!(gb.cpu.bus.apu.output.sample_rate(), 32000);
+    gb.load(rom).unwrap();
+    assert_eq!(gb.cpu.bus.apu.output.sample_rate(), 32000);
+}
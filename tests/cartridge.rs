@@ -0,0 +1,119 @@
+use gb_emulator::cartridge::Cartridge;
+
+const SECOND: u32 = 4194304;
+
+// a ROM of `banks` 16 KB banks whose first two bytes hold the bank's own number
+fn cartridge(cartridge_type: u8, banks: usize) -> Cartridge {
+    let mut rom = vec![0; banks * 0x4000];
+    for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+        data[..2].copy_from_slice(&(bank as u16).to_le_bytes());
+    }
+    rom[0x147] = cartridge_type;
+    rom[0x149] = 0x03; // 32 KB of RAM
+    Cartridge::new(rom).unwrap()
+}
+
+fn bank(cartridge: &Cartridge, addr: u16) -> u16 {
+    u16::from_le_bytes([cartridge.read(addr), cartridge.read(addr + 1)])
+}
+
+#[test]
+fn mbc1_maps_bank_0_as_1_and_adds_the_upper_bits() {
+    let mut cart = cartridge(0x03, 128);
+    assert_eq!(bank(&cart, 0x4000), 1);
+
+    cart.write(0x2000, 0x00);
+    assert_eq!(bank(&cart, 0x4000), 1);
+    cart.write(0x2000, 0x05);
+    assert_eq!(bank(&cart, 0x4000), 5);
+    // only five bits reach the register, so 0x20 is bank 0 again and reads as 1
+    cart.write(0x2000, 0x20);
+    assert_eq!(bank(&cart, 0x4000), 1);
+
+    // the 2-bit register supplies bits 5-6, and the 0 to 1 step only looks at the low five
+    cart.write(0x4000, 0x01);
+    assert_eq!(bank(&cart, 0x4000), 0x21);
+    cart.write(0x4000, 0x03);
+    cart.write(0x2000, 0x1F);
+    assert_eq!(bank(&cart, 0x4000), 0x7F);
+}
+
+#[test]
+fn mbc1_mode_1_banks_the_low_area_and_ram() {
+    let mut cart = cartridge(0x03, 128);
+    cart.write(0x0000, 0x0A);
+    cart.write(0x4000, 0x02);
+
+    // mode 0: the low area stays on bank 0 and RAM on bank 0
+    assert_eq!(bank(&cart, 0x0000), 0);
+    cart.write(0xA000, 0x11);
+
+    // mode 1: both follow the 2-bit register
+    cart.write(0x6000, 0x01);
+    assert_eq!(bank(&cart, 0x0000), 0x40);
+    assert_eq!(cart.read(0xA000), 0x00);
+    cart.write(0xA000, 0x22);
+
+    cart.write(0x6000, 0x00);
+    assert_eq!(bank(&cart, 0x0000), 0);
+    assert_eq!(cart.read(0xA000), 0x11);
+    assert_eq!(bank(&cart, 0x4000), 0x41);
+}
+
+#[test]
+fn mbc3_maps_bank_0_as_1_and_has_seven_bits() {
+    let mut cart = cartridge(0x10, 128);
+    cart.write(0x2000, 0x00);
+    assert_eq!(bank(&cart, 0x4000), 1);
+    cart.write(0x2000, 0x7F);
+    assert_eq!(bank(&cart, 0x4000), 0x7F);
+    cart.write(0x2000, 0x80);
+    assert_eq!(bank(&cart, 0x4000), 1);
+}
+
+#[test]
+fn mbc3_clock_reads_stay_put_until_latched() {
+    let mut cart = cartridge(0x10, 4);
+    cart.write(0x0000, 0x0A);
+    cart.write(0x4000, 0x08); // seconds
+    cart.write(0xA000, 5);
+    assert_eq!(cart.read(0xA000), 5);
+
+    cart.tick(2 * SECOND);
+    assert_eq!(cart.read(0xA000), 5);
+
+    // a 0 then a 1 latches
+    cart.write(0x6000, 0x00);
+    cart.write(0x6000, 0x01);
+    assert_eq!(cart.read(0xA000), 7);
+
+    // a 1 on its own doesn't
+    cart.tick(SECOND);
+    cart.write(0x6000, 0x01);
+    assert_eq!(cart.read(0xA000), 7);
+
+    // halting stops the count; the latch still picks up the halt bit
+    cart.write(0x4000, 0x0C);
+    cart.write(0xA000, 0x40);
+    cart.tick(5 * SECOND);
+    cart.write(0x6000, 0x00);
+    cart.write(0x6000, 0x01);
+    assert_eq!(cart.read(0xA000) & 0x40, 0x40);
+    cart.write(0x4000, 0x08);
+    assert_eq!(cart.read(0xA000), 8);
+}
+
+#[test]
+fn mbc5_has_a_real_bank_0_and_a_ninth_bit() {
+    let mut cart = cartridge(0x1B, 512);
+    cart.write(0x2000, 0x00);
+    assert_eq!(bank(&cart, 0x4000), 0);
+    cart.write(0x2000, 0xFF);
+    assert_eq!(bank(&cart, 0x4000), 0xFF);
+    cart.write(0x3000, 0x01);
+    assert_eq!(bank(&cart, 0x4000), 0x1FF);
+    cart.write(0x2000, 0x00);
+    assert_eq!(bank(&cart, 0x4000), 0x100);
+    cart.write(0x3000, 0x00);
+    assert_eq!(bank(&cart, 0x4000), 0);
+}
@@ -0,0 +1,139 @@
+use gb_emulator::GameBoy;
+use gb_emulator::memory::{HDMA_BLOCK_CYCLES, MemoryBus, SPEED_SWITCH_CYCLES};
+use gb_emulator::ppu::LCDC;
+
+const KEY1: u16 = 0xFF4D;
+const HDMA1: u16 = 0xFF51;
+const HDMA5: u16 = 0xFF55;
+
+// dots from the LCD coming on to the first line's HBlank, and for a whole line
+const FIRST_HBLANK: u32 = 80 + 172;
+const LINE: u32 = 456;
+
+// a CGB bus with 64 numbered bytes at $C000 and HDMA pointed from there to $8000
+fn bus(double_speed: bool) -> MemoryBus {
+    let mut bus = MemoryBus::new();
+    bus.cgb_mode = true;
+    bus.double_speed = double_speed;
+    for i in 0..0x40 {
+        bus.write_byte(0xC000 + i, i as u8 + 1);
+    }
+    for (i, value) in [0xC0, 0x00, 0x80, 0x00].into_iter().enumerate() {
+        bus.write_byte(HDMA1 + i as u16, value);
+    }
+    bus
+}
+
+fn vram(bus: &MemoryBus, len: u16) -> Vec<u8> {
+    (0..len).map(|i| bus.peek(0x8000 + i)).collect()
+}
+
+fn numbered(len: u8) -> Vec<u8> {
+    (1..=len).collect()
+}
+
+#[test]
+fn general_purpose_dma_copies_at_once_and_stalls_per_block() {
+    for double_speed in [false, true] {
+        let mut bus = bus(double_speed);
+        bus.write_byte(HDMA5, 0x03);
+        assert_eq!(vram(&bus, 0x40), numbered(0x40));
+        assert_eq!(bus.read_byte(HDMA5), 0xFF);
+        // the same real time in double speed is twice the CPU cycles
+        let block = if double_speed { HDMA_BLOCK_CYCLES * 2 } else { HDMA_BLOCK_CYCLES };
+        assert_eq!(bus.take_stall_cycles(), 4 * block);
+        assert_eq!(bus.take_stall_cycles(), 0);
+    }
+}
+
+#[test]
+fn hblank_dma_copies_a_block_per_hblank() {
+    for double_speed in [false, true] {
+        let mut bus = bus(double_speed);
+        // the PPU runs at the same pace either way, so the CPU ticks twice as many cycles
+        let dots = |dots: u32| if double_speed { dots * 2 } else { dots };
+        let block = if double_speed { HDMA_BLOCK_CYCLES * 2 } else { HDMA_BLOCK_CYCLES };
+
+        bus.write_byte(LCDC as u16, 0x80);
+        bus.write_byte(HDMA5, 0x81);
+        // bit 7 clear while it runs, under the blocks left less one
+        assert_eq!(bus.read_byte(HDMA5), 0x01);
+        assert_eq!(vram(&bus, 0x10), [0; 0x10]);
+
+        bus.tick(dots(FIRST_HBLANK - 4));
+        assert_eq!(bus.take_stall_cycles(), 0);
+        bus.tick(dots(4));
+        assert_eq!(vram(&bus, 0x20), [numbered(0x10), vec![0; 0x10]].concat());
+        assert_eq!(bus.take_stall_cycles(), block);
+        assert_eq!(bus.read_byte(HDMA5), 0x00);
+
+        bus.tick(dots(LINE));
+        assert_eq!(vram(&bus, 0x20), numbered(0x20));
+        assert_eq!(bus.take_stall_cycles(), block);
+        assert_eq!(bus.read_byte(HDMA5), 0xFF);
+
+        // and nothing more after that
+        bus.tick(dots(LINE));
+        assert_eq!(bus.take_stall_cycles(), 0);
+    }
+}
+
+#[test]
+fn writing_hdma5_with_bit_7_clear_cancels_an_hblank_dma() {
+    let mut bus = bus(false);
+    bus.write_byte(LCDC as u16, 0x80);
+    bus.write_byte(HDMA5, 0x83);
+    bus.tick(FIRST_HBLANK);
+    bus.take_stall_cycles();
+
+    bus.write_byte(HDMA5, 0x00);
+    // stopped with three blocks to go, which reads back with bit 7 set
+    assert_eq!(bus.read_byte(HDMA5), 0x82);
+    bus.tick(LINE * 2);
+    assert_eq!(bus.take_stall_cycles(), 0);
+    assert_eq!(vram(&bus, 0x20), [numbered(0x10), vec![0; 0x10]].concat());
+}
+
+// a CGB cart that arms KEY1, stops and then loops
+fn speed_switch_rom(arm: u8) -> Vec<u8> {
+    let program = [
+        0x3E, arm,              // 0150: ld a, arm
+        0xE0, 0x4D,             //       ldh [rKEY1], a
+        0x10, 0x00,             //       stop
+        0x18, 0xFE,             // 0156: jr $0156
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x143] = 0x80;
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+    rom
+}
+
+fn run_to_stop(gb: &mut GameBoy) -> u32 {
+    while gb.cpu.pc != 0x0154 {
+        gb.step().unwrap();
+    }
+    gb.step().unwrap()
+}
+
+#[test]
+fn stop_with_key1_armed_switches_speed() {
+    let mut gb = GameBoy::new();
+    gb.load(speed_switch_rom(0x01)).unwrap();
+    assert!(!gb.double_speed());
+
+    // the CPU sits out the switch on top of STOP itself
+    assert_eq!(run_to_stop(&mut gb), 4 + SPEED_SWITCH_CYCLES);
+    assert!(gb.double_speed());
+    assert!(!gb.cpu.stopped());
+    assert_eq!(gb.cpu.pc, 0x0156);
+    assert_eq!(gb.cpu.bus.read_byte(KEY1), 0xFE);
+
+    // without KEY1 armed it's an ordinary STOP
+    let mut gb = GameBoy::new();
+    gb.load(speed_switch_rom(0x00)).unwrap();
+    assert_eq!(run_to_stop(&mut gb), 4);
+    assert!(!gb.double_speed());
+    assert!(gb.cpu.stopped());
+    assert_eq!(gb.cpu.bus.read_byte(KEY1), 0x7E);
+}
@@ -0,0 +1,215 @@
+use gb_emulator::GameBoy;
+use gb_emulator::debugger::{Access, Breakpoint, Condition, Debugger, Frame, FrameKind, Stop, WatchHit, Watchpoint};
+use gb_emulator::symbols::Symbols;
+
+// Main calls Sub, which calls Leaf, then reaches Leaf again through rst $08. After that it calls
+// $4000 with bank 2 mapped and then with bank 3, and loops
+const MAIN: [u8; 25] = [
+    0x31, 0xFE, 0xFF,       // 0150: ld sp, $FFFE
+    0xCD, 0x00, 0x02,       // 0153: call Sub
+    0xCF,                   // 0156: rst $08
+    0x3E, 0x02,             // 0157: ld a, 2
+    0xEA, 0x00, 0x20,       // 0159: ld [$2000], a
+    0xCD, 0x00, 0x40,       // 015C: call $4000
+    0x3E, 0x03,             // 015F: ld a, 3
+    0xEA, 0x00, 0x20,       // 0161: ld [$2000], a
+    0xCD, 0x00, 0x40,       // 0164: call $4000
+    0x18, 0xFE,             // 0167: jr $0167
+];
+
+const SYMBOLS: &str = "\
+00:0150 Main
+00:0200 Sub
+00:0210 Leaf
+03:4000 Far
+";
+
+fn debugger() -> Debugger {
+    let mut rom = vec![0; 4 * 0x4000];
+    rom[0x147] = 0x01; // MBC1
+    rom[0x0008..0x000C].copy_from_slice(&[0xCD, 0x10, 0x02, 0xC9]); // call Leaf; ret
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp Main
+    rom[0x0150..0x0150 + MAIN.len()].copy_from_slice(&MAIN);
+    rom[0x0200..0x0204].copy_from_slice(&[0xCD, 0x10, 0x02, 0xC9]); // Sub: call Leaf; ret
+    rom[0x0210..0x0213].copy_from_slice(&[0x00, 0x00, 0xC9]); // Leaf: nop; nop; ret
+    for bank in [2, 3] {
+        rom[bank * 0x4000..bank * 0x4000 + 2].copy_from_slice(&[0x00, 0xC9]); // nop; ret
+    }
+
+    let mut gb = GameBoy::new();
+    gb.load(rom).unwrap();
+    let mut debugger = Debugger::new(gb);
+    debugger.symbols = Symbols::parse(SYMBOLS);
+    debugger
+}
+
+fn frame(kind: FrameKind, call_site: u16, target: u16, return_address: u16, sp: u16) -> Frame {
+    Frame { kind, call_site, target, bank: Some(if target < 0x4000 { 0 } else { 1 }), return_address, sp }
+}
+
+#[test]
+fn a_banked_breakpoint_only_fires_in_its_bank() {
+    let mut debugger = debugger();
+    debugger.add_breakpoint(Breakpoint { bank: Some(3), addr: 0x4000 });
+    assert_eq!(debugger.cont().unwrap(), Stop::Breakpoint(0));
+    assert_eq!(debugger.pc(), 0x4000);
+    assert_eq!(debugger.bank(0x4000), Some(3));
+
+    // without a bank the first call there stops it, with bank 2 mapped
+    let mut debugger = self::debugger();
+    debugger.add_breakpoint(Breakpoint { bank: None, addr: 0x4000 });
+    assert_eq!(debugger.cont().unwrap(), Stop::Breakpoint(0));
+    assert_eq!(debugger.bank(0x4000), Some(2));
+}
+
+#[test]
+fn breakpoints_at_labels() {
+    let mut debugger = debugger();
+    // a label in switchable ROM keeps its bank, one in bank 0 doesn't need it
+    let far = Breakpoint::at_label(&debugger.symbols, "Far").unwrap();
+    assert_eq!(far, Breakpoint { bank: Some(3), addr: 0x4000 });
+    let leaf = Breakpoint::at_label(&debugger.symbols, "Leaf+2").unwrap();
+    assert_eq!(leaf, Breakpoint { bank: None, addr: 0x0212 });
+    assert_eq!(Breakpoint::at_label(&debugger.symbols, "Nowhere"), None);
+
+    debugger.add_breakpoint(far);
+    debugger.add_breakpoint(leaf);
+    assert_eq!(debugger.cont().unwrap(), Stop::Breakpoint(1));
+    assert_eq!(debugger.symbol(debugger.pc()).as_deref(), Some("Leaf+2"));
+    debugger.remove_breakpoint(1);
+    assert_eq!(debugger.cont().unwrap(), Stop::Breakpoint(0));
+    assert_eq!(debugger.symbol(debugger.pc()).as_deref(), Some("Far"));
+}
+
+#[test]
+fn step_over_runs_calls_and_rsts_to_the_next_instruction() {
+    let mut debugger = debugger();
+    while debugger.pc() != 0x0153 {
+        debugger.step().unwrap();
+    }
+
+    assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+    assert_eq!(debugger.pc(), 0x0156);
+    assert!(debugger.backtrace().is_empty());
+    assert_eq!(debugger.step_over().unwrap(), Stop::Step);
+    assert_eq!(debugger.pc(), 0x0157);
+    assert!(debugger.backtrace().is_empty());
+
+    // a breakpoint inside what's being stepped over still stops it
+    let mut debugger = self::debugger();
+    while debugger.pc() != 0x0156 {
+        debugger.step().unwrap();
+    }
+    debugger.add_breakpoint(Breakpoint { bank: None, addr: 0x0210 });
+    assert_eq!(debugger.step_over().unwrap(), Stop::Breakpoint(0));
+    assert_eq!(debugger.backtrace().len(), 2);
+}
+
+#[test]
+fn finish_returns_one_frame_at_a_time() {
+    let mut debugger = debugger();
+    debugger.add_breakpoint(Breakpoint { bank: None, addr: 0x0210 });
+    debugger.cont().unwrap();
+    assert_eq!(debugger.backtrace().len(), 2);
+
+    assert_eq!(debugger.finish().unwrap(), Stop::Finished);
+    assert_eq!(debugger.pc(), 0x0203);
+    assert_eq!(debugger.backtrace().len(), 1);
+    assert_eq!(debugger.finish().unwrap(), Stop::Finished);
+    assert_eq!(debugger.pc(), 0x0156);
+    assert!(debugger.backtrace().is_empty());
+}
+
+#[test]
+fn backtraces_go_through_calls_and_rsts() {
+    let mut debugger = debugger();
+    debugger.add_breakpoint(Breakpoint { bank: None, addr: 0x0210 });
+
+    debugger.cont().unwrap();
+    assert_eq!(debugger.backtrace(), [
+        frame(FrameKind::Call, 0x0153, 0x0200, 0x0156, 0xFFFC),
+        frame(FrameKind::Call, 0x0200, 0x0210, 0x0203, 0xFFFA),
+    ]);
+
+    debugger.cont().unwrap();
+    assert_eq!(debugger.backtrace(), [
+        frame(FrameKind::Rst, 0x0156, 0x0008, 0x0157, 0xFFFC),
+        frame(FrameKind::Call, 0x0008, 0x0210, 0x000B, 0xFFFA),
+    ]);
+
+    // returning from both leaves nothing
+    debugger.remove_breakpoint(0);
+    debugger.add_breakpoint(Breakpoint { bank: None, addr: 0x0157 });
+    debugger.cont().unwrap();
+    assert!(debugger.backtrace().is_empty());
+}
+
+fn watchpoint(start: u16, end: u16, read: bool, write: bool, condition: Option<Condition>) -> Watchpoint {
+    Watchpoint { start, end, read, write, condition }
+}
+
+#[test]
+fn watchpoints_tell_reads_from_writes_over_a_range() {
+    let mut debugger = debugger();
+    let bus = &mut debugger.gb.cpu.bus;
+    bus.watchpoints.push(Some(watchpoint(0xC000, 0xC000, true, false, None)));
+    bus.watchpoints.push(Some(watchpoint(0xC001, 0xC003, false, true, None)));
+
+    bus.write_byte(0xC000, 0x12);
+    assert!(bus.take_watch_hits().is_empty());
+    bus.read_byte(0xC000);
+    assert_eq!(bus.take_watch_hits(), [WatchHit { index: 0, access: Access::Read, addr: 0xC000, value: 0x12 }]);
+
+    bus.write_byte(0xC003, 0x34);
+    bus.write_byte(0xC004, 0x56);
+    bus.read_byte(0xC002);
+    assert_eq!(bus.take_watch_hits(), [WatchHit { index: 1, access: Access::Write, addr: 0xC003, value: 0x34 }]);
+
+    // peeking is for debuggers and goes unseen
+    bus.peek(0xC000);
+    assert!(bus.take_watch_hits().is_empty());
+}
+
+#[test]
+fn conditions_match_the_value_under_the_mask() {
+    // bit 7 clear, whatever the rest
+    let bit_7_clear = watchpoint(0xFF40, 0xFF40, false, true, Some(Condition { mask: 0x80, value: 0x00 }));
+    assert!(bit_7_clear.matches(Access::Write, 0xFF40, 0x7F));
+    assert!(!bit_7_clear.matches(Access::Write, 0xFF40, 0x80));
+    assert!(!bit_7_clear.matches(Access::Read, 0xFF40, 0x00));
+    assert!(!bit_7_clear.matches(Access::Write, 0xFF41, 0x00));
+
+    let mut debugger = debugger();
+    let exact = watchpoint(0xC000, 0xC0FF, false, true, Some(Condition { mask: 0xFF, value: 0x42 }));
+    debugger.gb.cpu.bus.watchpoints.push(Some(exact));
+    debugger.gb.cpu.bus.write_byte(0xC010, 0x41);
+    debugger.gb.cpu.bus.write_byte(0xC020, 0x42);
+    assert_eq!(debugger.gb.cpu.bus.take_watch_hits(), [WatchHit { index: 0, access: Access::Write, addr: 0xC020, value: 0x42 }]);
+}
+
+#[test]
+fn watchpoints_stop_at_the_instruction_that_tripped_them() {
+    let mut debugger = debugger();
+    // the second bank switch, which writes 3
+    let index = debugger.add_watchpoint(watchpoint(0x2000, 0x3FFF, false, true, Some(Condition { mask: 0xFF, value: 0x03 })));
+    let hit = WatchHit { index, access: Access::Write, addr: 0x2000, value: 0x03 };
+    assert_eq!(debugger.cont().unwrap(), Stop::Watchpoint { hit, pc: 0x0161 });
+    assert_eq!(debugger.pc(), 0x0164);
+
+    // fetching an instruction reads it
+    let index = debugger.add_watchpoint(watchpoint(0x4000, 0x4000, true, false, None));
+    let hit = WatchHit { index, access: Access::Read, addr: 0x4000, value: 0x00 };
+    assert_eq!(debugger.cont().unwrap(), Stop::Watchpoint { hit, pc: 0x4000 });
+}
+
+#[test]
+fn the_debuggers_own_writes_dont_trip_watchpoints() {
+    let mut debugger = debugger();
+    debugger.add_watchpoint(watchpoint(0xC000, 0xDFFF, true, true, None));
+    debugger.write_memory(0xC000, &[1, 2, 3]);
+    assert_eq!(debugger.read_memory(0xC000, 3), [1, 2, 3]);
+    assert!(debugger.gb.cpu.bus.take_watch_hits().is_empty());
+
+    // so the first instruction steps normally
+    assert_eq!(debugger.step().unwrap(), Stop::Step);
+}
@@ -0,0 +1,89 @@
+use gb_emulator::cpu::OPCODE_LENGTHS;
+use gb_emulator::disasm::{Syntax, disassemble};
+
+// the bytes with no instruction behind them
+const ILLEGAL: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+// disassembles `bytes` at $0100 in both syntaxes, with $0150 named Main
+fn text(bytes: &[u8]) -> (String, String) {
+    let labels = |addr: u16| (addr == 0x0150).then(|| "Main".to_string());
+    let instruction = disassemble(bytes, 0x0100);
+    (instruction.text(Syntax::Rgbds, &labels), instruction.text(Syntax::Classic, &labels))
+}
+
+#[test]
+fn every_real_opcode_comes_from_the_cpu_decoder() {
+    for opcode in 0..=0xFFu8 {
+        let instruction = disassemble(&[opcode, 0x00, 0x00], 0x0100);
+        if ILLEGAL.contains(&opcode) {
+            assert_eq!(instruction.mnemonic, "db");
+            assert_eq!(instruction.length, 1);
+        } else {
+            assert_ne!(instruction.mnemonic, "db", "${opcode:02X} isn't decoded");
+            assert_eq!(instruction.length, OPCODE_LENGTHS[opcode as usize] as u16, "length of ${opcode:02X}");
+        }
+    }
+    for opcode in 0..=0xFFu8 {
+        let instruction = disassemble(&[0xCB, opcode], 0x0100);
+        assert_ne!(instruction.mnemonic, "db");
+        assert_eq!(instruction.length, 2);
+    }
+}
+
+#[test]
+fn rgbds_syntax() {
+    let cases: [(&[u8], &str); 16] = [
+        (&[0x2A], "ld a, [hl+]"),
+        (&[0x36, 0x12], "ld [hl], $12"),
+        (&[0xFA, 0x00, 0xC0], "ld a, [$C000]"),
+        (&[0xE0, 0x44], "ldh [$FF44], a"),
+        (&[0xF2], "ldh a, [c]"),
+        (&[0x20, 0x4E], "jr nz, Main"),
+        (&[0x18, 0xFE], "jr $0100"),
+        (&[0xC2, 0x00, 0x40], "jp nz, $4000"),
+        (&[0xCD, 0x50, 0x01], "call Main"),
+        (&[0xFF], "rst $38"),
+        (&[0x08, 0x00, 0xC0], "ld [$C000], sp"),
+        (&[0xE8, 0xFE], "add sp, -$02"),
+        (&[0xF8, 0xFF], "ld hl, sp - $01"),
+        (&[0xF9], "ld sp, hl"),
+        (&[0xCB, 0x7E], "bit 7, [hl]"),
+        (&[0xD3], "db $D3"),
+    ];
+    for (bytes, expected) in cases {
+        assert_eq!(text(bytes).0, expected);
+    }
+}
+
+#[test]
+fn classic_syntax() {
+    let cases: [(&[u8], &str); 14] = [
+        (&[0x2A], "LD A,(HL+)"),
+        (&[0x36, 0x12], "LD (HL),$12"),
+        (&[0xE0, 0x44], "LDH ($44),A"),
+        (&[0xF2], "LDH A,(C)"),
+        (&[0x20, 0x4E], "JR NZ,Main"),
+        (&[0xCD, 0x50, 0x01], "CALL Main"),
+        (&[0xFF], "RST $38"),
+        (&[0x27], "DAA"),
+        (&[0x0B], "DEC BC"),
+        (&[0x08, 0x00, 0xC0], "LD ($C000),SP"),
+        (&[0xE8, 0xFE], "ADD SP,-$02"),
+        (&[0xF8, 0x01], "LD HL,SP+$01"),
+        (&[0xCB, 0x7E], "BIT 7,(HL)"),
+        (&[0xD3], "DB $D3"),
+    ];
+    for (bytes, expected) in cases {
+        assert_eq!(text(bytes).1, expected);
+    }
+}
+
+#[test]
+fn targets_of_jumps_calls_and_restarts() {
+    assert_eq!(disassemble(&[0x20, 0x4E], 0x0100).target(), Some(0x0150));
+    assert_eq!(disassemble(&[0xCD, 0x34, 0x12], 0x0100).target(), Some(0x1234));
+    assert_eq!(disassemble(&[0xEF], 0x0100).target(), Some(0x0028));
+    assert_eq!(disassemble(&[0xE9], 0x0100).target(), None);
+    // past the end of the slice reads as zero
+    assert_eq!(disassemble(&[0xC3], 0x0100).target(), Some(0x0000));
+}
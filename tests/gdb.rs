@@ -0,0 +1,115 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use gb_emulator::GameBoy;
+use gb_emulator::debugger::Debugger;
+use gb_emulator::gdb::{GdbStub, TARGET_XML};
+
+const MAIN: [u8; 7] = [
+    0x3E, 0x42,             // 0150: ld a, $42
+    0xEA, 0x00, 0xC0,       // 0152: ld [$C000], a
+    0x18, 0xFE,             // 0155: jr $0155
+];
+
+// a client's end of the connection, speaking the protocol a byte at a time
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+fn checksum(payload: &str) -> u8 {
+    payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b))
+}
+
+impl Client {
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.reader.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, payload: &str, checksum: u8) -> u8 {
+        self.writer.write_all(format!("${payload}#{checksum:02x}").as_bytes()).unwrap();
+        self.byte()
+    }
+
+    // sends a packet and returns the reply, once its checksum has been checked and acked
+    fn request(&mut self, payload: &str) -> String {
+        assert_eq!(self.send(payload, checksum(payload)), b'+', "{payload} wasn't acked");
+        assert_eq!(self.byte(), b'$');
+        let mut reply = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let reply = String::from_utf8(reply).unwrap();
+        let sent = String::from_utf8(vec![self.byte(), self.byte()]).unwrap();
+        assert_eq!(u8::from_str_radix(&sent, 16).unwrap(), checksum(&reply), "checksum of {reply}");
+        self.writer.write_all(b"+").unwrap();
+        reply
+    }
+}
+
+// a stub serving the test ROM on a thread of its own, and a client connected to it
+fn connect() -> (Client, thread::JoinHandle<std::io::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp Main
+        rom[0x0150..0x0150 + MAIN.len()].copy_from_slice(&MAIN);
+        let mut gb = GameBoy::new();
+        gb.load(rom).unwrap();
+        let mut debugger = Debugger::new(gb);
+
+        let (stream, _) = listener.accept()?;
+        GdbStub::new(&mut debugger, stream)?.serve()
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    (Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream }, server)
+}
+
+// sp and pc at the end of a `g` reply, which sends af, bc, de, hl, sp and pc little endian
+fn sp_pc(registers: &str) -> (&str, &str) {
+    assert_eq!(registers.len(), 6 * 4);
+    (&registers[16..20], &registers[20..24])
+}
+
+#[test]
+fn a_session_over_tcp() {
+    let (mut client, server) = connect();
+
+    assert_eq!(client.request("qSupported:multiprocess+;xmlRegisters=i386"),
+        "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+");
+    assert_eq!(client.request("qXfer:features:read:target.xml:0,ffff"), format!("l{TARGET_XML}"));
+    // a bad checksum is refused, and the stub waits for the packet again
+    assert_eq!(client.send("g", checksum("g") ^ 1), b'-');
+    assert_eq!(client.request("?"), "S05");
+
+    assert_eq!(sp_pc(&client.request("g")), ("feff", "0001"));
+    assert_eq!(client.request("m150,5"), "3e42ea00c0");
+
+    // a breakpoint at Main stops there
+    assert_eq!(client.request("Z0,150,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(sp_pc(&client.request("g")).1, "5001");
+
+    // and a write watchpoint reports the address it caught
+    assert_eq!(client.request("Z2,c000,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:c000;");
+    assert_eq!(sp_pc(&client.request("g")).1, "5501");
+    assert_eq!(client.request("mc000,1"), "42");
+
+    // with both taken out again a step just goes round the loop
+    assert_eq!(client.request("z2,c000,1"), "OK");
+    assert_eq!(client.request("z0,150,1"), "OK");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(sp_pc(&client.request("g")).1, "5501");
+
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap().unwrap();
+}
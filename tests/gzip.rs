@@ -0,0 +1,67 @@
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use gb_emulator::gzip::GzipWriter;
+
+// compresses `data` in uneven pieces, the way trace lines arrive, and decompresses it with zlib
+fn round_trip(data: &[u8]) -> Vec<u8> {
+    let mut gzip = GzipWriter::new(Vec::new()).unwrap();
+    for piece in data.chunks(1000) {
+        gzip.write_all(piece).unwrap();
+    }
+    let compressed = gzip.finish().unwrap();
+
+    let mut out = Vec::new();
+    GzDecoder::new(compressed.as_slice()).read_to_end(&mut out).unwrap();
+    assert!(out == data, "{} bytes came back as {} different ones", data.len(), out.len());
+    compressed
+}
+
+// bytes that don't repeat, from a xorshift generator
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_F491_u32;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }).collect()
+}
+
+#[test]
+fn empty_input() {
+    round_trip(&[]);
+}
+
+#[test]
+fn input_spanning_several_blocks() {
+    // lines like a trace log, over 200 KB so a few blocks go out before finish()
+    let mut log = String::new();
+    for i in 0..4000u32 {
+        log.push_str(&format!("A:{:02X} F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:00:{:04X}\n", i % 256, i.wrapping_mul(7)));
+    }
+    assert!(log.len() > 0x30000);
+    let compressed = round_trip(log.as_bytes());
+    assert!(compressed.len() < log.len() / 3);
+
+    round_trip(&noise(0x28000));
+}
+
+#[test]
+fn long_matches_and_far_distances() {
+    // a single byte repeated is one long run of maximum length matches
+    let compressed = round_trip(&[0x55; 100_000]);
+    assert!(compressed.len() < 1000);
+
+    // a chunk repeated from almost the whole window back
+    let chunk = noise(30_000);
+    let repeated: Vec<u8> = chunk.iter().chain(&chunk).chain(&chunk).copied().collect();
+    let compressed = round_trip(&repeated);
+    // the first copy doesn't compress, the other two cost next to nothing
+    assert!(compressed.len() < 40_000);
+
+    // matches that cross from one block into the next
+    let mut data = noise(0x10000 - 100);
+    data.extend_from_within(..20_000);
+    round_trip(&data);
+}
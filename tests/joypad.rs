@@ -0,0 +1,65 @@
+use gb_emulator::joypad::{Button, P1};
+use gb_emulator::memory::MemoryBus;
+use gb_emulator::ppu::IF;
+
+const DIRECTIONS: u8 = 0x20; // P14 low
+const BUTTONS: u8 = 0x10;    // P15 low
+const NEITHER: u8 = 0x30;
+
+// whether the joypad interrupt has been requested since the last call
+fn interrupted(bus: &mut MemoryBus) -> bool {
+    let requested = bus.memory[IF] & 0x10 != 0;
+    bus.memory[IF] &= !0x10;
+    requested
+}
+
+#[test]
+fn p1_shows_the_selected_row() {
+    let mut bus = MemoryBus::new();
+    bus.set_button(Button::Right, true);
+    bus.set_button(Button::Start, true);
+
+    bus.write_byte(P1, DIRECTIONS);
+    assert_eq!(bus.read_byte(P1), 0xEE);
+    bus.write_byte(P1, BUTTONS);
+    assert_eq!(bus.read_byte(P1), 0xD7);
+    // both rows at once mix, and with neither every line floats high
+    bus.write_byte(P1, 0x00);
+    assert_eq!(bus.read_byte(P1), 0xC6);
+    bus.write_byte(P1, NEITHER);
+    assert_eq!(bus.read_byte(P1), 0xFF);
+
+    // only the select bits can be written
+    bus.write_byte(P1, 0xCF | BUTTONS);
+    assert_eq!(bus.read_byte(P1), 0xD7);
+}
+
+#[test]
+fn a_line_going_low_requests_the_joypad_interrupt() {
+    let mut bus = MemoryBus::new();
+    bus.write_byte(P1, DIRECTIONS);
+    interrupted(&mut bus);
+
+    // a button on the unselected row doesn't touch the lines
+    bus.set_button(Button::A, true);
+    assert!(!interrupted(&mut bus));
+    bus.set_button(Button::Down, true);
+    assert!(interrupted(&mut bus));
+
+    // releasing takes a line high, which doesn't count
+    bus.set_button(Button::Down, false);
+    assert!(!interrupted(&mut bus));
+    // any line going low does, whatever else is held
+    bus.set_button(Button::Left, true);
+    assert!(interrupted(&mut bus));
+    bus.set_buttons(Button::Left.mask() | Button::Up.mask());
+    assert!(interrupted(&mut bus));
+    bus.set_buttons(Button::Left.mask() | Button::A.mask());
+    assert!(!interrupted(&mut bus));
+
+    // selecting a row with a button already held on it (A) pulls its line low too
+    bus.write_byte(P1, NEITHER);
+    assert!(!interrupted(&mut bus));
+    bus.write_byte(P1, BUTTONS);
+    assert!(interrupted(&mut bus));
+}
@@ -0,0 +1,123 @@
+use gb_emulator::GameBoy;
+use gb_emulator::link::{DEFAULT_QUANTUM, LinkCable, LinkedPair};
+
+const MASTER: u8 = 0x42;
+const SLAVE: u8 = 0x99;
+
+// Loads SB and starts a transfer with the given SC, waits for it to finish, then stores what
+// DIV read at that point in $C001, the byte shifted in in $C000 and a done flag in $C002
+fn rom(sb: u8, sc: u8) -> Vec<u8> {
+    let program = [
+        0x3E, sb,               // 0150: ld a, sb
+        0xE0, 0x01,             //       ldh [rSB], a
+        0x3E, sc,               //       ld a, sc
+        0xE0, 0x02,             //       ldh [rSC], a
+        0xF0, 0x02,             // 0158: ldh a, [rSC]
+        0xCB, 0x7F,             //       bit 7, a
+        0x20, 0xFA,             //       jr nz, $0158
+        0xF0, 0x04,             //       ldh a, [rDIV]
+        0xEA, 0x01, 0xC0,       //       ld [$C001], a
+        0xF0, 0x01,             //       ldh a, [rSB]
+        0xEA, 0x00, 0xC0,       //       ld [$C000], a
+        0x3E, 0x01,             //       ld a, 1
+        0xEA, 0x02, 0xC0,       //       ld [$C002], a
+        0x18, 0xFE,             // 016D: jr $016D
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    rom[0x150..0x150 + program.len()].copy_from_slice(&program);
+    rom
+}
+
+// what a console saw once its transfer was done: the byte it got, and DIV at the time
+fn result(gb: &GameBoy) -> Option<(u8, u8)> {
+    let bus = &gb.cpu.bus;
+    (bus.peek(0xC002) == 1).then(|| (bus.peek(0xC000), bus.peek(0xC001)))
+}
+
+// runs a console on one end of a socket for a fixed stretch of emulated time
+fn run_linked(rom: Vec<u8>, cable: impl FnOnce() -> LinkCable) -> Option<(u8, u8)> {
+    let mut gb = GameBoy::new();
+    gb.load(rom).unwrap();
+    gb.set_serial_device(Box::new(cable()));
+    let mut cycles = 0;
+    while cycles < 20000 {
+        cycles += gb.step().unwrap();
+    }
+    result(&gb)
+}
+
+#[test]
+fn two_instances_swap_bytes_over_a_unix_socket() {
+    let path = std::env::temp_dir().join(format!("gb-link-test-{}.sock", std::process::id()));
+    let listen_path = path.clone();
+
+    // the slave listens and waits on the master's clock, the master connects and clocks the byte
+    let slave = std::thread::spawn(move || {
+        run_linked(rom(SLAVE, 0x80), || LinkCable::listen_unix(&listen_path).unwrap())
+    });
+    let master = run_linked(rom(MASTER, 0x81), || loop {
+        match LinkCable::connect_unix(&path) {
+            Ok(cable) => break cable,
+            Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+        }
+    });
+    let slave = slave.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let (master_got, master_div) = master.expect("the master's transfer never finished");
+    let (slave_got, slave_div) = slave.expect("the slave's transfer never finished");
+    assert_eq!(master_got, SLAVE);
+    assert_eq!(slave_got, MASTER);
+
+    // the master is done after 8 bits of 512 cycles, the slave gets the byte at the next sync
+    // point after that. DIV counts in 256 cycle steps from the same power on value on both
+    assert!((16..=18).contains(&master_div), "master finished at DIV {master_div}");
+    let lag = slave_div - master_div;
+    assert!(lag as u32 * 256 <= DEFAULT_QUANTUM + 256, "slave finished {lag} DIV steps after the master");
+}
+
+#[test]
+fn a_linked_pair_swaps_bytes_in_step() {
+    let mut master = GameBoy::new();
+    master.load(rom(MASTER, 0x81)).unwrap();
+    let mut slave = GameBoy::new();
+    slave.load(rom(SLAVE, 0x80)).unwrap();
+
+    let mut pair = LinkedPair::new(master, slave);
+    pair.run_for(20000).unwrap();
+
+    let (master_got, master_div) = result(&pair.consoles[0]).expect("the master's transfer never finished");
+    let (slave_got, slave_div) = result(&pair.consoles[1]).expect("the slave's transfer never finished");
+    assert_eq!(master_got, SLAVE);
+    assert_eq!(slave_got, MASTER);
+
+    // the shared wire settles the transfer on both ends at once, so they finish together
+    assert!((16..=18).contains(&master_div), "master finished at DIV {master_div}");
+    assert_eq!(slave_div, master_div);
+}
+
+#[test]
+fn a_peer_going_away_is_reported_once_and_reads_as_no_cable() {
+    use gb_emulator::serial::SerialDevice;
+    use std::io::Write;
+
+    // the other end's half of the handshake is already waiting when the cable connects
+    let (ours, mut theirs) = std::os::unix::net::UnixStream::pair().unwrap();
+    theirs.write_all(b"GBLK\x01").unwrap();
+    theirs.write_all(&DEFAULT_QUANTUM.to_le_bytes()).unwrap();
+    let mut cable = LinkCable::new(Box::new(ours), DEFAULT_QUANTUM).unwrap();
+    assert!(cable.connected());
+    drop(theirs);
+
+    cable.tick(DEFAULT_QUANTUM);
+    assert!(!cable.connected());
+    assert!(cable.disconnect_error().is_some());
+    assert!(cable.take_message().unwrap().starts_with("link cable disconnected: "));
+    assert_eq!(cable.take_message(), None);
+
+    // from then on nothing answers
+    cable.tick(DEFAULT_QUANTUM);
+    assert_eq!(cable.exchange(0x12), 0xFF);
+    assert_eq!(cable.external_clock(0x34), None);
+}
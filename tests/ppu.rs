@@ -0,0 +1,188 @@
+use gb_emulator::memory::MemoryBus;
+use gb_emulator::ppu::{LCDC, STAT, rgb555};
+
+const VBK: u16 = 0xFF4F;
+const SVBK: u16 = 0xFF70;
+const BCPS: u16 = 0xFF68;
+const BCPD: u16 = 0xFF69;
+const OCPS: u16 = 0xFF6A;
+const OCPD: u16 = 0xFF6B;
+
+const WHITE: u16 = 0x7FFF;
+const RED: u16 = 0x001F;
+const GREEN: u16 = 0x03E0;
+const BLUE: u16 = 0x7C00;
+
+fn cgb() -> MemoryBus {
+    let mut bus = MemoryBus::new();
+    bus.cgb_mode = true;
+    bus.ppu.cgb_mode = true;
+    bus
+}
+
+// fills colours 0 and 1 of a palette through its auto-incrementing port
+fn palette(bus: &mut MemoryBus, select: u16, palette: u8, colors: [u16; 2]) {
+    bus.write_byte(select, 0x80 | (palette * 8));
+    for color in colors {
+        bus.write_byte(select + 1, color as u8);
+        bus.write_byte(select + 1, (color >> 8) as u8);
+    }
+}
+
+// writes one 2bpp tile row of colour 1 pixels, in VRAM bank `bank`
+fn tile_row(bus: &mut MemoryBus, bank: u8, tile: u16, row: u16, pixels: u8) {
+    bus.write_byte(VBK, bank);
+    bus.write_byte(0x8000 + tile * 16 + row * 2, pixels);
+    bus.write_byte(VBK, 0);
+}
+
+// the tile and attributes of a background map entry on the top row
+fn map(bus: &mut MemoryBus, column: u16, tile: u8, attributes: u8) {
+    bus.write_byte(0x9800 + column, tile);
+    bus.write_byte(VBK, 1);
+    bus.write_byte(0x9800 + column, attributes);
+    bus.write_byte(VBK, 0);
+}
+
+fn sprite(bus: &mut MemoryBus, index: u16, x: u8, tile: u8, attributes: u8) {
+    for (i, byte) in [16, x, tile, attributes].into_iter().enumerate() {
+        bus.write_byte(0xFE00 + index * 4 + i as u16, byte);
+    }
+}
+
+// turns the LCD on with `lcdc` and returns the first line it draws
+fn line(bus: &mut MemoryBus, lcdc: u8) -> Vec<u32> {
+    bus.write_byte(LCDC as u16, 0x00);
+    bus.tick(4);
+    bus.write_byte(LCDC as u16, lcdc);
+    bus.tick(456);
+    bus.ppu.framebuffer[..160].to_vec()
+}
+
+#[test]
+fn vram_and_wram_banks_switch_through_vbk_and_svbk() {
+    let mut bus = cgb();
+    bus.write_byte(0x8000, 0x11);
+    bus.write_byte(VBK, 0x01);
+    assert_eq!(bus.read_byte(VBK), 0xFF);
+    bus.write_byte(0x8000, 0x22);
+    assert_eq!(bus.vram1[0], 0x22);
+    bus.write_byte(VBK, 0xFE);
+    assert_eq!(bus.read_byte(VBK), 0xFE);
+    assert_eq!(bus.read_byte(0x8000), 0x11);
+
+    for bank in 1..=7 {
+        bus.write_byte(SVBK, bank);
+        bus.write_byte(0xD000, bank * 0x10);
+    }
+    bus.write_byte(0xC000, 0x99);
+    for bank in 1..=7 {
+        bus.write_byte(SVBK, bank);
+        assert_eq!(bus.read_byte(SVBK), 0xF8 | bank);
+        assert_eq!(bus.read_byte(0xD000), bank * 0x10);
+        // bank 0 stays put at $C000
+        assert_eq!(bus.read_byte(0xC000), 0x99);
+    }
+    // bank 0 can't be put at $D000, asking for it gives bank 1
+    bus.write_byte(SVBK, 0x00);
+    assert_eq!(bus.read_byte(SVBK), 0xF9);
+    assert_eq!(bus.read_byte(0xD000), 0x10);
+}
+
+#[test]
+fn palette_ports_auto_increment_and_read_back() {
+    let mut bus = cgb();
+    bus.write_byte(BCPS, 0x80 | 0x3E);
+    for value in [0x12, 0x34, 0x56] {
+        bus.write_byte(BCPD, value);
+    }
+    // bit 6 always reads set, and the index wraps round the 64 bytes
+    assert_eq!(bus.read_byte(BCPS), 0xC1);
+    assert_eq!(&bus.ppu.bg_palette_ram[0x3E..], [0x12, 0x34]);
+    assert_eq!(bus.ppu.bg_palette_ram[0], 0x56);
+
+    // reading doesn't move the index, and neither does writing without bit 7
+    bus.write_byte(BCPS, 0x3F);
+    assert_eq!(bus.read_byte(BCPD), 0x34);
+    assert_eq!(bus.read_byte(BCPD), 0x34);
+    bus.write_byte(BCPD, 0x78);
+    assert_eq!(bus.read_byte(BCPS), 0x7F);
+    assert_eq!(bus.read_byte(BCPD), 0x78);
+
+    // while the PPU is drawing the data is out of reach, but a write still moves the index on
+    bus.write_byte(OCPS, 0x80 | 0x05);
+    bus.memory[STAT] = 0x03;
+    assert_eq!(bus.read_byte(OCPD), 0xFF);
+    bus.write_byte(OCPD, 0xAB);
+    assert_eq!(bus.read_byte(OCPS), 0xC6);
+    bus.memory[STAT] = 0x00;
+    assert_eq!(bus.ppu.obj_palette_ram[5], 0xFF);
+    bus.write_byte(OCPD, 0xCD);
+    assert_eq!(bus.ppu.obj_palette_ram[6], 0xCD);
+}
+
+#[test]
+fn bg_priority_against_objects() {
+    let mut bus = cgb();
+    palette(&mut bus, BCPS, 0, [WHITE, RED]);
+    palette(&mut bus, OCPS, 0, [WHITE, BLUE]);
+    tile_row(&mut bus, 0, 1, 0, 0xFF);
+    tile_row(&mut bus, 0, 2, 0, 0xFF);
+
+    // BG colour 1 with its priority bit, BG colour 1 without, BG colour 0 with it
+    map(&mut bus, 0, 1, 0x80);
+    map(&mut bus, 1, 1, 0x00);
+    map(&mut bus, 2, 0, 0x80);
+    // an object over each
+    sprite(&mut bus, 0, 8, 2, 0x00);
+    sprite(&mut bus, 1, 16, 2, 0x00);
+    sprite(&mut bus, 2, 24, 2, 0x00);
+    let pixels = line(&mut bus, 0x93);
+    assert_eq!([pixels[0], pixels[8], pixels[16]], [rgb555(RED), rgb555(BLUE), rgb555(BLUE)]);
+
+    // one asking to go behind loses to any colour but 0
+    sprite(&mut bus, 1, 16, 2, 0x80);
+    assert_eq!(line(&mut bus, 0x93)[8], rgb555(RED));
+
+    // LCDC bit 0 clear puts every object on top, whatever the attributes say
+    let pixels = line(&mut bus, 0x92);
+    assert_eq!([pixels[0], pixels[8], pixels[16]], [rgb555(BLUE); 3]);
+}
+
+#[test]
+fn cgb_attributes_flip_bank_and_colour_tiles() {
+    let mut bus = cgb();
+    palette(&mut bus, BCPS, 0, [WHITE, RED]);
+    palette(&mut bus, BCPS, 3, [WHITE, GREEN]);
+    palette(&mut bus, OCPS, 0, [WHITE, BLUE]);
+    // tile 1 has its left pixel on the top row and its right one on the bottom row. in bank 1
+    // the same tile has only the middle two on top
+    tile_row(&mut bus, 0, 1, 0, 0x80);
+    tile_row(&mut bus, 0, 1, 7, 0x01);
+    tile_row(&mut bus, 1, 1, 0, 0x18);
+
+    let attributes = [0x00, 0x20, 0x40, 0x60, 0x08, 0x03];
+    for (column, attributes) in attributes.into_iter().enumerate() {
+        map(&mut bus, column as u16, 1, attributes);
+    }
+    let pixels = line(&mut bus, 0x91);
+    let lit = |column: usize| -> Vec<usize> {
+        (0..8).filter(|x| pixels[column * 8 + x] != rgb555(WHITE)).collect()
+    };
+    assert_eq!(lit(0), [0]);
+    assert_eq!(lit(1), [7]); // flipped across
+    assert_eq!(lit(2), [7]); // flipped upside down, so the bottom row
+    assert_eq!(lit(3), [0]); // both
+    assert_eq!(lit(4), [3, 4]); // from bank 1
+    assert_eq!(pixels[5 * 8], rgb555(GREEN)); // in palette 3
+
+    // objects flip across and take tiles from bank 1 the same way
+    sprite(&mut bus, 0, 8, 1, 0x20);
+    sprite(&mut bus, 1, 16, 1, 0x08);
+    let pixels = line(&mut bus, 0x93);
+    assert_eq!(pixels[7], rgb555(BLUE));
+    assert_eq!(&pixels[11..13], [rgb555(BLUE); 2]);
+    // and their colour 0 leaves the background showing
+    assert_eq!(pixels[0], rgb555(RED));
+    assert_eq!(pixels[15], rgb555(RED));
+}
@@ -0,0 +1,116 @@
+use gb_emulator::printer::Printer;
+use gb_emulator::serial::SerialDevice;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// sends a packet the way a game does and returns the printer's answers to the last two bytes.
+// `corrupt` is added to the checksum to send a bad one
+fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8], corrupt: u16) -> (u8, u8) {
+    let mut packet = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+    packet.extend_from_slice(data);
+    let checksum = packet.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16)).wrapping_add(corrupt);
+
+    for byte in [0x88, 0x33].into_iter().chain(packet).chain(checksum.to_le_bytes()) {
+        assert_eq!(printer.exchange(byte), 0x00);
+    }
+    (printer.exchange(0x00), printer.exchange(0x00))
+}
+
+fn output_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("gb-printer-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn a_bad_checksum_is_reported_in_the_status() {
+    let mut printer = Printer::new(output_dir("checksum"));
+    assert_eq!(send(&mut printer, INIT, false, &[], 0), (0x81, 0x00));
+    assert_eq!(send(&mut printer, INIT, false, &[], 1), (0x81, 0x01));
+    // the next good packet clears it again
+    assert_eq!(send(&mut printer, STATUS, false, &[], 0), (0x81, 0x00));
+}
+
+#[test]
+fn status_follows_data_and_printing() {
+    let mut printer = Printer::new(output_dir("status"));
+    send(&mut printer, INIT, false, &[], 0);
+
+    // data waiting to be printed
+    assert_eq!(send(&mut printer, DATA, false, &[0; 640], 0), (0x81, 0x08));
+    // printing: one sheet, no feed, palette E4, exposure 40
+    assert_eq!(send(&mut printer, PRINT, false, &[1, 0x00, 0xE4, 0x40], 0), (0x81, 0x02));
+    assert_eq!(send(&mut printer, STATUS, false, &[], 0), (0x81, 0x02));
+
+    // the head is done well within a second
+    printer.tick(4194304);
+    assert_eq!(send(&mut printer, STATUS, false, &[], 0), (0x81, 0x00));
+}
+
+#[test]
+fn compressed_data_prints_the_same_as_raw() {
+    // one row of tiles: the first all colour 0, the other 19 all colour 3
+    let mut raw = vec![0x00; 16];
+    raw.extend(vec![0xFF; 19 * 16]);
+    // 16 bytes copied as they are, then 0xFF repeated 129 + 129 + 46 times
+    let mut compressed = vec![0x0F];
+    compressed.extend([0x00; 16]);
+    compressed.extend([0xFF, 0xFF, 0xFF, 0xFF, 0x80 | 44, 0xFF]);
+
+    let page = |test: &str, data: &[u8], compress: bool| {
+        let dir = output_dir(test);
+        let mut printer = Printer::new(&dir);
+        send(&mut printer, INIT, false, &[], 0);
+        assert_eq!(send(&mut printer, DATA, compress, data, 0), (0x81, 0x08));
+        // a feed after printing ends the page
+        send(&mut printer, PRINT, false, &[1, 0x03, 0xE4, 0x40], 0);
+        assert_eq!(printer.pages_printed, 1);
+        let page = std::fs::read(dir.join("page-001.pgm")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        page
+    };
+
+    let raw_page = page("raw", &raw, false);
+    assert_eq!(page("rle", &compressed, true), raw_page);
+
+    let header = b"P5\n160 8\n255\n";
+    assert!(raw_page.starts_with(header));
+    let pixels = &raw_page[header.len()..];
+    assert_eq!(pixels.len(), 160 * 8);
+    for row in pixels.chunks(160) {
+        assert!(row[..8].iter().all(|&p| p == 0xFF));
+        assert!(row[8..].iter().all(|&p| p == 0x00));
+    }
+}
+
+#[test]
+fn a_page_without_a_feed_is_written_when_the_printer_goes() {
+    let dir = output_dir("unfed");
+    let mut printer = Printer::new(&dir);
+    send(&mut printer, INIT, false, &[], 0);
+    send(&mut printer, DATA, false, &[0; 640], 0);
+    send(&mut printer, PRINT, false, &[1, 0x00, 0xE4, 0x40], 0);
+    assert_eq!(printer.take_message(), None);
+    assert!(!dir.join("page-001.pgm").exists());
+
+    drop(printer);
+    let page = std::fs::read(dir.join("page-001.pgm")).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(page.starts_with(b"P5\n160 16\n255\n"));
+}
+
+#[test]
+fn printed_pages_are_reported_once() {
+    let dir = output_dir("message");
+    let mut printer = Printer::new(&dir);
+    send(&mut printer, INIT, false, &[], 0);
+    send(&mut printer, DATA, false, &[0; 640], 0);
+    send(&mut printer, PRINT, false, &[1, 0x03, 0xE4, 0x40], 0);
+    let expected = format!("printed {}", dir.join("page-001.pgm").display());
+    assert_eq!(printer.take_message(), Some(expected));
+    assert_eq!(printer.take_message(), None);
+    let _ = std::fs::remove_dir_all(&dir);
+}
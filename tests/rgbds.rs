@@ -0,0 +1,60 @@
+use gb_emulator::rgbds::disassemble_rom;
+
+const PROGRAM: [u8; 21] = [
+    0xF0, 0x44,             // 0150: ldh a, [rLY]
+    0xFA, 0x44, 0xFF,       //       ld a, [$FF44] in its long form
+    0xEA, 0x40, 0xFF,       //       ld [$FF40], a in its long form
+    0xEA, 0x00, 0xC0,       //       ld [$C000], a
+    0x10, 0x00,             //       stop
+    0x10, 0x01,             //       stop with a nonzero second byte
+    0xCD, 0x64, 0x01,       //       call $0164
+    0x18, 0xEC,             //       jr $0150
+    0xC9,                   // 0164: ret
+];
+
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    for vector in (0x00..=0x60).step_by(8) {
+        rom[vector] = 0xD9; // reti
+    }
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    rom[0x150..0x150 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    rom
+}
+
+const MAIN: &str = "\
+; disassembled from game: 2 banks, 38 of 32768 bytes traced as code
+; build with: rgbasm -o game.o game.asm && rgblink -o game.gb game.o
+
+INCLUDE \"bank_000.asm\"
+INCLUDE \"bank_001.asm\"
+";
+
+// rgbasm would assemble the long forms of ld to and from $FFxx as ldh, so they stay as bytes
+const CODE: &str = "
+Jump_000_0150:
+    ldh a, [$FF44]
+    db $FA, $44, $FF
+    db $EA, $40, $FF
+    ld [$C000], a
+    stop
+    db $10, $01
+    call Call_000_0164
+    jr Jump_000_0150
+
+Call_000_0164:
+    ret
+";
+
+#[test]
+fn disassembly_matches_the_golden_output() {
+    let project = disassemble_rom(&rom(), "game");
+    let names: Vec<&str> = project.files.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["game.asm", "bank_000.asm", "bank_001.asm"]);
+    assert_eq!(project.files[0].1, MAIN);
+
+    let bank_0 = &project.files[1].1;
+    assert!(bank_0.starts_with("SECTION \"ROM Bank $000\", ROM0[$0000]\n\nRST_00:\n    reti\n"));
+    assert!(bank_0.contains(CODE), "bank 0 came out as\n{bank_0}");
+    assert!(project.files[2].1.starts_with("SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]\n"));
+}
@@ -0,0 +1,176 @@
+use gb_emulator::rewind::Rewind;
+use gb_emulator::{EmulationError, GameBoy};
+
+// Each VBlank this rewrites tile 0 (which the whole background shows) from a counter in WRAM
+// and bumps SCX, so every frame differs from the last
+const PROGRAM: [u8; 41] = [
+    0x21, 0x00, 0x80,       // 0150: ld hl, $8000
+    0xF0, 0x44,             // 0153: ldh a, [rLY]
+    0xFE, 0x90,             //       cp 144
+    0x20, 0xFA,             //       jr nz, $0153
+    0x21, 0x00, 0x80,       //       ld hl, $8000
+    0x06, 0x10,             //       ld b, 16
+    0xFA, 0x00, 0xC0,       //       ld a, [$C000]
+    0x3C,                   //       inc a
+    0xEA, 0x00, 0xC0,       //       ld [$C000], a
+    0x22,                   // 0165: ld [hl+], a
+    0xC6, 0x11,             //       add a, $11
+    0x05,                   //       dec b
+    0x20, 0xFA,             //       jr nz, $0165
+    0xF0, 0x43,             //       ldh a, [rSCX]
+    0x3C,                   //       inc a
+    0xE0, 0x43,             //       ldh [rSCX], a
+    0xF0, 0x44,             // 0170: ldh a, [rLY]
+    0xFE, 0x90,             //       cp 144
+    0x28, 0xFA,             //       jr z, $0170
+    0xC3, 0x53, 0x01,       //       jp $0153
+];
+
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    rom[0x150..0x150 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    rom
+}
+
+fn frames(gb: &mut GameBoy, count: usize) -> Vec<Vec<u32>> {
+    (0..count).map(|_| {
+        gb.run_frame().unwrap();
+        gb.framebuffer().to_vec()
+    }).collect()
+}
+
+#[test]
+fn loading_a_state_continues_with_identical_frames() {
+    let mut original = GameBoy::new();
+    original.load(rom()).unwrap();
+    frames(&mut original, 30);
+    let state = original.save_state();
+    let expected = frames(&mut original, 60);
+    assert_ne!(expected[0], expected[59], "the test ROM should animate");
+
+    let mut restored = GameBoy::new();
+    restored.load(rom()).unwrap();
+    frames(&mut restored, 5);
+    restored.load_state(&state).unwrap();
+    assert_eq!(frames(&mut restored, 60), expected);
+    assert_eq!(restored.save_state(), original.save_state());
+}
+
+// the address space and cartridge RAM, where retro_get_memory_data points frontends
+fn memory_pointers(gb: &GameBoy) -> (*const u8, *const u8) {
+    let cartridge = gb.cpu.bus.cartridge.as_ref().unwrap();
+    (gb.cpu.bus.memory.as_ptr(), cartridge.ram.as_ptr())
+}
+
+#[test]
+fn loading_a_state_keeps_memory_where_it_was() {
+    let mut rom = rom();
+    rom[0x147] = 0x03; // MBC1 with battery backed RAM
+    rom[0x149] = 0x02;
+    let mut gb = GameBoy::new();
+    gb.load(rom).unwrap();
+    frames(&mut gb, 10);
+    let state = gb.save_state();
+    frames(&mut gb, 10);
+
+    let before = memory_pointers(&gb);
+    gb.load_state(&state).unwrap();
+    assert_eq!(memory_pointers(&gb), before);
+    assert_eq!(gb.save_state(), state);
+
+    // BESS states as well
+    let state = gb.export_bess();
+    frames(&mut gb, 10);
+    gb.load_state(&state).unwrap();
+    assert_eq!(memory_pointers(&gb), before);
+    assert_eq!(gb.export_bess(), state);
+}
+
+#[test]
+fn states_from_another_rom_are_refused() {
+    let mut gb = GameBoy::new();
+    gb.load(rom()).unwrap();
+    let state = gb.save_state();
+
+    let mut other = rom();
+    other[0x134] = b'X';
+    gb.load(other).unwrap();
+    assert!(matches!(gb.load_state(&state), Err(EmulationError::InvalidState(_))));
+    assert!(matches!(gb.load_state(&state[..20]), Err(EmulationError::InvalidState(_))));
+}
+
+#[test]
+fn bess_states_round_trip() {
+    let mut original = GameBoy::new();
+    original.load(rom()).unwrap();
+    frames(&mut original, 30);
+    let state = original.export_bess();
+    assert!(state.ends_with(b"BESS"));
+
+    let mut restored = GameBoy::new();
+    restored.load(rom()).unwrap();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.cpu.pc, original.cpu.pc);
+    assert_eq!(restored.cpu.bus.memory[0x8000..0xA000], original.cpu.bus.memory[0x8000..0xA000]);
+    assert_eq!(restored.export_bess(), state);
+}
+
+#[test]
+fn unknown_bess_blocks_are_skipped() {
+    let mut gb = GameBoy::new();
+    gb.load(rom()).unwrap();
+    frames(&mut gb, 10);
+    let state = gb.export_bess();
+
+    // put a block from some other emulator in front of END
+    let (chain, footer) = state.split_at(state.len() - 16);
+    let mut extended = chain.to_vec();
+    extended.extend_from_slice(b"XTRA\x03\x00\x00\x00abc");
+    extended.extend_from_slice(footer);
+
+    let mut restored = GameBoy::new();
+    restored.load(rom()).unwrap();
+    restored.load_state(&extended).unwrap();
+    assert_eq!(restored.export_bess(), state);
+
+    extended.truncate(extended.len() - 8);
+    assert!(matches!(restored.load_state(&extended), Err(EmulationError::InvalidState(_))));
+}
+
+#[test]
+fn rewinding_steps_back_through_the_same_frames() {
+    let mut gb = GameBoy::new();
+    gb.load(rom()).unwrap();
+    let mut rewind = Rewind::new(4, 100, usize::MAX);
+    let mut states = Vec::new();
+    for frame in 0..50u8 {
+        gb.set_buttons(frame & 0x0F);
+        gb.run_frame().unwrap();
+        rewind.record(&gb);
+        states.push(gb.save_state());
+    }
+    assert_eq!(rewind.frames(), 49);
+
+    for expected in states.iter().rev().skip(1) {
+        assert!(rewind.step_back(&mut gb).unwrap());
+        assert!(gb.save_state() == *expected);
+    }
+    assert!(!rewind.step_back(&mut gb).unwrap());
+}
+
+#[test]
+fn rewind_keeps_to_its_depth_and_memory_cap() {
+    let mut gb = GameBoy::new();
+    gb.load(rom()).unwrap();
+    let mut deep = Rewind::new(5, 20, usize::MAX);
+    let mut capped = Rewind::new(5, 1000, 300_000);
+    for _ in 0..100 {
+        gb.run_frame().unwrap();
+        deep.record(&gb);
+        capped.record(&gb);
+    }
+    assert!((20..25).contains(&deep.frames()), "{} frames kept", deep.frames());
+    assert!(capped.memory() <= 300_000);
+    assert!(capped.frames() > 5, "deltas should fit several snapshots under the cap");
+}
@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gb_emulator::memory::MemoryBus;
+use gb_emulator::ppu::IF;
+use gb_emulator::serial::{SB, SC, SerialDevice};
+
+// answers every byte with 0x42 and keeps what it was sent
+struct Echo(Rc<RefCell<Vec<u8>>>);
+
+impl SerialDevice for Echo {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.0.borrow_mut().push(byte);
+        0x42
+    }
+}
+
+fn bus(cgb: bool) -> (MemoryBus, Rc<RefCell<Vec<u8>>>) {
+    let sent = Rc::new(RefCell::new(Vec::new()));
+    let mut bus = MemoryBus::new();
+    bus.serial.cgb = cgb;
+    bus.serial.device = Box::new(Echo(sent.clone()));
+    (bus, sent)
+}
+
+fn done(bus: &MemoryBus) -> bool {
+    bus.read_byte(SC) & 0x80 == 0
+}
+
+// starts a transfer of 0x99 with `sc` and returns how many cycles it took to finish
+fn transfer(bus: &mut MemoryBus, sc: u8) -> u32 {
+    bus.memory[IF] &= !0x08;
+    bus.write_byte(SB, 0x99);
+    bus.write_byte(SC, sc);
+    let mut cycles = 0;
+    while !done(bus) {
+        bus.tick(4);
+        cycles += 4;
+        // the interrupt comes with the last bit and not before
+        assert_eq!(bus.memory[IF] & 0x08 != 0, done(bus));
+    }
+    cycles
+}
+
+#[test]
+fn the_internal_clock_shifts_a_bit_every_512_cycles() {
+    let (mut bus, sent) = bus(false);
+    assert_eq!(transfer(&mut bus, 0x81), 8 * 512);
+    assert_eq!(bus.read_byte(SB), 0x42);
+    assert_eq!(*sent.borrow(), [0x99]);
+
+    // the DMG has no fast clock, so bit 1 changes nothing
+    assert_eq!(transfer(&mut bus, 0x83), 8 * 512);
+    assert_eq!(bus.read_byte(SC), 0x7F);
+}
+
+#[test]
+fn the_cgb_fast_clock_shifts_a_bit_every_16_cycles() {
+    let (mut bus, sent) = bus(true);
+    assert_eq!(transfer(&mut bus, 0x83), 8 * 16);
+    assert_eq!(bus.read_byte(SC), 0x7F);
+    assert_eq!(transfer(&mut bus, 0x81), 8 * 512);
+    assert_eq!(*sent.borrow(), [0x99, 0x99]);
+}
+
+#[test]
+fn an_external_clock_with_nothing_connected_never_finishes() {
+    let mut bus = MemoryBus::new();
+    bus.write_byte(SB, 0x99);
+    bus.write_byte(SC, 0x80);
+    for _ in 0..1000 {
+        bus.tick(512);
+    }
+    assert_eq!(bus.read_byte(SC), 0xFE);
+    assert_eq!(bus.read_byte(SB), 0x99);
+    assert_eq!(bus.memory[IF] & 0x08, 0);
+
+    // switching to the internal clock gets it going again, and the open line reads 0xFF
+    bus.write_byte(SC, 0x81);
+    bus.tick(8 * 512);
+    assert_eq!(bus.read_byte(SB), 0xFF);
+    assert_ne!(bus.memory[IF] & 0x08, 0);
+}
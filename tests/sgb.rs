@@ -0,0 +1,129 @@
+use gb_emulator::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, rgb555};
+use gb_emulator::sgb::{Mask, SGB_WIDTH, Sgb};
+
+const PAL01: u8 = 0x00;
+const ATTR_BLK: u8 = 0x04;
+const MASK_EN: u8 = 0x17;
+
+const RED: u16 = 0x001F;
+const GREEN: u16 = 0x03E0;
+const BLUE: u16 = 0x7C00;
+
+// pulses a 16 byte packet out over P14/P15: a reset, 128 bits LSB first, then the stop bit
+fn send(sgb: &mut Sgb, packet: [u8; 16]) {
+    sgb.write_p1(0x00);
+    sgb.write_p1(0x30);
+    for bit in (0..128).map(|i| packet[i / 8] >> (i % 8) & 1).chain([0]) {
+        sgb.write_p1(if bit == 1 { 0x10 } else { 0x20 });
+        sgb.write_p1(0x30);
+    }
+}
+
+// a one packet command with its payload after the command byte
+fn packet(command: u8, payload: &[u8]) -> [u8; 16] {
+    let mut packet = [0; 16];
+    packet[0] = command << 3 | 1;
+    packet[1..1 + payload.len()].copy_from_slice(payload);
+    packet
+}
+
+// PAL01 with colour 1 of palette 0 red and colour 1 of palette 1 green
+fn pal01(sgb: &mut Sgb) {
+    let mut payload = Vec::new();
+    for color in [BLUE, RED, 0, 0, GREEN, 0, 0] {
+        payload.extend(color.to_le_bytes());
+    }
+    send(sgb, packet(PAL01, &payload));
+}
+
+// draws a frame of shade 1 everywhere and returns the colour of the screen's 8x8 cell at x, y
+fn cell(sgb: &mut Sgb, x: usize, y: usize) -> u32 {
+    sgb.vblank(&[1; SCREEN_WIDTH * SCREEN_HEIGHT]);
+    sgb.framebuffer[(40 + y * 8) * SGB_WIDTH + 48 + x * 8]
+}
+
+#[test]
+fn pal01_sets_both_palettes_and_the_shared_colour_0() {
+    let mut sgb = Sgb::new();
+    pal01(&mut sgb);
+    assert_eq!(cell(&mut sgb, 0, 0), rgb555(RED));
+
+    // colour 0 shows as the backdrop, around the screen as well as in it
+    sgb.vblank(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+    assert_eq!(sgb.framebuffer[0], rgb555(BLUE));
+    assert_eq!(sgb.framebuffer[40 * SGB_WIDTH + 48], rgb555(BLUE));
+}
+
+#[test]
+fn a_packet_only_counts_once_its_stop_bit_arrives() {
+    let mut sgb = Sgb::new();
+    let packet = packet(MASK_EN, &[2]);
+    sgb.write_p1(0x00);
+    sgb.write_p1(0x30);
+    for bit in (0..128).map(|i| packet[i / 8] >> (i % 8) & 1) {
+        sgb.write_p1(if bit == 1 { 0x10 } else { 0x20 });
+        sgb.write_p1(0x30);
+    }
+    assert_eq!(sgb.mask, Mask::None);
+    sgb.write_p1(0x20);
+    sgb.write_p1(0x30);
+    assert_eq!(sgb.mask, Mask::Black);
+}
+
+#[test]
+fn multi_packet_commands_wait_for_every_packet() {
+    let mut sgb = Sgb::new();
+    pal01(&mut sgb);
+
+    // ATTR_BLK over two packets: one set, inside (and so the edge) of cells 2,3-4,5 to palette 1
+    let mut first = packet(ATTR_BLK, &[1, 0x01, 0x01, 2, 3, 4, 5]);
+    first[0] = ATTR_BLK << 3 | 2;
+    send(&mut sgb, first);
+    assert_eq!(cell(&mut sgb, 3, 4), rgb555(RED));
+
+    send(&mut sgb, [0; 16]);
+    assert_eq!(cell(&mut sgb, 3, 4), rgb555(GREEN));
+}
+
+#[test]
+fn attr_blk_colours_inside_edge_and_outside() {
+    let mut sgb = Sgb::new();
+    pal01(&mut sgb);
+
+    // only the inside: the edge follows it and the outside is left alone
+    send(&mut sgb, packet(ATTR_BLK, &[1, 0x01, 0x01, 2, 3, 4, 5]));
+    assert_eq!(cell(&mut sgb, 3, 4), rgb555(GREEN));
+    assert_eq!(cell(&mut sgb, 2, 3), rgb555(GREEN));
+    assert_eq!(cell(&mut sgb, 0, 0), rgb555(RED));
+
+    // only the outside, to palette 1, with the block keeping what it had
+    send(&mut sgb, packet(ATTR_BLK, &[1, 0x04, 0x10, 10, 10, 12, 12]));
+    assert_eq!(cell(&mut sgb, 0, 0), rgb555(GREEN));
+    assert_eq!(cell(&mut sgb, 11, 11), rgb555(RED));
+    // the edge goes with the outside here
+    assert_eq!(cell(&mut sgb, 10, 10), rgb555(GREEN));
+}
+
+#[test]
+fn mask_en_blanks_freezes_or_fills_the_screen() {
+    let mut sgb = Sgb::new();
+    pal01(&mut sgb);
+
+    send(&mut sgb, packet(MASK_EN, &[2]));
+    assert_eq!(cell(&mut sgb, 5, 5), 0);
+
+    send(&mut sgb, packet(MASK_EN, &[3]));
+    assert_eq!(cell(&mut sgb, 5, 5), rgb555(BLUE));
+
+    // a frozen screen isn't redrawn, whatever the game shows
+    send(&mut sgb, packet(MASK_EN, &[0]));
+    assert_eq!(cell(&mut sgb, 5, 5), rgb555(RED));
+    send(&mut sgb, packet(MASK_EN, &[1]));
+    sgb.frame_ready = false;
+    sgb.vblank(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]);
+    assert!(!sgb.frame_ready);
+    assert_eq!(sgb.framebuffer[(40 + 40) * SGB_WIDTH + 48 + 40], rgb555(RED));
+
+    send(&mut sgb, packet(MASK_EN, &[0]));
+    assert_eq!(sgb.mask, Mask::None);
+}
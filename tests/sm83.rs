@@ -0,0 +1,157 @@
+use gb_emulator::cpu::{CPU, CpuTest};
+
+// cases in the format of the SM83 JSON tests, aimed at registers the real bus treats specially
+// and at opcodes outside the ALU and load blocks
+const LD_DIV: &str = r#"[
+    {
+        "name": "77 ld [hl], a onto DIV",
+        "initial": { "pc": 256, "sp": 65534, "a": 171, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 255, "l": 4,
+                     "ime": 0, "ram": [[256, 119], [65284, 18]] },
+        "final":   { "pc": 257, "sp": 65534, "a": 171, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 255, "l": 4,
+                     "ime": 0, "ram": [[256, 119], [65284, 171]] }
+    }
+]"#;
+
+const INC_LY: &str = r#"[
+    {
+        "name": "34 inc [hl] on LY",
+        "initial": { "pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 255, "l": 68,
+                     "ime": 0, "ram": [[256, 52], [65348, 143]] },
+        "final":   { "pc": 257, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 32, "h": 255, "l": 68,
+                     "ime": 0, "ram": [[256, 52], [65348, 144]] }
+    }
+]"#;
+
+const DAA: &str = r#"[
+    {
+        "name": "27 daa after an addition",
+        "initial": { "pc": 256, "sp": 65534, "a": 60, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 39]] },
+        "final":   { "pc": 257, "sp": 65534, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 39]] }
+    },
+    {
+        "name": "27 daa after an addition that carries",
+        "initial": { "pc": 256, "sp": 65534, "a": 154, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 39]] },
+        "final":   { "pc": 257, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 144, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 39]] }
+    },
+    {
+        "name": "27 daa after a subtraction",
+        "initial": { "pc": 256, "sp": 65534, "a": 63, "b": 0, "c": 0, "d": 0, "e": 0, "f": 96, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 39]] },
+        "final":   { "pc": 257, "sp": 65534, "a": 57, "b": 0, "c": 0, "d": 0, "e": 0, "f": 64, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 39]] }
+    }
+]"#;
+
+const RST_38: &str = r#"[
+    {
+        "name": "ff rst $38",
+        "initial": { "pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 255]] },
+        "final":   { "pc": 56, "sp": 65532, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 255], [65533, 1], [65532, 1]] }
+    }
+]"#;
+
+const LD_A16_SP: &str = r#"[
+    {
+        "name": "08 ld [$C000], sp",
+        "initial": { "pc": 256, "sp": 43981, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 8], [257, 0], [258, 192], [49152, 0], [49153, 0]] },
+        "final":   { "pc": 259, "sp": 43981, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 8], [257, 0], [258, 192], [49152, 205], [49153, 171]] }
+    }
+]"#;
+
+const ADD_SP: &str = r#"[
+    {
+        "name": "e8 add sp, $08 carrying out of the low byte",
+        "initial": { "pc": 256, "sp": 65528, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 128, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 232], [257, 8]] },
+        "final":   { "pc": 258, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 48, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 232], [257, 8]] }
+    },
+    {
+        "name": "e8 add sp, -$02",
+        "initial": { "pc": 256, "sp": 5, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 232], [257, 254]] },
+        "final":   { "pc": 258, "sp": 3, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 48, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 232], [257, 254]] }
+    }
+]"#;
+
+const LD_HL_SP: &str = r#"[
+    {
+        "name": "f8 ld hl, sp + $01",
+        "initial": { "pc": 256, "sp": 49152, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 240, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 248], [257, 1]] },
+        "final":   { "pc": 258, "sp": 49152, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 192, "l": 1,
+                     "ime": 0, "ram": [[256, 248], [257, 1]] }
+    }
+]"#;
+
+const LD_SP_HL: &str = r#"[
+    {
+        "name": "f9 ld sp, hl",
+        "initial": { "pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 18, "l": 52,
+                     "ime": 0, "ram": [[256, 249]] },
+        "final":   { "pc": 257, "sp": 4660, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 18, "l": 52,
+                     "ime": 0, "ram": [[256, 249]] }
+    }
+]"#;
+
+const DEC_BC: &str = r#"[
+    {
+        "name": "0b dec bc",
+        "initial": { "pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 11]] },
+        "final":   { "pc": 257, "sp": 65534, "a": 0, "b": 255, "c": 255, "d": 0, "e": 0, "f": 176, "h": 0, "l": 0,
+                     "ime": 0, "ram": [[256, 11]] }
+    }
+]"#;
+
+fn run(json: &str, opcode: u8) {
+    let tests: Vec<CpuTest> = serde_json::from_str(json).unwrap();
+    let mut cpu = CPU::new();
+    for test in &tests {
+        if let Err(e) = cpu.run_sm83_test(opcode, false, test) {
+            panic!("{e}");
+        }
+    }
+}
+
+#[test]
+fn stores_to_registers_land_as_plain_ram() {
+    run(LD_DIV, 0x77);
+}
+
+#[test]
+fn read_modify_write_on_a_read_only_register_lands_as_plain_ram() {
+    run(INC_LY, 0x34);
+}
+
+#[test]
+fn daa_adjusts_after_additions_and_subtractions() {
+    run(DAA, 0x27);
+}
+
+#[test]
+fn rst_pushes_the_next_address_and_jumps_to_the_vector() {
+    run(RST_38, 0xFF);
+}
+
+#[test]
+fn sp_loads_stores_and_offsets() {
+    run(LD_A16_SP, 0x08);
+    run(ADD_SP, 0xE8);
+    run(LD_HL_SP, 0xF8);
+    run(LD_SP_HL, 0xF9);
+}
+
+#[test]
+fn dec_of_a_register_pair_leaves_the_flags_alone() {
+    run(DEC_BC, 0x0B);
+}
@@ -0,0 +1,55 @@
+use gb_emulator::symbols::{Symbol, Symbols};
+
+// what rgblink writes, with a tab-separated line as no$gmb has them and some that aren't symbols
+const SYM: &str = "\
+; File generated by rgblink
+00:0150 Main
+00:0158 Main.loop ; the rest of the line is a comment
+01:4000 LoadTiles
+02:4000 PlaySound
+02:4010\tPlaySound.next
+00:C000 wBuffer
+
+not a symbol
+zz:0150 Bad
+";
+
+#[test]
+fn parse_reads_rgblink_and_nocash_lines_and_skips_the_rest() {
+    let symbols = Symbols::parse(SYM);
+    let names: Vec<_> = symbols.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["Main", "Main.loop", "LoadTiles", "PlaySound", "PlaySound.next", "wBuffer"]);
+    assert_eq!(symbols.get("PlaySound.next"), Some(&Symbol { bank: 2, addr: 0x4010, name: "PlaySound.next".into() }));
+    assert!(Symbols::parse("; nothing but a comment\n\n").is_empty());
+}
+
+#[test]
+fn resolve_adds_a_decimal_offset() {
+    let symbols = Symbols::parse(SYM);
+    assert_eq!(symbols.resolve("Main"), Some((0, 0x0150)));
+    assert_eq!(symbols.resolve("Main+3"), Some((0, 0x0153)));
+    assert_eq!(symbols.resolve("Main.loop+10"), Some((0, 0x0162)));
+    assert_eq!(symbols.resolve("PlaySound+16"), Some((2, 0x4010)));
+    assert_eq!(symbols.resolve("Main+x"), None);
+    assert_eq!(symbols.resolve("Nowhere+3"), None);
+}
+
+#[test]
+fn describe_picks_the_label_in_the_mapped_bank() {
+    let symbols = Symbols::parse(SYM);
+    assert_eq!(symbols.describe(Some(1), 0x4005).as_deref(), Some("LoadTiles+5"));
+    assert_eq!(symbols.describe(Some(2), 0x4005).as_deref(), Some("PlaySound+5"));
+    assert_eq!(symbols.describe(Some(2), 0x4012).as_deref(), Some("PlaySound.next+2"));
+    // bank 1 has nothing at $4010, so it's still in LoadTiles
+    assert_eq!(symbols.describe(Some(1), 0x4012).as_deref(), Some("LoadTiles+18"));
+    // nothing is named in a bank without labels, or when the bank isn't known
+    assert_eq!(symbols.describe(Some(3), 0x4005), None);
+    assert_eq!(symbols.describe(None, 0x4005), None);
+
+    // bank 0 doesn't switch, and a label never reaches past its own area
+    assert_eq!(symbols.describe(None, 0x015A).as_deref(), Some("Main.loop+2"));
+    assert_eq!(symbols.describe(Some(1), 0x3FFF).as_deref(), Some("Main.loop+16039"));
+    assert_eq!(symbols.describe(None, 0xC001).as_deref(), Some("wBuffer+1"));
+    assert_eq!(symbols.describe(None, 0xBFFF), None);
+    assert_eq!(symbols.name(Some(2), 0x4000), Some("PlaySound"));
+}